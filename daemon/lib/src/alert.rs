@@ -0,0 +1,265 @@
+use serde::Deserialize;
+use shared::message::{Alerts, MAX_ALERTS};
+use shared::metric::{self, Metric, MetricId, Unit};
+use std::time::{Duration, Instant};
+
+/// Share of the threshold a value must recover by to clear an alert, unless configured.
+const DEFAULT_HYSTERESIS: f32 = 0.05;
+
+/// Alert rule, as described in the config file.  Thresholds are in percent for percentage
+/// metrics, otherwise in the metric's own unit.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AlertConfig {
+    /// Metric name, as used by layouts.
+    pub metric: String,
+    /// Alert when the value rises above this level.
+    pub above: Option<f32>,
+    /// Alert when the value falls below this level.
+    pub below: Option<f32>,
+    /// Level the value must recover past to clear the alert.
+    pub clear: Option<f32>,
+    /// Seconds the threshold must be crossed before alerting.
+    #[serde(rename = "for", default)]
+    pub hold_secs: u64,
+}
+
+impl AlertConfig {
+    /// Checks the rule is complete, returning a description of the problem if not.
+    pub fn validate(&self) -> Result<(), String> {
+        if metric::by_name(&self.metric).is_none() {
+            return Err(format!("unknown metric {:?}", self.metric));
+        }
+        match (self.above, self.below, self.clear) {
+            (Some(above), None, Some(clear)) if clear >= above => Err(format!(
+                "alert on {} must clear below {}",
+                self.metric, above
+            )),
+            (None, Some(below), Some(clear)) if clear <= below => Err(format!(
+                "alert on {} must clear above {}",
+                self.metric, below
+            )),
+            (Some(_), None, _) | (None, Some(_), _) => Ok(()),
+            _ => Err(format!(
+                "alert on {} requires one of above or below",
+                self.metric
+            )),
+        }
+    }
+}
+
+/// Tracks alert rules against collected metrics.
+pub struct AlertMonitor {
+    rules: Vec<Rule>,
+}
+
+// Validated alert rule, with thresholds scaled to metric values.
+struct Rule {
+    id: MetricId,
+    name: String,
+    // Alerting level, and whether values above it alert.
+    level: f32,
+    above: bool,
+    clear: f32,
+    hold: Duration,
+    // When the level was first crossed, while pending.
+    crossed: Option<Instant>,
+    active: bool,
+}
+
+impl AlertMonitor {
+    /// Creates a monitor for validated alert configs.
+    pub fn new(configs: &[AlertConfig]) -> Self {
+        let rules = configs
+            .iter()
+            .filter_map(|config| {
+                let id = metric::by_name(&config.metric)?;
+                let scale = match metric::info(id) {
+                    Some(info) if info.unit == Unit::Ratio => 0.01,
+                    _ => 1.0,
+                };
+                let (level, above) = match (config.above, config.below) {
+                    (Some(level), _) => (level, true),
+                    (None, Some(level)) => (level, false),
+                    (None, None) => return None,
+                };
+                let hysteresis = level.abs() * DEFAULT_HYSTERESIS;
+                let clear = config.clear.unwrap_or(if above {
+                    level - hysteresis
+                } else {
+                    level + hysteresis
+                });
+
+                Some(Rule {
+                    id,
+                    name: config.metric.clone(),
+                    level: level * scale,
+                    above,
+                    clear: clear * scale,
+                    hold: Duration::from_secs(config.hold_secs),
+                    crossed: None,
+                    active: false,
+                })
+            })
+            .collect();
+
+        AlertMonitor { rules }
+    }
+
+    /// Updates rules with metrics collected at `now`, returning the metrics alerting.
+    pub fn update(&mut self, metrics: &[Metric], now: Instant) -> Alerts {
+        for rule in &mut self.rules {
+            // Failed sources neither trigger nor clear alerts.
+            match metrics.iter().find(|m| m.id == rule.id) {
+                Some(m) if !m.value.is_nan() => rule.update(m.value, now),
+                _ => {}
+            }
+        }
+
+        let mut alerts = Alerts::default();
+        for rule in self.rules.iter().filter(|r| r.active) {
+            if !alerts.contains(rule.id) && alerts.metrics.push(rule.id).is_err() {
+                log::warn!("More than {} metrics alerting", MAX_ALERTS);
+                break;
+            }
+        }
+
+        alerts
+    }
+}
+
+impl Rule {
+    fn update(&mut self, value: f32, now: Instant) {
+        let crossed = if self.above {
+            value > self.level
+        } else {
+            value < self.level
+        };
+        let recovered = if self.above {
+            value < self.clear
+        } else {
+            value > self.clear
+        };
+
+        if self.active {
+            if recovered {
+                log::info!("Alert on {} cleared at {}", self.name, value);
+                self.active = false;
+                self.crossed = None;
+            }
+        } else if crossed {
+            let since = *self.crossed.get_or_insert(now);
+            if now.saturating_duration_since(since) >= self.hold {
+                log::warn!("Alert on {} triggered at {}", self.name, value);
+                self.active = true;
+            }
+        } else {
+            self.crossed = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config(metric: &str, above: Option<f32>, below: Option<f32>) -> AlertConfig {
+        AlertConfig {
+            metric: metric.to_string(),
+            above,
+            below,
+            clear: None,
+            hold_secs: 30,
+        }
+    }
+
+    fn update(monitor: &mut AlertMonitor, value: f32, now: Instant) -> Vec<MetricId> {
+        let metrics = [Metric::new(metric::MEM_LOAD, value)];
+        monitor.update(&metrics, now).metrics.to_vec()
+    }
+
+    #[test]
+    fn validate_requires_one_threshold() {
+        assert!(config("mem_load", Some(90.0), None).validate().is_ok());
+        assert!(config("mem_load", None, None).validate().is_err());
+        assert!(config("mem_load", Some(90.0), Some(10.0))
+            .validate()
+            .is_err());
+        assert!(config("gpu", Some(90.0), None).validate().is_err());
+    }
+
+    #[test]
+    fn validate_requires_clear_past_threshold() {
+        let with_clear = |above, below, clear| AlertConfig {
+            clear: Some(clear),
+            ..config("mem_load", above, below)
+        };
+
+        assert!(with_clear(Some(90.0), None, 80.0).validate().is_ok());
+        assert!(with_clear(Some(90.0), None, 95.0).validate().is_err());
+        assert!(with_clear(Some(90.0), None, 90.0).validate().is_err());
+        assert!(with_clear(None, Some(10.0), 15.0).validate().is_ok());
+        assert!(with_clear(None, Some(10.0), 5.0).validate().is_err());
+    }
+
+    #[test]
+    fn triggers_after_hold() {
+        let mut monitor = AlertMonitor::new(&[config("mem_load", Some(90.0), None)]);
+        let start = Instant::now();
+
+        assert!(update(&mut monitor, 0.95, start).is_empty());
+        assert!(update(&mut monitor, 0.95, start + Duration::from_secs(29)).is_empty());
+        assert_eq!(
+            update(&mut monitor, 0.95, start + Duration::from_secs(30)),
+            vec![metric::MEM_LOAD]
+        );
+    }
+
+    #[test]
+    fn dip_restarts_hold() {
+        let mut monitor = AlertMonitor::new(&[config("mem_load", Some(90.0), None)]);
+        let start = Instant::now();
+
+        update(&mut monitor, 0.95, start);
+        update(&mut monitor, 0.80, start + Duration::from_secs(20));
+        update(&mut monitor, 0.95, start + Duration::from_secs(25));
+
+        assert!(update(&mut monitor, 0.95, start + Duration::from_secs(40)).is_empty());
+    }
+
+    #[test]
+    fn clears_with_hysteresis() {
+        let mut monitor = AlertMonitor::new(&[config("mem_load", Some(90.0), None)]);
+        let start = Instant::now();
+        update(&mut monitor, 0.95, start);
+        update(&mut monitor, 0.95, start + Duration::from_secs(30));
+
+        // Default clear level is 5% below the threshold, 85.5%.
+        let later = start + Duration::from_secs(31);
+        assert!(!update(&mut monitor, 0.88, later).is_empty());
+        assert!(update(&mut monitor, 0.85, later).is_empty());
+    }
+
+    #[test]
+    fn below_threshold_in_units() {
+        let mut rule = config("custom_0", None, Some(10.0));
+        rule.hold_secs = 0;
+        let mut monitor = AlertMonitor::new(&[rule]);
+
+        let metrics = [Metric::new(metric::custom(0), 5.0)];
+        let alerts = monitor.update(&metrics, Instant::now());
+
+        assert!(alerts.contains(metric::custom(0)));
+    }
+
+    #[test]
+    fn nan_does_not_clear() {
+        let mut rule = config("mem_load", Some(90.0), None);
+        rule.hold_secs = 0;
+        let mut monitor = AlertMonitor::new(&[rule]);
+        let now = Instant::now();
+        update(&mut monitor, 0.95, now);
+
+        assert!(!update(&mut monitor, f32::NAN, now).is_empty());
+    }
+}
@@ -0,0 +1,480 @@
+use crate::collector::Collector;
+use crate::{device, Error};
+use serde::{Deserialize, Serialize};
+use shared::message::{Notification, NotifyLevel};
+use shared::metric::{self, Metric, MetricLabel};
+use shared::settings::Page;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use tokio::sync::Notify;
+
+/// Pushed metrics not updated within this period are reported as failed.
+const METRIC_TTL: Duration = Duration::from_secs(60);
+
+/// Notifications held while the device is disconnected, older ones are dropped.
+const MAX_QUEUED_NOTIFICATIONS: usize = 8;
+
+/// Seconds a notification is shown, unless the request specifies otherwise.
+pub const DEFAULT_NOTIFY_SECS: u16 = 5;
+
+/// Request from a local program, sent as a single line of JSON.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum Request {
+    /// Sets a metric, e.g. `{"metric": {"name": "custom_0", "value": 3}}`.
+    Metric(PushedMetric),
+    /// Switches page, e.g. `{"page": "top"}`.
+    Page(Page),
+    /// Shows a notification, e.g. `{"notify": {"text": "Build failed", "level": "error"}}`.
+    Notify(NotifyRequest),
+}
+
+fn default_duration() -> u16 {
+    DEFAULT_NOTIFY_SECS
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct PushedMetric {
+    // Metric name, as used by layouts.
+    name: String,
+    value: f32,
+    label: Option<MetricLabel>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+struct NotifyRequest {
+    // Truncated to fit a message if too long.
+    text: String,
+    #[serde(default)]
+    level: NotifyLevel,
+    // Seconds to show the notification.
+    #[serde(default = "default_duration")]
+    duration: u16,
+}
+
+/// Reply to each request line.
+#[derive(Debug, Deserialize, Serialize)]
+struct Reply {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Requests received since they were last taken.
+#[derive(Debug, Default, PartialEq)]
+pub struct Received {
+    pub page: Option<Page>,
+    pub notifications: Vec<Notification>,
+}
+
+/// Requests waiting to be sent to the device.
+#[derive(Default)]
+struct Inbox {
+    // Latest value of each pushed metric, and when it was pushed.
+    metrics: Vec<(Metric, Instant)>,
+    received: Received,
+}
+
+/// Unix socket where local programs push metrics, notifications and page switches, without
+/// needing access to the serial port.  Pushed metrics are collected alongside the others.
+#[derive(Clone)]
+pub struct Server {
+    inbox: Arc<Mutex<Inbox>>,
+    // Notified as requests arrive.
+    arrived: Arc<Notify>,
+}
+
+impl Server {
+    /// Listens on a socket at `path`, handling each connection as a task on a background
+    /// thread, which outlives device connections.  A socket left behind by a previous run is
+    /// replaced.
+    pub fn start(path: &Path) -> io::Result<Server> {
+        if let Ok(meta) = std::fs::symlink_metadata(path) {
+            if meta.file_type().is_socket() {
+                std::fs::remove_file(path)?;
+            }
+        }
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .build()?;
+
+        let server = Server {
+            inbox: Arc::new(Mutex::new(Inbox::default())),
+            arrived: Arc::new(Notify::new()),
+        };
+        let accepting = server.clone();
+        thread::spawn(move || {
+            runtime.block_on(async {
+                let listener = match tokio::net::UnixListener::from_std(listener) {
+                    Ok(listener) => listener,
+                    Err(err) => {
+                        log::error!("Failed to accept API connections: {}", err);
+                        return;
+                    }
+                };
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, _)) => {
+                            tokio::spawn(handle_client(stream, accepting.clone()));
+                        }
+                        Err(err) => log::warn!("Failed to accept API connection: {}", err),
+                    }
+                }
+            })
+        });
+
+        Ok(server)
+    }
+
+    /// Returns page switches and notifications received since the last call.
+    pub fn take(&self) -> Received {
+        std::mem::take(&mut lock(&self.inbox).received)
+    }
+
+    /// Waits for page switches or notifications, returning those received since the last
+    /// call.
+    pub async fn received(&self) -> Received {
+        loop {
+            let received = self.take();
+            if received != Received::default() {
+                return received;
+            }
+            self.arrived.notified().await;
+        }
+    }
+}
+
+impl Collector for Server {
+    fn collect(&mut self, metrics: &mut Vec<Metric>) -> Result<(), Error> {
+        lock(&self.inbox).collect(metrics, Instant::now());
+        Ok(())
+    }
+}
+
+impl Inbox {
+    fn accept(&mut self, request: Request) -> Result<(), String> {
+        match request {
+            Request::Metric(pushed) => {
+                let id = metric::by_name(&pushed.name)
+                    .ok_or_else(|| format!("unknown metric {:?}", pushed.name))?;
+                let mut metric = Metric::new(id, pushed.value);
+                metric.label = pushed.label;
+
+                let now = Instant::now();
+                match self.metrics.iter_mut().find(|(m, _)| m.id == id) {
+                    Some(entry) => *entry = (metric, now),
+                    None => self.metrics.push((metric, now)),
+                }
+            }
+            Request::Page(page) => self.received.page = Some(page),
+            Request::Notify(notify) => {
+                let notifications = &mut self.received.notifications;
+                if notifications.len() >= MAX_QUEUED_NOTIFICATIONS {
+                    notifications.remove(0);
+                }
+                notifications.push(device::notification(
+                    &notify.text,
+                    notify.level,
+                    notify.duration,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Appends pushed metrics, with those not updated recently marked as failed.
+    fn collect(&self, metrics: &mut Vec<Metric>, now: Instant) {
+        for (metric, pushed) in &self.metrics {
+            let mut metric = metric.clone();
+            if now.saturating_duration_since(*pushed) > METRIC_TTL {
+                metric.value = f32::NAN;
+            }
+            metrics.push(metric);
+        }
+    }
+}
+
+/// Sends a notification to the API socket of a running daemon.
+pub fn notify(
+    path: &Path,
+    text: &str,
+    level: NotifyLevel,
+    duration_secs: u16,
+) -> Result<(), Error> {
+    #[derive(Serialize)]
+    #[serde(rename_all = "snake_case")]
+    enum Outgoing<'a> {
+        Notify(&'a NotifyRequest),
+    }
+
+    let request = NotifyRequest {
+        text: text.to_string(),
+        level,
+        duration: duration_secs,
+    };
+    let mut line =
+        serde_json::to_string(&Outgoing::Notify(&request)).expect("request serialization failed");
+    line.push('\n');
+
+    let mut stream = UnixStream::connect(path).map_err(Error::IO)?;
+    stream.write_all(line.as_bytes()).map_err(Error::IO)?;
+    let mut reply = String::new();
+    BufReader::new(stream)
+        .read_line(&mut reply)
+        .map_err(Error::IO)?;
+
+    check_reply(&reply)
+}
+
+// Parses a reply line, returning an error unless the daemon accepted the request.
+fn check_reply(line: &str) -> Result<(), Error> {
+    let reply: Reply = serde_json::from_str(line)
+        .map_err(|err| Error::IO(io::Error::new(io::ErrorKind::InvalidData, err)))?;
+    if reply.ok {
+        return Ok(());
+    }
+    let err = reply
+        .error
+        .unwrap_or_else(|| "request rejected by daemon".to_string());
+    Err(Error::IO(io::Error::other(err)))
+}
+
+// The inbox holds plain data, so remains usable if a client thread panicked.
+fn lock(inbox: &Mutex<Inbox>) -> MutexGuard<'_, Inbox> {
+    inbox.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Handles request lines from a client until it disconnects, replying to each.
+async fn handle_client(stream: tokio::net::UnixStream, server: Server) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = tokio::io::BufReader::new(reader).lines();
+
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => return,
+            Err(err) => {
+                log::debug!("API connection closed: {}", err);
+                return;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let result = serde_json::from_str(&line)
+            .map_err(|err| err.to_string())
+            .and_then(|request| lock(&server.inbox).accept(request));
+        server.arrived.notify_one();
+        let reply = match result {
+            Ok(()) => Reply {
+                ok: true,
+                error: None,
+            },
+            Err(err) => {
+                log::debug!("Rejected API request {:?}: {}", line, err);
+                Reply {
+                    ok: false,
+                    error: Some(err),
+                }
+            }
+        };
+
+        let mut text = serde_json::to_string(&reply).expect("reply serialization failed");
+        text.push('\n');
+        if writer.write_all(text.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::TempPath;
+    use shared::message::NOTIFY_TEXT_LEN;
+
+    fn request(json: &str) -> Request {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn parse_requests() {
+        assert_eq!(
+            request(r#"{"metric": {"name": "custom_2", "value": 4.5, "label": "jobs"}}"#),
+            Request::Metric(PushedMetric {
+                name: "custom_2".to_string(),
+                value: 4.5,
+                label: Some("jobs".into()),
+            })
+        );
+        assert_eq!(request(r#"{"page": "top"}"#), Request::Page(Page::Top));
+        assert!(serde_json::from_str::<Request>(r#"{"page": "graphs"}"#).is_err());
+    }
+
+    #[test]
+    fn accept_replaces_metric() {
+        let mut inbox = Inbox::default();
+
+        inbox
+            .accept(request(r#"{"metric": {"name": "custom_0", "value": 1}}"#))
+            .unwrap();
+        inbox
+            .accept(request(r#"{"metric": {"name": "custom_0", "value": 2}}"#))
+            .unwrap();
+        let mut metrics = Vec::new();
+        inbox.collect(&mut metrics, Instant::now());
+
+        assert_eq!(metrics, vec![Metric::new(metric::custom(0), 2.0)]);
+    }
+
+    #[test]
+    fn accept_rejects_unknown_metric() {
+        let mut inbox = Inbox::default();
+
+        let result = inbox.accept(request(r#"{"metric": {"name": "gpu", "value": 1}}"#));
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn stale_metric_is_nan() {
+        let mut inbox = Inbox::default();
+        inbox
+            .accept(request(r#"{"metric": {"name": "custom_0", "value": 1}}"#))
+            .unwrap();
+        let mut metrics = Vec::new();
+
+        inbox.collect(&mut metrics, Instant::now() + METRIC_TTL * 2);
+
+        assert!(metrics[0].value.is_nan());
+    }
+
+    #[test]
+    fn notification_text_truncated() {
+        let mut inbox = Inbox::default();
+        let long = "x".repeat(NOTIFY_TEXT_LEN * 2);
+
+        inbox
+            .accept(Request::Notify(NotifyRequest {
+                text: long,
+                level: NotifyLevel::Info,
+                duration: DEFAULT_NOTIFY_SECS,
+            }))
+            .unwrap();
+
+        assert_eq!(inbox.received.notifications[0].text.len(), NOTIFY_TEXT_LEN);
+    }
+
+    #[test]
+    fn parse_notify_options() {
+        assert_eq!(
+            request(r#"{"notify": {"text": "Disk full", "level": "error", "duration": 30}}"#),
+            Request::Notify(NotifyRequest {
+                text: "Disk full".to_string(),
+                level: NotifyLevel::Error,
+                duration: 30,
+            })
+        );
+    }
+
+    #[test]
+    fn notify_client() {
+        let path = TempPath::new("notify.sock");
+        let server = Server::start(&path).unwrap();
+
+        let result = notify(&path, "Deploy done", NotifyLevel::Warning, 10);
+        let received = server.take();
+
+        result.unwrap();
+        assert_eq!(
+            received.notifications,
+            vec![Notification {
+                text: "Deploy done".into(),
+                level: NotifyLevel::Warning,
+                duration_secs: 10,
+            }]
+        );
+    }
+
+    #[test]
+    fn check_reply_rejects_failure() {
+        assert!(check_reply(r#"{"ok":true}"#).is_ok());
+        assert!(check_reply(r#"{"ok":false,"error":"bad level"}"#).is_err());
+        assert!(check_reply(r#"{"ok":false}"#).is_err());
+        assert!(check_reply("garbage").is_err());
+    }
+
+    #[test]
+    fn received_waits_for_request() {
+        let path = TempPath::new("wait.sock");
+        let server = Server::start(&path).unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+
+        let sender = {
+            let path = path.to_path_buf();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                notify(&path, "Tests passed", NotifyLevel::Info, 5)
+            })
+        };
+        let received = runtime.block_on(async {
+            tokio::time::timeout(Duration::from_secs(10), server.received()).await
+        });
+
+        sender.join().unwrap().unwrap();
+        assert_eq!(received.unwrap().notifications[0].text, "Tests passed");
+    }
+
+    #[test]
+    fn socket_round_trip() {
+        let path = TempPath::new("api.sock");
+        let mut server = Server::start(&path).unwrap();
+        let stream = UnixStream::connect(&path).unwrap();
+        let mut replies = BufReader::new(stream.try_clone().unwrap()).lines();
+        let mut send = |line: &str| {
+            (&stream).write_all(line.as_bytes()).unwrap();
+            replies.next().unwrap().unwrap()
+        };
+
+        let replies = [
+            send("{\"metric\": {\"name\": \"custom_1\", \"value\": 7}}\n"),
+            send("{\"page\": \"top\"}\n"),
+            send("{\"notify\": {\"text\": \"Build finished\"}}\n"),
+            send("not json\n"),
+        ];
+        let received = server.take();
+        let mut metrics = Vec::new();
+        server.collect(&mut metrics).unwrap();
+
+        assert_eq!(replies[0], r#"{"ok":true}"#);
+        assert!(replies[3].starts_with(r#"{"ok":false,"error":"#));
+        assert_eq!(
+            received,
+            Received {
+                page: Some(Page::Top),
+                notifications: vec![Notification {
+                    text: "Build finished".into(),
+                    level: NotifyLevel::Info,
+                    duration_secs: DEFAULT_NOTIFY_SECS,
+                }],
+            }
+        );
+        assert_eq!(metrics, vec![Metric::new(metric::custom(1), 7.0)]);
+        assert_eq!(server.take(), Received::default());
+    }
+}
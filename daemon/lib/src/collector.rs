@@ -0,0 +1,216 @@
+use crate::error::{Backoff, Recovery};
+use crate::stop::StopSignal;
+use crate::{Config, Error};
+use shared::metric::Metric;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[cfg(target_os = "linux")]
+pub use battery::BatteryCollector;
+#[cfg(target_os = "linux")]
+pub use cgroup::CgroupCollector;
+pub use command::{CommandCollector, CommandConfig};
+pub use cpu::CpuCollector;
+#[cfg(target_os = "linux")]
+pub use frequency::FrequencyCollector;
+pub use memory::{MemoryCollector, UsedMemory};
+#[cfg(target_os = "linux")]
+pub use pressure::PressureCollector;
+pub use scrape::{validate_url, ScrapeCollector};
+pub use system::SystemCollector;
+#[cfg(target_os = "linux")]
+pub use top::TopCollector;
+
+#[cfg(target_os = "linux")]
+mod battery;
+#[cfg(target_os = "linux")]
+mod cgroup;
+mod command;
+mod cpu;
+#[cfg(target_os = "linux")]
+mod frequency;
+mod memory;
+#[cfg(target_os = "linux")]
+mod pressure;
+mod scrape;
+mod system;
+#[cfg(target_os = "linux")]
+mod top;
+
+/// Source of metrics sent to the device.
+pub trait Collector: Send {
+    /// Appends current metric values to `metrics`.
+    fn collect(&mut self, metrics: &mut Vec<Metric>) -> Result<(), Error>;
+}
+
+/// Delay before retrying a failed collector, doubling with each failure.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Longest delay before retrying a failed collector.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Skips a collector after it fails, retrying it with exponential backoff, so that one failing
+/// source does not disconnect the device.  Failures requiring a reconnect are passed on.
+pub struct Retrying {
+    inner: Box<dyn Collector>,
+    backoff: Backoff,
+    retry_at: Option<Instant>,
+}
+
+impl Retrying {
+    pub fn new(inner: Box<dyn Collector>) -> Self {
+        Retrying {
+            inner,
+            backoff: Backoff::new(RETRY_DELAY, MAX_RETRY_DELAY),
+            retry_at: None,
+        }
+    }
+}
+
+impl Collector for Retrying {
+    fn collect(&mut self, metrics: &mut Vec<Metric>) -> Result<(), Error> {
+        if self
+            .retry_at
+            .is_some_and(|retry_at| Instant::now() < retry_at)
+        {
+            return Ok(());
+        }
+
+        match self.inner.collect(metrics) {
+            Ok(()) => {
+                if self.retry_at.take().is_some() {
+                    log::info!("Collector recovered");
+                }
+                self.backoff.succeeded();
+                Ok(())
+            }
+            Err(err) if err.recovery() == Recovery::RetryCollector => {
+                let delay = self.backoff.failed();
+                log::warn!("{}, retrying in {:?}", err, delay);
+                self.retry_at = Some(Instant::now() + delay);
+                Ok(())
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Creates the collectors selected by the config, each retried independently should it fail.
+/// The first collector blocks while measuring CPU load, setting the send period, until
+/// `stop` is signaled.
+pub fn from_config(config: &Config, stop: &Arc<StopSignal>) -> Vec<Box<dyn Collector>> {
+    let mut collectors: Vec<Box<dyn Collector>> = Vec::new();
+
+    // A remote host replaces all local host metrics.
+    if let Some(url) = &config.scrape.url {
+        collectors.push(Box::new(ScrapeCollector::new(
+            url,
+            config.memory.used,
+            Arc::clone(stop),
+        )));
+        push_commands(config, &mut collectors);
+        return with_retries(collectors);
+    }
+
+    #[cfg(target_os = "linux")]
+    if let Some(path) = &config.cgroup.path {
+        collectors.push(Box::new(CgroupCollector::new(path, Arc::clone(stop))));
+    }
+    if collectors.is_empty() {
+        collectors.push(Box::new(CpuCollector::new(Arc::clone(stop))));
+        collectors.push(Box::new(MemoryCollector::new(config.memory.used)));
+    }
+
+    collectors.push(Box::new(SystemCollector::new()));
+    // Reading every process is only worthwhile while the top page may be shown.
+    #[cfg(target_os = "linux")]
+    if config.shows_top() {
+        collectors.push(Box::new(TopCollector::new("/proc")));
+    }
+    #[cfg(target_os = "linux")]
+    if std::path::Path::new(pressure::PRESSURE_ROOT).exists() {
+        collectors.push(Box::new(PressureCollector::new(pressure::PRESSURE_ROOT)));
+    }
+    #[cfg(target_os = "linux")]
+    if std::path::Path::new(frequency::CPU_ROOT)
+        .join("cpu0/cpufreq")
+        .exists()
+    {
+        collectors.push(Box::new(FrequencyCollector::new(frequency::CPU_ROOT)));
+    }
+    #[cfg(target_os = "linux")]
+    collectors.push(Box::new(BatteryCollector::new(battery::POWER_SUPPLY_ROOT)));
+    push_commands(config, &mut collectors);
+
+    with_retries(collectors)
+}
+
+fn with_retries(collectors: Vec<Box<dyn Collector>>) -> Vec<Box<dyn Collector>> {
+    collectors
+        .into_iter()
+        .map(|collector| Box::new(Retrying::new(collector)) as Box<dyn Collector>)
+        .collect()
+}
+
+fn push_commands(config: &Config, collectors: &mut Vec<Box<dyn Collector>>) {
+    for command in &config.command {
+        collectors.push(Box::new(CommandCollector::new(command.clone())));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shared::metric;
+    use std::io;
+
+    // Fails the first `failures` calls, then succeeds.
+    struct FlakyCollector {
+        failures: usize,
+        calls: usize,
+    }
+
+    impl Collector for FlakyCollector {
+        fn collect(&mut self, metrics: &mut Vec<Metric>) -> Result<(), Error> {
+            self.calls += 1;
+            if self.calls <= self.failures {
+                return Err(Error::Collector(io::ErrorKind::NotFound.into()));
+            }
+            metrics.push(Metric::new(metric::CPU_LOAD, 0.5));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn retrying_skips_failed_collector() {
+        let mut collector = Retrying::new(Box::new(FlakyCollector {
+            failures: 1,
+            calls: 0,
+        }));
+        let mut metrics = Vec::new();
+
+        collector.collect(&mut metrics).unwrap();
+        collector.collect(&mut metrics).unwrap();
+        assert!(metrics.is_empty());
+
+        collector.retry_at = Some(Instant::now());
+        collector.collect(&mut metrics).unwrap();
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(collector.retry_at, None);
+    }
+
+    #[test]
+    fn retrying_passes_on_device_errors() {
+        struct GoneCollector;
+        impl Collector for GoneCollector {
+            fn collect(&mut self, _: &mut Vec<Metric>) -> Result<(), Error> {
+                Err(Error::DeviceGone(io::ErrorKind::BrokenPipe.into()))
+            }
+        }
+        let mut collector = Retrying::new(Box::new(GoneCollector));
+
+        let result = collector.collect(&mut Vec::new());
+
+        assert!(matches!(result, Err(Error::DeviceGone(_))));
+    }
+}
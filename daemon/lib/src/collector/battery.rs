@@ -0,0 +1,181 @@
+use super::Collector;
+use crate::Error;
+use shared::metric::{self, Metric};
+use std::path::{Path, PathBuf};
+
+/// Location of power supplies in sysfs.
+pub const POWER_SUPPLY_ROOT: &str = "/sys/class/power_supply";
+
+/// Reports battery charge, charging state and power draw.  Nothing is reported if the
+/// system has no battery.
+pub struct BatteryCollector {
+    root: PathBuf,
+}
+
+/// State of a single battery.
+#[derive(Debug, PartialEq)]
+struct Battery {
+    // Charge in percent.
+    capacity: u8,
+    charging: bool,
+    watts: f32,
+}
+
+impl BatteryCollector {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        BatteryCollector { root: root.into() }
+    }
+}
+
+impl Collector for BatteryCollector {
+    fn collect(&mut self, metrics: &mut Vec<Metric>) -> Result<(), Error> {
+        let batteries: Vec<Battery> = match std::fs::read_dir(&self.root) {
+            Ok(entries) => entries
+                .filter_map(|entry| read_battery(&entry.ok()?.path()))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        if batteries.is_empty() {
+            return Ok(());
+        }
+
+        // Laptops with several batteries report their average charge and total power.
+        let capacity =
+            batteries.iter().map(|b| b.capacity as f32).sum::<f32>() / batteries.len() as f32;
+        let charging = batteries.iter().any(|b| b.charging);
+        let watts = batteries.iter().map(|b| b.watts).sum::<f32>();
+
+        metrics.push(Metric::new(metric::BATTERY_LEVEL, capacity / 100.0));
+        metrics.push(Metric::new(
+            metric::BATTERY_CHARGING,
+            if charging { 1.0 } else { 0.0 },
+        ));
+        metrics.push(Metric::new(metric::BATTERY_POWER, watts));
+
+        Ok(())
+    }
+}
+
+fn read_attr(supply: &Path, name: &str) -> Option<String> {
+    std::fs::read_to_string(supply.join(name))
+        .ok()
+        .map(|text| text.trim().to_string())
+}
+
+fn read_number(supply: &Path, name: &str) -> Option<f64> {
+    read_attr(supply, name)?.parse().ok()
+}
+
+// Reads a system battery, ignoring mains supplies and peripherals such as wireless mice.
+fn read_battery(supply: &Path) -> Option<Battery> {
+    if read_attr(supply, "type")? != "Battery" {
+        return None;
+    }
+    if read_attr(supply, "scope").as_deref() == Some("Device") {
+        return None;
+    }
+
+    // Batteries report either power directly, or current and voltage; all in micro units.
+    let micro_watts = read_number(supply, "power_now").or_else(|| {
+        Some(read_number(supply, "current_now")? * read_number(supply, "voltage_now")? / 1e6)
+    });
+
+    Some(Battery {
+        capacity: read_number(supply, "capacity")?.clamp(0.0, 100.0) as u8,
+        charging: read_attr(supply, "status").as_deref() == Some("Charging"),
+        watts: micro_watts
+            .map(|uw| (uw.abs() / 1e6) as f32)
+            .unwrap_or_default(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::TempPath;
+    use std::fs;
+
+    fn fake_supply(root: &Path, name: &str, attrs: &[(&str, &str)]) {
+        let dir = root.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        for (attr, value) in attrs {
+            fs::write(dir.join(attr), format!("{}\n", value)).unwrap();
+        }
+    }
+
+    fn collect(name: &str, setup: impl FnOnce(&Path)) -> Vec<Metric> {
+        let root = TempPath::dir(&format!("battery-{}", name));
+        setup(&root);
+        let mut metrics = Vec::new();
+
+        BatteryCollector::new(&root).collect(&mut metrics).unwrap();
+        metrics
+    }
+
+    #[test]
+    fn no_battery_reports_nothing() {
+        let metrics = collect("none", |root| {
+            fake_supply(root, "AC", &[("type", "Mains"), ("online", "1")]);
+        });
+
+        assert!(metrics.is_empty());
+    }
+
+    #[test]
+    fn reports_power_now() {
+        let metrics = collect("power", |root| {
+            fake_supply(
+                root,
+                "BAT0",
+                &[
+                    ("type", "Battery"),
+                    ("capacity", "80"),
+                    ("status", "Discharging"),
+                    ("power_now", "12500000"),
+                ],
+            );
+        });
+
+        assert_eq!(
+            metrics,
+            vec![
+                Metric::new(metric::BATTERY_LEVEL, 0.8),
+                Metric::new(metric::BATTERY_CHARGING, 0.0),
+                Metric::new(metric::BATTERY_POWER, 12.5),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_current_and_voltage() {
+        let metrics = collect("current", |root| {
+            fake_supply(
+                root,
+                "BAT1",
+                &[
+                    ("type", "Battery"),
+                    ("capacity", "50"),
+                    ("status", "Charging"),
+                    ("current_now", "2000000"),
+                    ("voltage_now", "12000000"),
+                ],
+            );
+        });
+
+        assert_eq!(metrics[1], Metric::new(metric::BATTERY_CHARGING, 1.0));
+        assert_eq!(metrics[2], Metric::new(metric::BATTERY_POWER, 24.0));
+    }
+
+    #[test]
+    fn ignores_peripheral_batteries() {
+        let metrics = collect("mouse", |root| {
+            fake_supply(
+                root,
+                "hidpp_battery_0",
+                &[("type", "Battery"), ("scope", "Device"), ("capacity", "5")],
+            );
+        });
+
+        assert!(metrics.is_empty());
+    }
+}
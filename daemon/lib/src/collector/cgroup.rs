@@ -0,0 +1,247 @@
+use super::Collector;
+use crate::avg::Averager;
+use crate::stop::StopSignal;
+use crate::Error;
+use shared::metric::{self, Metric};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use systemstat::{Platform, System};
+
+const CPU_POLL_PERIOD: Duration = Duration::from_secs(1);
+const AVG_CPU_SAMPLES: usize = 15; // Seconds of data for CPU average.
+
+/// Mount point of the cgroup v2 hierarchy.
+pub const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Reports CPU and memory load of a single cgroup v2, relative to its limits.  Replaces the
+/// systemwide CPU and memory collectors.
+pub struct CgroupCollector {
+    dir: PathBuf,
+    avg: Averager,
+    poll_period: Duration,
+    stop: Arc<StopSignal>,
+}
+
+impl CgroupCollector {
+    /// Creates a collector for the cgroup at `path`, which is relative to the cgroup mount
+    /// point unless absolute.  Polling is cut short by `stop`.
+    pub fn new(path: &Path, stop: Arc<StopSignal>) -> Self {
+        CgroupCollector {
+            dir: Path::new(CGROUP_ROOT).join(path),
+            avg: Averager::new(AVG_CPU_SAMPLES),
+            poll_period: CPU_POLL_PERIOD,
+            stop,
+        }
+    }
+
+    fn read(&self, name: &str) -> Result<String, Error> {
+        std::fs::read_to_string(self.dir.join(name)).map_err(Error::Collector)
+    }
+}
+
+impl Collector for CgroupCollector {
+    /// Measures CPU load, blocking for the poll period or until stopped.
+    fn collect(&mut self, metrics: &mut Vec<Metric>) -> Result<(), Error> {
+        let start = Instant::now();
+        let start_usec = parse_usage_usec(&self.read("cpu.stat")?)?;
+        self.stop.wait(self.poll_period);
+        let end_usec = parse_usage_usec(&self.read("cpu.stat")?)?;
+        let elapsed_usec = start.elapsed().as_micros().max(1) as f32;
+
+        // Without a quota, the cgroup may use every core.
+        let cpus = match self.read("cpu.max") {
+            Ok(text) => parse_cpu_max(&text)?,
+            Err(_) => None,
+        }
+        .unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1) as f32
+        });
+
+        let cpu_load = (end_usec.saturating_sub(start_usec) as f32 / elapsed_usec / cpus).min(1.0);
+        self.avg.add_sample(cpu_load as f64);
+        metrics.push(Metric::new(metric::CPU_LOAD, cpu_load));
+        metrics.push(Metric::new(
+            metric::CPU_AVG,
+            self.avg.average().unwrap_or_default() as f32,
+        ));
+
+        // Without a limit, memory is relative to the host.
+        let current = parse_bytes(&self.read("memory.current")?)?;
+        let limit = match parse_limit(&self.read("memory.max")?)? {
+            Some(limit) => limit,
+            None => System::new()
+                .memory()
+                .map_err(Error::Collector)?
+                .total
+                .as_u64(),
+        };
+        let file = self
+            .read("memory.stat")
+            .ok()
+            .and_then(|text| parse_stat_field(&text, "file"))
+            .unwrap_or_default()
+            .min(current);
+        metrics.extend(memory_metrics(current, file, limit));
+
+        Ok(())
+    }
+}
+
+// Calculates memory metrics, treating page cache as reclaimable rather than used.
+fn memory_metrics(current: u64, file: u64, limit: u64) -> [Metric; 4] {
+    let ratio = |bytes: u64| match limit {
+        0 => 0.0,
+        limit => (bytes as f32 / limit as f32).min(1.0),
+    };
+    let used = current - file;
+
+    [
+        Metric::new(metric::MEM_LOAD, ratio(used)),
+        Metric::new(metric::MEM_CACHE, ratio(file)),
+        Metric::new(metric::MEM_USED, used as f32),
+        Metric::new(metric::MEM_CACHED, file as f32),
+    ]
+}
+
+fn invalid(file: &str) -> Error {
+    Error::Collector(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected {} format", file),
+    ))
+}
+
+// Finds a "key value" line in a flat keyed file, such as cpu.stat or memory.stat.
+fn parse_stat_field(text: &str, key: &str) -> Option<u64> {
+    text.lines().find_map(|line| {
+        let (k, v) = line.split_once(' ')?;
+        if k == key {
+            v.trim().parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
+// Reads total CPU time used by the cgroup, in microseconds.
+fn parse_usage_usec(text: &str) -> Result<u64, Error> {
+    parse_stat_field(text, "usage_usec").ok_or_else(|| invalid("cpu.stat"))
+}
+
+// Parses cpu.max, e.g. "200000 100000", into the number of CPUs the quota allows.  Returns
+// None if unlimited.
+fn parse_cpu_max(text: &str) -> Result<Option<f32>, Error> {
+    let mut fields = text.split_whitespace();
+    let quota = fields.next().ok_or_else(|| invalid("cpu.max"))?;
+    if quota == "max" {
+        return Ok(None);
+    }
+
+    let quota: f32 = quota.parse().map_err(|_| invalid("cpu.max"))?;
+    let period: f32 = fields
+        .next()
+        .and_then(|p| p.parse().ok())
+        .filter(|p| *p > 0.0)
+        .ok_or_else(|| invalid("cpu.max"))?;
+
+    Ok(Some(quota / period))
+}
+
+// Parses a single value file, such as memory.current.
+fn parse_bytes(text: &str) -> Result<u64, Error> {
+    text.trim().parse().map_err(|_| invalid("memory"))
+}
+
+// Parses a limit file, such as memory.max.  Returns None if unlimited.
+fn parse_limit(text: &str) -> Result<Option<u64>, Error> {
+    match text.trim() {
+        "max" => Ok(None),
+        _ => parse_bytes(text).map(Some),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::TempPath;
+    use std::fs;
+
+    #[test]
+    fn new_joins_relative_path() {
+        let collector = CgroupCollector::new(Path::new("system.slice/ci.slice"), Arc::default());
+
+        assert_eq!(
+            collector.dir,
+            Path::new("/sys/fs/cgroup/system.slice/ci.slice")
+        );
+    }
+
+    #[test]
+    fn new_keeps_absolute_path() {
+        let collector = CgroupCollector::new(Path::new("/tmp/cg"), Arc::default());
+
+        assert_eq!(collector.dir, Path::new("/tmp/cg"));
+    }
+
+    #[test]
+    fn parse_cpu_max_quota() {
+        assert_eq!(parse_cpu_max("200000 100000\n").unwrap(), Some(2.0));
+        assert_eq!(parse_cpu_max("max 100000\n").unwrap(), None);
+        assert!(parse_cpu_max("50000 0\n").is_err());
+    }
+
+    #[test]
+    fn parse_usage() {
+        let text = "usage_usec 12345\nuser_usec 10000\nsystem_usec 2345\n";
+
+        assert_eq!(parse_usage_usec(text).unwrap(), 12345);
+        assert!(parse_usage_usec("user_usec 1\n").is_err());
+    }
+
+    #[test]
+    fn parse_memory_limit() {
+        assert_eq!(parse_limit("max\n").unwrap(), None);
+        assert_eq!(parse_limit("1048576\n").unwrap(), Some(1048576));
+    }
+
+    #[test]
+    fn memory_relative_to_limit() {
+        let metrics = memory_metrics(600, 200, 1000);
+
+        assert_eq!(metrics[0], Metric::new(metric::MEM_LOAD, 0.4));
+        assert_eq!(metrics[1], Metric::new(metric::MEM_CACHE, 0.2));
+    }
+
+    #[test]
+    fn collect_reads_cgroup_files() {
+        let dir = TempPath::dir("cgroup");
+        fs::write(dir.join("cpu.stat"), "usage_usec 1000\n").unwrap();
+        fs::write(dir.join("cpu.max"), "100000 100000\n").unwrap();
+        fs::write(dir.join("memory.current"), "512\n").unwrap();
+        fs::write(dir.join("memory.max"), "1024\n").unwrap();
+        fs::write(dir.join("memory.stat"), "anon 256\nfile 256\n").unwrap();
+        let mut collector = CgroupCollector::new(&dir, Arc::default());
+        collector.poll_period = Duration::ZERO;
+        let mut metrics = Vec::new();
+
+        collector.collect(&mut metrics).unwrap();
+
+        let ids: Vec<_> = metrics.iter().map(|m| m.id).collect();
+        assert_eq!(
+            ids,
+            vec![
+                metric::CPU_LOAD,
+                metric::CPU_AVG,
+                metric::MEM_LOAD,
+                metric::MEM_CACHE,
+                metric::MEM_USED,
+                metric::MEM_CACHED
+            ]
+        );
+        assert_eq!(metrics[0].value, 0.0);
+        assert_eq!(metrics[2].value, 0.25);
+    }
+}
@@ -0,0 +1,319 @@
+use super::Collector;
+use crate::Error;
+use serde::Deserialize;
+use shared::metric::{self, Metric, MetricLabel, Unit};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// An external command reporting site specific metrics, as described in the config file.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CommandConfig {
+    /// Shell command line to run.
+    pub command: String,
+    /// Seconds between runs.
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+    /// Seconds before the command is killed and considered failed.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    /// Metrics parsed from the output.
+    #[serde(rename = "metric")]
+    pub metrics: Vec<CommandMetric>,
+}
+
+/// Maps a value from command output to a custom metric slot.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CommandMetric {
+    /// Custom metric slot, displayed as metric `custom_<slot>`.
+    pub slot: u16,
+    /// Name in `name=value` output, or None if the output is a single number.
+    pub name: Option<String>,
+    /// Short label displayed by value widgets without a suffix.
+    pub label: Option<MetricLabel>,
+    /// Value scaled to 0%, if scaling.
+    pub min: Option<f32>,
+    /// Value scaled to 100%, if scaling.
+    pub max: Option<f32>,
+}
+
+fn default_interval() -> u64 {
+    10
+}
+
+fn default_timeout() -> u64 {
+    5
+}
+
+/// Runs an external command on an interval, without blocking the send loop.  Failures are
+/// reported to the device as NaN values rather than errors.
+pub struct CommandCollector {
+    config: CommandConfig,
+    // Running command and its start time.
+    child: Option<(Child, Instant)>,
+    last_run: Option<Instant>,
+    // Results of the previous run, resent until the next completes.
+    values: Vec<Metric>,
+}
+
+impl CommandCollector {
+    pub fn new(config: CommandConfig) -> Self {
+        let values = config.metrics.iter().map(|m| m.metric(f32::NAN)).collect();
+        CommandCollector {
+            config,
+            child: None,
+            last_run: None,
+            values,
+        }
+    }
+
+    fn spawn(&mut self) {
+        #[cfg(windows)]
+        let mut command = {
+            let mut command = Command::new("cmd");
+            command.arg("/C");
+            command
+        };
+        #[cfg(not(windows))]
+        let mut command = {
+            let mut command = Command::new("sh");
+            command.arg("-c");
+            command
+        };
+
+        let spawned = command
+            .arg(&self.config.command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn();
+        self.last_run = Some(Instant::now());
+        match spawned {
+            Ok(child) => self.child = Some((child, Instant::now())),
+            Err(err) => {
+                log::warn!("Failed to run {:?}: {}", self.config.command, err);
+                self.fail();
+            }
+        }
+    }
+
+    // Checks on the running command, recording its results once it exits.
+    fn poll(&mut self) {
+        let Some((child, started)) = &mut self.child else {
+            return;
+        };
+
+        match child.try_wait() {
+            Ok(None) if started.elapsed() < Duration::from_secs(self.config.timeout) => {}
+            Ok(None) => {
+                log::warn!("Command {:?} timed out", self.config.command);
+                let _ = child.kill();
+                let _ = child.wait();
+                self.child = None;
+                self.fail();
+            }
+            Ok(Some(_)) => {
+                let (child, _) = self.child.take().unwrap();
+                match child.wait_with_output() {
+                    Ok(output) if output.status.success() => {
+                        let stdout = String::from_utf8_lossy(&output.stdout);
+                        self.values = parse_output(&self.config.metrics, &stdout);
+                    }
+                    Ok(output) => {
+                        log::warn!(
+                            "Command {:?} failed: {}",
+                            self.config.command,
+                            output.status
+                        );
+                        self.fail();
+                    }
+                    Err(err) => {
+                        log::warn!("Command {:?} failed: {}", self.config.command, err);
+                        self.fail();
+                    }
+                }
+            }
+            Err(err) => {
+                log::warn!("Command {:?} failed: {}", self.config.command, err);
+                self.child = None;
+                self.fail();
+            }
+        }
+    }
+
+    fn fail(&mut self) {
+        for value in &mut self.values {
+            value.value = f32::NAN;
+        }
+    }
+}
+
+impl Collector for CommandCollector {
+    fn collect(&mut self, metrics: &mut Vec<Metric>) -> Result<(), Error> {
+        self.poll();
+
+        let due = match self.last_run {
+            Some(last) => last.elapsed() >= Duration::from_secs(self.config.interval),
+            None => true,
+        };
+        if due && self.child.is_none() {
+            self.spawn();
+            self.poll();
+        }
+
+        metrics.extend(self.values.iter().cloned());
+        Ok(())
+    }
+}
+
+impl Drop for CommandCollector {
+    fn drop(&mut self) {
+        if let Some((child, _)) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+impl CommandMetric {
+    // Creates the metric for a raw value, scaled if a range is configured.
+    fn metric(&self, raw: f32) -> Metric {
+        let mut metric = Metric::new(metric::custom(self.slot), raw);
+        metric.label = self.label.clone();
+        if let (Some(min), Some(max)) = (self.min, self.max) {
+            metric.unit = Unit::Ratio;
+            if max > min {
+                metric.value = ((raw - min) / (max - min)).clamp(0.0, 1.0);
+            }
+        }
+
+        metric
+    }
+}
+
+// Parses command output, either a single number or `name=value` lines.  Values that are
+// missing or not numeric are NaN.
+fn parse_output(metrics: &[CommandMetric], output: &str) -> Vec<Metric> {
+    metrics
+        .iter()
+        .map(|m| {
+            let raw = match &m.name {
+                None => output.trim().parse().ok(),
+                Some(name) => output.lines().find_map(|line| {
+                    let (key, value) = line.split_once('=')?;
+                    if key.trim() == name {
+                        value.trim().parse().ok()
+                    } else {
+                        None
+                    }
+                }),
+            };
+
+            // NaN is not scaled, so remains a failure.
+            m.metric(raw.unwrap_or(f32::NAN))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn metric(slot: u16, name: Option<&str>) -> CommandMetric {
+        CommandMetric {
+            slot,
+            name: name.map(String::from),
+            label: None,
+            min: None,
+            max: None,
+        }
+    }
+
+    fn config(command: &str, timeout: u64) -> CommandConfig {
+        CommandConfig {
+            command: command.to_string(),
+            interval: 60,
+            timeout,
+            metrics: vec![metric(0, None)],
+        }
+    }
+
+    // Collects until the command completes.
+    fn collect_result(collector: &mut CommandCollector) -> f32 {
+        for _ in 0..100 {
+            let mut metrics = Vec::new();
+            collector.collect(&mut metrics).unwrap();
+            if collector.child.is_none() {
+                return metrics[0].value;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        panic!("command did not complete");
+    }
+
+    #[test]
+    fn parse_single_number() {
+        let actual = parse_output(&[metric(1, None)], " 42.5\n");
+
+        assert_eq!(actual, vec![Metric::new(metric::custom(1), 42.5)]);
+    }
+
+    #[test]
+    fn parse_named_values() {
+        let metrics = [metric(0, Some("depth")), metric(1, Some("rate"))];
+
+        let actual = parse_output(&metrics, "rate=0.5\ndepth = 12\nother=1\n");
+
+        assert_eq!(actual[0].value, 12.0);
+        assert_eq!(actual[1].value, 0.5);
+    }
+
+    #[test]
+    fn parse_missing_value_is_nan() {
+        let actual = parse_output(&[metric(0, Some("depth"))], "build=ok\n");
+
+        assert!(actual[0].value.is_nan());
+    }
+
+    #[test]
+    fn scales_to_range() {
+        let m = CommandMetric {
+            min: Some(100.0),
+            max: Some(200.0),
+            label: Some("queue".into()),
+            ..metric(2, None)
+        };
+
+        let actual = m.metric(150.0);
+
+        assert_eq!(actual.value, 0.5);
+        assert_eq!(actual.unit, Unit::Ratio);
+        assert_eq!(actual.label, Some("queue".into()));
+        assert_eq!(m.metric(500.0).value, 1.0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn runs_command() {
+        let mut collector = CommandCollector::new(config("echo 7", 5));
+
+        assert_eq!(collect_result(&mut collector), 7.0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failed_command_is_nan() {
+        let mut collector = CommandCollector::new(config("exit 3", 5));
+
+        assert!(collect_result(&mut collector).is_nan());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn kills_command_after_timeout() {
+        let mut collector = CommandCollector::new(config("sleep 10", 0));
+
+        assert!(collect_result(&mut collector).is_nan());
+    }
+}
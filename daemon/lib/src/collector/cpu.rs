@@ -0,0 +1,66 @@
+use super::Collector;
+use crate::avg::Averager;
+use crate::stop::StopSignal;
+use crate::Error;
+use shared::metric::{self, Metric};
+use std::sync::Arc;
+use std::time::Duration;
+use systemstat::{data::CPULoad, Platform, System};
+
+const CPU_POLL_PERIOD: Duration = Duration::from_secs(1);
+const AVG_CPU_SAMPLES: usize = 15; // Seconds of data for CPU average.
+
+/// Reports aggregate, averaged and peak core CPU load.
+pub struct CpuCollector {
+    sys: System,
+    avg: Averager,
+    stop: Arc<StopSignal>,
+}
+
+impl CpuCollector {
+    /// Creates a collector whose polling is cut short by `stop`.
+    pub fn new(stop: Arc<StopSignal>) -> Self {
+        CpuCollector {
+            sys: System::new(),
+            avg: Averager::new(AVG_CPU_SAMPLES),
+            stop,
+        }
+    }
+}
+
+impl Collector for CpuCollector {
+    /// Measures CPU load, blocking for the poll period or until stopped.
+    fn collect(&mut self, metrics: &mut Vec<Metric>) -> Result<(), Error> {
+        fn busy_fraction(load: &CPULoad) -> f32 {
+            1.0f32 - load.idle
+        }
+
+        // Capture CPU metrics.
+        let cpu_load = self.sys.cpu_load().map_err(Error::Collector)?;
+        let load_agg = self.sys.cpu_load_aggregate().map_err(Error::Collector)?;
+        self.stop.wait(CPU_POLL_PERIOD);
+
+        // Load across all cores.
+        let load_agg = load_agg.done().map_err(Error::Collector)?;
+
+        // Select least idle core.
+        let cpu_load = cpu_load.done().map_err(Error::Collector)?;
+        let min_idle = cpu_load
+            .iter()
+            .min_by(|a, b| a.idle.partial_cmp(&b.idle).unwrap())
+            .unwrap_or(&load_agg);
+
+        // Average all cores load over time.
+        let all_cores_load = busy_fraction(&load_agg);
+        self.avg.add_sample(all_cores_load as f64);
+
+        metrics.push(Metric::new(metric::CPU_LOAD, all_cores_load));
+        metrics.push(Metric::new(
+            metric::CPU_AVG,
+            self.avg.average().unwrap_or_default() as f32,
+        ));
+        metrics.push(Metric::new(metric::CPU_PEAK, busy_fraction(min_idle)));
+
+        Ok(())
+    }
+}
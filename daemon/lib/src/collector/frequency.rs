@@ -0,0 +1,198 @@
+use super::Collector;
+use crate::Error;
+use shared::metric::{self, Metric};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Location of per-core CPU information in sysfs.
+pub const CPU_ROOT: &str = "/sys/devices/system/cpu";
+
+/// Reports core frequencies relative to their maximum, and whether the CPU is being
+/// thermally throttled.
+pub struct FrequencyCollector {
+    root: PathBuf,
+    // Sum of throttle counters, as of the previous collection.
+    prev_throttles: Option<u64>,
+}
+
+/// Frequencies of a single core, in kHz.
+#[derive(Debug, PartialEq)]
+struct CoreFreq {
+    current: u64,
+    max: u64,
+}
+
+impl FrequencyCollector {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        FrequencyCollector {
+            root: root.into(),
+            prev_throttles: None,
+        }
+    }
+
+    // Returns the directories of each core, e.g. cpu0, cpu1.
+    fn cores(&self) -> Result<Vec<PathBuf>, Error> {
+        let mut cores: Vec<PathBuf> = std::fs::read_dir(&self.root)
+            .map_err(Error::Collector)?
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let name = entry.file_name();
+                let index = name.to_str()?.strip_prefix("cpu")?;
+                index.parse::<u32>().ok()?;
+                Some(entry.path())
+            })
+            .collect();
+        cores.sort();
+
+        Ok(cores)
+    }
+}
+
+impl Collector for FrequencyCollector {
+    fn collect(&mut self, metrics: &mut Vec<Metric>) -> Result<(), Error> {
+        let cores = self.cores()?;
+
+        // Offline cores have no cpufreq directory, and are skipped.
+        let freqs: Vec<CoreFreq> = cores.iter().filter_map(|core| read_freq(core)).collect();
+        if freqs.is_empty() {
+            return Err(Error::Collector(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no cpufreq information",
+            )));
+        }
+        metrics.extend(frequency_metrics(&freqs));
+
+        // Counters are only present on some Intel CPUs.
+        let throttles: u64 = cores.iter().map(|core| read_throttles(core)).sum();
+        let throttled = matches!(self.prev_throttles, Some(prev) if throttles > prev);
+        self.prev_throttles = Some(throttles);
+        metrics.push(Metric::new(
+            metric::CPU_THROTTLED,
+            if throttled { 1.0 } else { 0.0 },
+        ));
+
+        Ok(())
+    }
+}
+
+fn read_number(path: &Path) -> Option<u64> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+fn read_freq(core: &Path) -> Option<CoreFreq> {
+    let cpufreq = core.join("cpufreq");
+    Some(CoreFreq {
+        current: read_number(&cpufreq.join("scaling_cur_freq"))?,
+        max: read_number(&cpufreq.join("cpuinfo_max_freq"))?.max(1),
+    })
+}
+
+fn read_throttles(core: &Path) -> u64 {
+    let throttle = core.join("thermal_throttle");
+    ["core_throttle_count", "package_throttle_count"]
+        .iter()
+        .filter_map(|name| read_number(&throttle.join(name)))
+        .sum()
+}
+
+// Calculates average and fastest core frequencies.
+fn frequency_metrics(freqs: &[CoreFreq]) -> [Metric; 4] {
+    let count = freqs.len().max(1) as f32;
+    let avg_ratio = freqs
+        .iter()
+        .map(|f| f.current as f32 / f.max as f32)
+        .sum::<f32>()
+        / count;
+    let max_ratio = freqs
+        .iter()
+        .map(|f| f.current as f32 / f.max as f32)
+        .fold(0.0, f32::max);
+    let avg_khz = freqs.iter().map(|f| f.current as f32).sum::<f32>() / count;
+    let max_khz = freqs.iter().map(|f| f.current).max().unwrap_or_default() as f32;
+
+    [
+        Metric::new(metric::CPU_FREQ_AVG, avg_ratio.min(1.0)),
+        Metric::new(metric::CPU_FREQ_MAX, max_ratio.min(1.0)),
+        Metric::new(metric::CPU_MHZ_AVG, avg_khz / 1000.0),
+        Metric::new(metric::CPU_MHZ_MAX, max_khz / 1000.0),
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::TempPath;
+    use std::fs;
+
+    fn fake_core(root: &Path, core: u32, current: u64, max: u64) {
+        let cpufreq = root.join(format!("cpu{}", core)).join("cpufreq");
+        fs::create_dir_all(&cpufreq).unwrap();
+        fs::write(cpufreq.join("scaling_cur_freq"), format!("{}\n", current)).unwrap();
+        fs::write(cpufreq.join("cpuinfo_max_freq"), format!("{}\n", max)).unwrap();
+    }
+
+    fn fake_throttles(root: &Path, core: u32, count: u64) {
+        let throttle = root.join(format!("cpu{}", core)).join("thermal_throttle");
+        fs::create_dir_all(&throttle).unwrap();
+        fs::write(throttle.join("core_throttle_count"), format!("{}\n", count)).unwrap();
+    }
+
+    fn value(metrics: &[Metric], id: metric::MetricId) -> f32 {
+        metrics.iter().find(|m| m.id == id).unwrap().value
+    }
+
+    #[test]
+    fn frequency_metrics_average_and_max() {
+        let freqs = [
+            CoreFreq {
+                current: 1_000_000,
+                max: 4_000_000,
+            },
+            CoreFreq {
+                current: 3_000_000,
+                max: 4_000_000,
+            },
+        ];
+
+        let metrics = frequency_metrics(&freqs);
+
+        assert_eq!(value(&metrics, metric::CPU_FREQ_AVG), 0.5);
+        assert_eq!(value(&metrics, metric::CPU_FREQ_MAX), 0.75);
+        assert_eq!(value(&metrics, metric::CPU_MHZ_AVG), 2000.0);
+        assert_eq!(value(&metrics, metric::CPU_MHZ_MAX), 3000.0);
+    }
+
+    #[test]
+    fn collect_detects_throttling() {
+        let root = TempPath::new("freq");
+        fake_core(&root, 0, 2_000_000, 4_000_000);
+        fake_core(&root, 1, 4_000_000, 4_000_000);
+        fs::create_dir_all(root.join("cpufreq")).unwrap();
+        fake_throttles(&root, 0, 7);
+        let mut collector = FrequencyCollector::new(&root);
+
+        let mut first = Vec::new();
+        collector.collect(&mut first).unwrap();
+        let mut unchanged = Vec::new();
+        collector.collect(&mut unchanged).unwrap();
+        fake_throttles(&root, 0, 8);
+        let mut increased = Vec::new();
+        collector.collect(&mut increased).unwrap();
+
+        assert_eq!(value(&first, metric::CPU_FREQ_AVG), 0.75);
+        assert_eq!(value(&first, metric::CPU_THROTTLED), 0.0);
+        assert_eq!(value(&unchanged, metric::CPU_THROTTLED), 0.0);
+        assert_eq!(value(&increased, metric::CPU_THROTTLED), 1.0);
+    }
+
+    #[test]
+    fn collect_without_cpufreq() {
+        let root = TempPath::new("nofreq");
+        fs::create_dir_all(root.join("cpu0")).unwrap();
+        let mut collector = FrequencyCollector::new(&root);
+
+        let result = collector.collect(&mut Vec::new());
+
+        assert!(matches!(result, Err(Error::Collector(_))));
+    }
+}
@@ -0,0 +1,233 @@
+use super::Collector;
+use crate::Error;
+use serde::Deserialize;
+use shared::metric::{self, Metric};
+use std::io;
+
+/// Selects which memory counts as used.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UsedMemory {
+    // Everything the kernel could not make available without swapping.
+    #[default]
+    Available,
+    // Everything but free memory, including caches.
+    Free,
+    // Everything but free memory, buffers and page cache.
+    BuffersCache,
+}
+
+/// Memory statistics in bytes, as reported by the OS.
+#[derive(Debug, Default, PartialEq)]
+pub(super) struct MemInfo {
+    pub(super) total: u64,
+    pub(super) free: u64,
+    pub(super) available: u64,
+    // Buffers, page cache and reclaimable kernel slabs.
+    pub(super) cached: u64,
+    pub(super) swap_total: u64,
+    pub(super) swap_free: u64,
+}
+
+/// Reports memory and swap usage.
+pub struct MemoryCollector {
+    used: UsedMemory,
+}
+
+impl MemoryCollector {
+    pub fn new(used: UsedMemory) -> Self {
+        MemoryCollector { used }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn read(&self) -> Result<MemInfo, Error> {
+        let text = std::fs::read_to_string("/proc/meminfo").map_err(Error::Collector)?;
+        parse_meminfo(&text)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn read(&self) -> Result<MemInfo, Error> {
+        use systemstat::{Platform, System};
+
+        // Without a breakdown, cache is counted as used.
+        let mem = System::new().memory().map_err(Error::Collector)?;
+        Ok(MemInfo {
+            total: mem.total.as_u64(),
+            free: mem.free.as_u64(),
+            available: mem.free.as_u64(),
+            ..Default::default()
+        })
+    }
+}
+
+impl Collector for MemoryCollector {
+    fn collect(&mut self, metrics: &mut Vec<Metric>) -> Result<(), Error> {
+        let info = self.read()?;
+        metrics.extend(memory_metrics(&info, self.used));
+
+        Ok(())
+    }
+}
+
+// Calculates memory metrics, such that used, cache and free memory together make up the total.
+pub(super) fn memory_metrics(info: &MemInfo, used: UsedMemory) -> [Metric; 7] {
+    let used_bytes = match used {
+        UsedMemory::Available => info.total.saturating_sub(info.available),
+        UsedMemory::Free => info.total.saturating_sub(info.free),
+        UsedMemory::BuffersCache => info
+            .total
+            .saturating_sub(info.free)
+            .saturating_sub(info.cached),
+    };
+    let cache_bytes = info
+        .total
+        .saturating_sub(info.free)
+        .saturating_sub(used_bytes)
+        .min(info.cached);
+    let swap_used = info.swap_total.saturating_sub(info.swap_free);
+
+    let ratio = |bytes: u64, total: u64| match total {
+        0 => 0.0,
+        total => bytes as f32 / total as f32,
+    };
+
+    [
+        Metric::new(metric::MEM_LOAD, ratio(used_bytes, info.total)),
+        Metric::new(metric::MEM_CACHE, ratio(cache_bytes, info.total)),
+        Metric::new(metric::SWAP_LOAD, ratio(swap_used, info.swap_total)),
+        Metric::new(metric::MEM_USED, used_bytes as f32),
+        Metric::new(metric::MEM_AVAILABLE, info.available as f32),
+        Metric::new(metric::MEM_CACHED, info.cached as f32),
+        Metric::new(metric::SWAP_USED, swap_used as f32),
+    ]
+}
+
+// Parses the contents of /proc/meminfo.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_meminfo(text: &str) -> Result<MemInfo, Error> {
+    let field = |name: &str| -> Option<u64> {
+        text.lines().find_map(|line| {
+            let rest = line.strip_prefix(name)?.strip_prefix(':')?;
+            let kib: u64 = rest.trim().trim_end_matches("kB").trim().parse().ok()?;
+            Some(kib * 1024)
+        })
+    };
+
+    let total = field("MemTotal").ok_or_else(|| {
+        Error::Collector(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "MemTotal missing from meminfo",
+        ))
+    })?;
+    let free = field("MemFree").unwrap_or_default();
+    let cached = field("Buffers").unwrap_or_default()
+        + field("Cached").unwrap_or_default()
+        + field("SReclaimable").unwrap_or_default();
+
+    Ok(MemInfo {
+        total,
+        free,
+        // Kernels prior to 3.14 do not estimate available memory.
+        available: field("MemAvailable").unwrap_or(free + cached),
+        cached,
+        swap_total: field("SwapTotal").unwrap_or_default(),
+        swap_free: field("SwapFree").unwrap_or_default(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MEMINFO: &str = "\
+MemTotal:       16000000 kB
+MemFree:         4000000 kB
+MemAvailable:   10000000 kB
+Buffers:          500000 kB
+Cached:          6000000 kB
+SwapCached:        10000 kB
+SReclaimable:     500000 kB
+SwapTotal:       2000000 kB
+SwapFree:        1500000 kB
+";
+
+    fn value(metrics: &[Metric], id: metric::MetricId) -> f32 {
+        metrics.iter().find(|m| m.id == id).unwrap().value
+    }
+
+    #[test]
+    fn parse_meminfo_reads_fields() {
+        let actual = parse_meminfo(MEMINFO).unwrap();
+
+        assert_eq!(
+            actual,
+            MemInfo {
+                total: 16_000_000 * 1024,
+                free: 4_000_000 * 1024,
+                available: 10_000_000 * 1024,
+                cached: 7_000_000 * 1024,
+                swap_total: 2_000_000 * 1024,
+                swap_free: 1_500_000 * 1024,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_meminfo_requires_total() {
+        let result = parse_meminfo("MemFree: 1 kB\n");
+
+        assert!(matches!(result, Err(Error::Collector(_))));
+    }
+
+    #[test]
+    fn parse_meminfo_without_available() {
+        let actual = parse_meminfo("MemTotal: 100 kB\nMemFree: 10 kB\nCached: 20 kB\n").unwrap();
+
+        assert_eq!(actual.available, 30 * 1024);
+    }
+
+    #[test]
+    fn used_available() {
+        let info = parse_meminfo(MEMINFO).unwrap();
+
+        let metrics = memory_metrics(&info, UsedMemory::Available);
+
+        assert_eq!(value(&metrics, metric::MEM_LOAD), 6.0 / 16.0);
+        assert_eq!(value(&metrics, metric::MEM_CACHE), 6.0 / 16.0);
+        assert_eq!(value(&metrics, metric::SWAP_LOAD), 0.25);
+    }
+
+    #[test]
+    fn used_free() {
+        let info = parse_meminfo(MEMINFO).unwrap();
+
+        let metrics = memory_metrics(&info, UsedMemory::Free);
+
+        assert_eq!(value(&metrics, metric::MEM_LOAD), 12.0 / 16.0);
+        assert_eq!(value(&metrics, metric::MEM_CACHE), 0.0);
+    }
+
+    #[test]
+    fn used_buffers_cache() {
+        let info = parse_meminfo(MEMINFO).unwrap();
+
+        let metrics = memory_metrics(&info, UsedMemory::BuffersCache);
+
+        assert_eq!(value(&metrics, metric::MEM_LOAD), 5.0 / 16.0);
+        assert_eq!(value(&metrics, metric::MEM_CACHE), 7.0 / 16.0);
+    }
+
+    #[test]
+    fn no_swap() {
+        let info = MemInfo {
+            total: 100,
+            free: 100,
+            available: 100,
+            ..Default::default()
+        };
+
+        let metrics = memory_metrics(&info, UsedMemory::Available);
+
+        assert_eq!(value(&metrics, metric::SWAP_LOAD), 0.0);
+    }
+}
@@ -0,0 +1,109 @@
+use super::Collector;
+use crate::Error;
+use shared::metric::{self, Metric, MetricId};
+use std::path::PathBuf;
+
+/// Location of Linux pressure stall information.
+pub const PRESSURE_ROOT: &str = "/proc/pressure";
+
+// Resource files, with the metrics for their "some" and "full" lines.
+const RESOURCES: [(&str, MetricId, MetricId); 3] = [
+    ("cpu", metric::PSI_CPU_SOME, metric::PSI_CPU_FULL),
+    ("memory", metric::PSI_MEM_SOME, metric::PSI_MEM_FULL),
+    ("io", metric::PSI_IO_SOME, metric::PSI_IO_FULL),
+];
+
+/// Reports the 10 second average of CPU, memory and IO pressure.
+pub struct PressureCollector {
+    root: PathBuf,
+}
+
+impl PressureCollector {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        PressureCollector { root: root.into() }
+    }
+}
+
+impl Collector for PressureCollector {
+    fn collect(&mut self, metrics: &mut Vec<Metric>) -> Result<(), Error> {
+        for (file, some_id, full_id) in RESOURCES.iter() {
+            let text = std::fs::read_to_string(self.root.join(file)).map_err(Error::Collector)?;
+            if let Some(some) = parse_avg10(&text, "some") {
+                metrics.push(Metric::new(*some_id, some));
+            }
+            // Older kernels do not report full CPU pressure.
+            if let Some(full) = parse_avg10(&text, "full") {
+                metrics.push(Metric::new(*full_id, full));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Finds avg10 on the given line of a pressure file, e.g.
+// "some avg10=1.53 avg60=0.87 avg300=0.29 total=4402378", as a 0-1.0 ratio.
+fn parse_avg10(text: &str, kind: &str) -> Option<f32> {
+    let line = text
+        .lines()
+        .find(|line| line.split_whitespace().next() == Some(kind))?;
+    let percent: f32 = line
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("avg10="))?
+        .parse()
+        .ok()?;
+
+    Some(percent / 100.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::TempPath;
+    use std::fs;
+
+    const MEMORY: &str = "\
+some avg10=12.50 avg60=3.00 avg300=1.00 total=1000
+full avg10=5.00 avg60=1.00 avg300=0.50 total=500
+";
+
+    #[test]
+    fn parse_avg10_some_and_full() {
+        assert_eq!(parse_avg10(MEMORY, "some"), Some(0.125));
+        assert_eq!(parse_avg10(MEMORY, "full"), Some(0.05));
+    }
+
+    #[test]
+    fn parse_avg10_missing_line() {
+        let cpu = "some avg10=1.00 avg60=0.00 avg300=0.00 total=0\n";
+
+        assert_eq!(parse_avg10(cpu, "full"), None);
+    }
+
+    #[test]
+    fn collect_reads_each_resource() {
+        let root = TempPath::dir("psi");
+        fs::write(
+            root.join("cpu"),
+            "some avg10=50.00 avg60=0.00 avg300=0.00 total=0\n",
+        )
+        .unwrap();
+        fs::write(root.join("memory"), MEMORY).unwrap();
+        fs::write(root.join("io"), MEMORY).unwrap();
+        let mut collector = PressureCollector::new(&root);
+        let mut metrics = Vec::new();
+
+        collector.collect(&mut metrics).unwrap();
+
+        assert_eq!(
+            metrics,
+            vec![
+                Metric::new(metric::PSI_CPU_SOME, 0.5),
+                Metric::new(metric::PSI_MEM_SOME, 0.125),
+                Metric::new(metric::PSI_MEM_FULL, 0.05),
+                Metric::new(metric::PSI_IO_SOME, 0.125),
+                Metric::new(metric::PSI_IO_FULL, 0.05),
+            ]
+        );
+    }
+}
@@ -0,0 +1,435 @@
+use super::memory::{memory_metrics, MemInfo};
+use super::{Collector, UsedMemory};
+use crate::avg::Averager;
+use crate::stop::StopSignal;
+use crate::Error;
+use shared::metric::{self, Metric};
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const SCRAPE_PERIOD: Duration = Duration::from_secs(1);
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(2);
+const AVG_CPU_SAMPLES: usize = 15; // Seconds of data for CPU average.
+
+/// Reports CPU, memory, load and uptime of a remote host, scraped from the Prometheus
+/// endpoint of its node_exporter.  Replaces the local host collectors.
+pub struct ScrapeCollector {
+    url: String,
+    used: UsedMemory,
+    agent: ureq::Agent,
+    avg: Averager,
+    poll_period: Duration,
+    stop: Arc<StopSignal>,
+    // CPU counters, and when they were scraped.
+    prev: Option<(Vec<CpuTimes>, Instant)>,
+}
+
+/// A single sample from the Prometheus text format.
+#[derive(Debug, PartialEq)]
+struct Sample {
+    name: String,
+    labels: Vec<(String, String)>,
+    value: f64,
+}
+
+/// Cumulative seconds a single core has spent idle, and in total.
+#[derive(Debug, PartialEq)]
+struct CpuTimes {
+    cpu: String,
+    idle: f64,
+    total: f64,
+}
+
+/// Checks `url` can be scraped, returning a description of the problem if not.  TLS is not
+/// supported, to keep the daemon free of a TLS stack.
+pub fn validate_url(url: &str) -> Result<(), String> {
+    if url.starts_with("http://") {
+        Ok(())
+    } else {
+        Err(format!("scrape url {:?} must start with http://", url))
+    }
+}
+
+impl ScrapeCollector {
+    /// Creates a collector scraping `url`, whose waits between scrapes are cut short by
+    /// `stop`.
+    pub fn new(url: &str, used: UsedMemory, stop: Arc<StopSignal>) -> Self {
+        ScrapeCollector {
+            url: url.to_string(),
+            used,
+            agent: ureq::AgentBuilder::new().timeout(SCRAPE_TIMEOUT).build(),
+            avg: Averager::new(AVG_CPU_SAMPLES),
+            poll_period: SCRAPE_PERIOD,
+            stop,
+            prev: None,
+        }
+    }
+
+    fn scrape(&self) -> Result<Vec<Sample>, Error> {
+        let text = self
+            .agent
+            .get(&self.url)
+            .call()
+            .map_err(|err| Error::Collector(io::Error::other(err)))?
+            .into_string()
+            .map_err(Error::Collector)?;
+
+        Ok(parse_samples(&text))
+    }
+
+    // Scrapes once the poll period has passed since the previous scrape, blocking until then.
+    fn scrape_next(&mut self) -> Result<Vec<Sample>, Error> {
+        if self.prev.is_none() {
+            let samples = self.scrape()?;
+            self.prev = Some((cpu_times(&samples), Instant::now()));
+        }
+        if let Some((_, scraped)) = &self.prev {
+            self.stop
+                .wait(self.poll_period.saturating_sub(scraped.elapsed()));
+        }
+
+        self.scrape()
+    }
+}
+
+impl Collector for ScrapeCollector {
+    /// Scrapes the remote host, blocking for the poll period or until stopped.  Failures are
+    /// reported to the device as NaN values, as the remote host may recover.
+    fn collect(&mut self, metrics: &mut Vec<Metric>) -> Result<(), Error> {
+        let samples = match self.scrape_next() {
+            Ok(samples) => samples,
+            Err(err) => {
+                log::warn!("Failed to scrape {}: {}", self.url, err);
+                if self.prev.take().is_none() {
+                    // Nothing was slept, so wait out the period before trying again.
+                    self.stop.wait(self.poll_period);
+                }
+                for id in [metric::CPU_LOAD, metric::CPU_AVG, metric::MEM_LOAD].iter() {
+                    metrics.push(Metric::new(*id, f32::NAN));
+                }
+                return Ok(());
+            }
+        };
+
+        let times = cpu_times(&samples);
+        if let Some((prev, _)) = &self.prev {
+            if let Some((all_cores, peak)) = cpu_load(prev, &times) {
+                self.avg.add_sample(all_cores as f64);
+                metrics.push(Metric::new(metric::CPU_LOAD, all_cores));
+                metrics.push(Metric::new(
+                    metric::CPU_AVG,
+                    self.avg.average().unwrap_or_default() as f32,
+                ));
+                metrics.push(Metric::new(metric::CPU_PEAK, peak));
+            }
+        }
+
+        if let Some(info) = mem_info(&samples) {
+            metrics.extend(memory_metrics(&info, self.used));
+        }
+
+        // Load averages are relative to the number of cores, as for the local host.
+        let cores = times.len().max(1) as f64;
+        let load = [
+            ("node_load1", metric::LOAD_1),
+            ("node_load5", metric::LOAD_5),
+            ("node_load15", metric::LOAD_15),
+        ];
+        for (name, id) in load.iter() {
+            if let Some(value) = find(&samples, name) {
+                metrics.push(Metric::new(*id, (value / cores) as f32));
+            }
+        }
+        if let (Some(now), Some(boot)) = (
+            find(&samples, "node_time_seconds"),
+            find(&samples, "node_boot_time_seconds"),
+        ) {
+            metrics.push(Metric::new(metric::UPTIME, (now - boot).max(0.0) as f32));
+        }
+
+        self.prev = Some((times, Instant::now()));
+        Ok(())
+    }
+}
+
+impl Sample {
+    fn label(&self, key: &str) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+// Returns the value of an unlabeled sample.
+fn find(samples: &[Sample], name: &str) -> Option<f64> {
+    samples.iter().find(|s| s.name == name).map(|s| s.value)
+}
+
+// Parses node_exporter samples from the Prometheus text format, e.g.
+// `node_cpu_seconds_total{cpu="0",mode="idle"} 1234.5`.  Other series and comments are
+// skipped, as are lines that do not parse.
+fn parse_samples(text: &str) -> Vec<Sample> {
+    text.lines()
+        .filter(|line| line.starts_with("node_"))
+        .filter_map(parse_line)
+        .collect()
+}
+
+fn parse_line(line: &str) -> Option<Sample> {
+    let name_end = line.find(|c: char| c == '{' || c.is_whitespace())?;
+    let name = line[..name_end].to_string();
+    let mut rest = &line[name_end..];
+
+    let mut labels = Vec::new();
+    if let Some(body) = rest.strip_prefix('{') {
+        let mut chars = body.char_indices();
+        let mut key = String::new();
+        loop {
+            let (i, c) = chars.next()?;
+            match c {
+                '}' => {
+                    rest = &body[i + 1..];
+                    break;
+                }
+                ',' | ' ' => {}
+                '=' => {
+                    // Quoted value, with backslash escapes.
+                    if chars.next()?.1 != '"' {
+                        return None;
+                    }
+                    let mut value = String::new();
+                    loop {
+                        match chars.next()?.1 {
+                            '"' => break,
+                            '\\' => match chars.next()?.1 {
+                                'n' => value.push('\n'),
+                                c => value.push(c),
+                            },
+                            c => value.push(c),
+                        }
+                    }
+                    labels.push((std::mem::take(&mut key), value));
+                }
+                c => key.push(c),
+            }
+        }
+    }
+
+    // An optional timestamp may follow the value.
+    let value = rest.split_whitespace().next()?.parse().ok()?;
+    Some(Sample {
+        name,
+        labels,
+        value,
+    })
+}
+
+// Totals idle and overall CPU seconds for each core.  IO wait is counted as idle.
+fn cpu_times(samples: &[Sample]) -> Vec<CpuTimes> {
+    let mut times: Vec<CpuTimes> = Vec::new();
+    for sample in samples
+        .iter()
+        .filter(|s| s.name == "node_cpu_seconds_total")
+    {
+        let (Some(cpu), Some(mode)) = (sample.label("cpu"), sample.label("mode")) else {
+            continue;
+        };
+        let entry = match times.iter().position(|t| t.cpu == cpu) {
+            Some(i) => &mut times[i],
+            None => {
+                times.push(CpuTimes {
+                    cpu: cpu.to_string(),
+                    idle: 0.0,
+                    total: 0.0,
+                });
+                times.last_mut().unwrap()
+            }
+        };
+        if mode == "idle" || mode == "iowait" {
+            entry.idle += sample.value;
+        }
+        entry.total += sample.value;
+    }
+
+    times
+}
+
+// Calculates the load of all cores together, and of the busiest core, between two scrapes.
+// Returns None if no time has passed.
+fn cpu_load(prev: &[CpuTimes], current: &[CpuTimes]) -> Option<(f32, f32)> {
+    let busy = |idle: f64, total: f64| {
+        if total > 0.0 {
+            Some((1.0 - idle / total).clamp(0.0, 1.0) as f32)
+        } else {
+            None
+        }
+    };
+
+    let (mut idle_sum, mut total_sum, mut peak) = (0.0, 0.0, 0.0f32);
+    for core in current {
+        let Some(before) = prev.iter().find(|p| p.cpu == core.cpu) else {
+            continue;
+        };
+        let (idle, total) = (core.idle - before.idle, core.total - before.total);
+        idle_sum += idle;
+        total_sum += total;
+        if let Some(load) = busy(idle, total) {
+            peak = peak.max(load);
+        }
+    }
+
+    Some((busy(idle_sum, total_sum)?, peak))
+}
+
+// Reads memory statistics from node_memory series, which mirror /proc/meminfo.
+fn mem_info(samples: &[Sample]) -> Option<MemInfo> {
+    let field = |name: &str| find(samples, &format!("node_memory_{}_bytes", name));
+
+    let total = field("MemTotal")?;
+    let free = field("MemFree").unwrap_or_default();
+    let cached = field("Buffers").unwrap_or_default()
+        + field("Cached").unwrap_or_default()
+        + field("SReclaimable").unwrap_or_default();
+    Some(MemInfo {
+        total: total as u64,
+        free: free as u64,
+        available: field("MemAvailable").unwrap_or(free + cached) as u64,
+        cached: cached as u64,
+        swap_total: field("SwapTotal").unwrap_or_default() as u64,
+        swap_free: field("SwapFree").unwrap_or_default() as u64,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    const FIRST: &str = "\
+# HELP node_cpu_seconds_total Seconds the CPUs spent in each mode.
+# TYPE node_cpu_seconds_total counter
+node_cpu_seconds_total{cpu=\"0\",mode=\"idle\"} 100
+node_cpu_seconds_total{cpu=\"0\",mode=\"user\"} 100
+node_cpu_seconds_total{cpu=\"1\",mode=\"idle\"} 100
+node_cpu_seconds_total{cpu=\"1\",mode=\"user\"} 100
+node_memory_MemTotal_bytes 1000
+node_memory_MemFree_bytes 200
+node_memory_MemAvailable_bytes 600
+node_load1 1
+node_time_seconds 5000
+node_boot_time_seconds 1000
+go_goroutines 8
+";
+
+    // Core 0 was busy for 1 of 2 seconds, and core 1 for all 2 seconds.
+    const SECOND: &str = "\
+node_cpu_seconds_total{cpu=\"0\",mode=\"idle\"} 101
+node_cpu_seconds_total{cpu=\"0\",mode=\"user\"} 101
+node_cpu_seconds_total{cpu=\"1\",mode=\"idle\"} 100
+node_cpu_seconds_total{cpu=\"1\",mode=\"user\"} 102
+node_memory_MemTotal_bytes 1000
+node_memory_MemFree_bytes 200
+node_memory_MemAvailable_bytes 600
+node_load1 1
+node_time_seconds 5001
+node_boot_time_seconds 1000
+";
+
+    // Serves each body in turn to successive requests, on a local port.
+    fn stub_server(bodies: Vec<&'static str>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/metrics", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for body in bodies {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        url
+    }
+
+    fn value(metrics: &[Metric], id: metric::MetricId) -> f32 {
+        metrics.iter().find(|m| m.id == id).unwrap().value
+    }
+
+    #[test]
+    fn parse_line_with_labels() {
+        let sample =
+            parse_line(r#"node_filesystem_avail_bytes{mountpoint="/",label="a\"b"} 1.5e3 1700"#)
+                .unwrap();
+
+        assert_eq!(sample.name, "node_filesystem_avail_bytes");
+        assert_eq!(sample.label("mountpoint"), Some("/"));
+        assert_eq!(sample.label("label"), Some("a\"b"));
+        assert_eq!(sample.value, 1500.0);
+    }
+
+    #[test]
+    fn parse_samples_skips_others() {
+        let samples = parse_samples(FIRST);
+
+        assert_eq!(samples.len(), 10);
+        assert_eq!(find(&samples, "node_load1"), Some(1.0));
+    }
+
+    #[test]
+    fn cpu_load_between_scrapes() {
+        let prev = cpu_times(&parse_samples(FIRST));
+        let current = cpu_times(&parse_samples(SECOND));
+
+        assert_eq!(cpu_load(&prev, &current), Some((0.75, 1.0)));
+        assert_eq!(cpu_load(&current, &current), None);
+    }
+
+    #[test]
+    fn collect_from_stub_server() {
+        let url = stub_server(vec![FIRST, SECOND]);
+        let mut collector = ScrapeCollector::new(&url, UsedMemory::Available, Arc::default());
+        collector.poll_period = Duration::ZERO;
+        let mut metrics = Vec::new();
+
+        collector.collect(&mut metrics).unwrap();
+
+        assert_eq!(value(&metrics, metric::CPU_LOAD), 0.75);
+        assert_eq!(value(&metrics, metric::CPU_PEAK), 1.0);
+        assert_eq!(value(&metrics, metric::MEM_LOAD), 0.4);
+        assert_eq!(value(&metrics, metric::LOAD_1), 0.5);
+        assert_eq!(value(&metrics, metric::UPTIME), 4001.0);
+    }
+
+    #[test]
+    fn unreachable_host_is_nan() {
+        // Bind and drop a listener to find a port that refuses connections.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut collector = ScrapeCollector::new(
+            &format!("http://127.0.0.1:{}/metrics", port),
+            UsedMemory::Available,
+            Arc::default(),
+        );
+        collector.poll_period = Duration::ZERO;
+        let mut metrics = Vec::new();
+
+        collector.collect(&mut metrics).unwrap();
+
+        assert!(value(&metrics, metric::CPU_LOAD).is_nan());
+    }
+}
@@ -0,0 +1,134 @@
+use super::Collector;
+use crate::Error;
+use shared::metric::{self, Metric};
+use std::io;
+
+/// Reports load averages, process counts and uptime.
+pub struct SystemCollector {
+    cores: usize,
+}
+
+impl SystemCollector {
+    pub fn new() -> Self {
+        SystemCollector {
+            cores: std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn read(&self, metrics: &mut Vec<Metric>) -> Result<(), Error> {
+        let loadavg = std::fs::read_to_string("/proc/loadavg").map_err(Error::Collector)?;
+        metrics.extend(parse_loadavg(&loadavg, self.cores)?);
+        let uptime = std::fs::read_to_string("/proc/uptime").map_err(Error::Collector)?;
+        metrics.push(parse_uptime(&uptime)?);
+
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn read(&self, metrics: &mut Vec<Metric>) -> Result<(), Error> {
+        use systemstat::{Platform, System};
+
+        // Load averages and process counts are not available on all platforms.
+        let sys = System::new();
+        if let Ok(load) = sys.load_average() {
+            let cores = self.cores as f32;
+            metrics.push(Metric::new(metric::LOAD_1, load.one / cores));
+            metrics.push(Metric::new(metric::LOAD_5, load.five / cores));
+            metrics.push(Metric::new(metric::LOAD_15, load.fifteen / cores));
+        }
+        let uptime = sys.uptime().map_err(Error::Collector)?;
+        metrics.push(Metric::new(metric::UPTIME, uptime.as_secs() as f32));
+
+        Ok(())
+    }
+}
+
+impl Default for SystemCollector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Collector for SystemCollector {
+    fn collect(&mut self, metrics: &mut Vec<Metric>) -> Result<(), Error> {
+        self.read(metrics)
+    }
+}
+
+fn invalid(file: &str) -> Error {
+    Error::Collector(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected {} format", file),
+    ))
+}
+
+// Parses /proc/loadavg, e.g. "0.50 0.40 0.30 2/1234 5678".
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_loadavg(text: &str, cores: usize) -> Result<[Metric; 5], Error> {
+    let mut fields = text.split_whitespace();
+    let mut load = || -> Result<f32, Error> {
+        let value: f32 = fields
+            .next()
+            .and_then(|f| f.parse().ok())
+            .ok_or_else(|| invalid("loadavg"))?;
+        Ok(value / cores.max(1) as f32)
+    };
+    let (one, five, fifteen) = (load()?, load()?, load()?);
+
+    let (running, total) = fields
+        .next()
+        .and_then(|f| f.split_once('/'))
+        .and_then(|(r, t)| Some((r.parse::<u32>().ok()?, t.parse::<u32>().ok()?)))
+        .ok_or_else(|| invalid("loadavg"))?;
+
+    Ok([
+        Metric::new(metric::LOAD_1, one),
+        Metric::new(metric::LOAD_5, five),
+        Metric::new(metric::LOAD_15, fifteen),
+        Metric::new(metric::PROCS_RUNNING, running as f32),
+        Metric::new(metric::PROCS_TOTAL, total as f32),
+    ])
+}
+
+// Parses /proc/uptime, e.g. "350735.47 234388.90".
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_uptime(text: &str) -> Result<Metric, Error> {
+    let secs: f32 = text
+        .split_whitespace()
+        .next()
+        .and_then(|f| f.parse().ok())
+        .ok_or_else(|| invalid("uptime"))?;
+
+    Ok(Metric::new(metric::UPTIME, secs))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_loadavg_normalizes_by_cores() {
+        let actual = parse_loadavg("2.00 1.00 0.50 3/412 5678\n", 4).unwrap();
+
+        let values: Vec<f32> = actual.iter().map(|m| m.value).collect();
+        assert_eq!(values, vec![0.5, 0.25, 0.125, 3.0, 412.0]);
+        assert_eq!(actual[3].id, metric::PROCS_RUNNING);
+    }
+
+    #[test]
+    fn parse_loadavg_rejects_garbage() {
+        let result = parse_loadavg("0.50 0.40\n", 1);
+
+        assert!(matches!(result, Err(Error::Collector(_))));
+    }
+
+    #[test]
+    fn parse_uptime_reads_seconds() {
+        let actual = parse_uptime("350735.47 234388.90\n").unwrap();
+
+        assert_eq!(actual, Metric::new(metric::UPTIME, 350735.47));
+    }
+}
@@ -0,0 +1,326 @@
+use super::Collector;
+use crate::Error;
+use shared::metric::{self, Metric, MetricLabel, Unit, TOP_COUNT};
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+
+/// Reports the processes using the most CPU and memory, from `/proc`.
+pub struct TopCollector {
+    proc_root: PathBuf,
+    // CPU ticks used by each process, as of the previous collection.
+    prev_ticks: HashMap<u32, u64>,
+    // Total CPU ticks of all cores, as of the previous collection.
+    prev_total: Option<u64>,
+}
+
+/// A single process, as read from `/proc/<pid>/stat` and `/proc/<pid>/status`.
+#[derive(Debug, PartialEq)]
+struct ProcStat {
+    pid: u32,
+    name: String,
+    // User and system CPU ticks.
+    ticks: u64,
+    rss_bytes: u64,
+}
+
+impl TopCollector {
+    /// Creates a collector reading from `proc_root`, normally `/proc`.
+    pub fn new(proc_root: impl Into<PathBuf>) -> Self {
+        TopCollector {
+            proc_root: proc_root.into(),
+            prev_ticks: HashMap::new(),
+            prev_total: None,
+        }
+    }
+
+    fn read_processes(&self) -> Vec<ProcStat> {
+        let entries = match std::fs::read_dir(&self.proc_root) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+
+        // Processes may exit while being read, so failures are skipped.
+        entries
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let pid: u32 = entry.file_name().to_str()?.parse().ok()?;
+                let text = std::fs::read_to_string(entry.path().join("stat")).ok()?;
+                let mut stat = parse_stat(pid, &text)?;
+                // Resident memory is read in kB, as stat reports pages of an unknown size.
+                // Kernel threads have none.
+                stat.rss_bytes = std::fs::read_to_string(entry.path().join("status"))
+                    .ok()
+                    .and_then(|text| parse_vm_rss(&text))
+                    .unwrap_or(0);
+                Some(stat)
+            })
+            .collect()
+    }
+}
+
+impl Collector for TopCollector {
+    fn collect(&mut self, metrics: &mut Vec<Metric>) -> Result<(), Error> {
+        let read = |name: &str| {
+            std::fs::read_to_string(self.proc_root.join(name)).map_err(Error::Collector)
+        };
+        let total = parse_total_ticks(&read("stat")?)?;
+        let mem_total = parse_mem_total(&read("meminfo")?)?;
+        let procs = self.read_processes();
+
+        // CPU usage requires a previous sample to compare against.
+        if let Some(prev_total) = self.prev_total {
+            let elapsed = total.saturating_sub(prev_total).max(1) as f32;
+            let mut usage: Vec<(&str, f32)> = procs
+                .iter()
+                .filter_map(|p| {
+                    let prev = self.prev_ticks.get(&p.pid)?;
+                    Some((
+                        p.name.as_str(),
+                        p.ticks.saturating_sub(*prev) as f32 / elapsed,
+                    ))
+                })
+                .collect();
+            usage.sort_by(|a, b| b.1.total_cmp(&a.1));
+            push_ranked(metrics, metric::top_cpu, &usage);
+        }
+
+        let mut memory: Vec<(&str, f32)> = procs
+            .iter()
+            .map(|p| (p.name.as_str(), p.rss_bytes as f32 / mem_total as f32))
+            .collect();
+        memory.sort_by(|a, b| b.1.total_cmp(&a.1));
+        push_ranked(metrics, metric::top_mem, &memory);
+
+        self.prev_total = Some(total);
+        self.prev_ticks = procs.iter().map(|p| (p.pid, p.ticks)).collect();
+
+        Ok(())
+    }
+}
+
+// Appends the first TOP_COUNT entries as labelled metrics.
+fn push_ranked(
+    metrics: &mut Vec<Metric>,
+    id: fn(usize) -> metric::MetricId,
+    ranked: &[(&str, f32)],
+) {
+    for (rank, (name, value)) in ranked.iter().take(TOP_COUNT).enumerate() {
+        let mut metric = Metric::new(id(rank), *value);
+        metric.unit = Unit::Ratio;
+        metric.label = Some(truncate(name));
+        metrics.push(metric);
+    }
+}
+
+// Truncates a process name to fit a metric label, respecting character boundaries.
+fn truncate(name: &str) -> MetricLabel {
+    let mut label = MetricLabel::new();
+    for c in name.chars() {
+        if label.push(c).is_err() {
+            break;
+        }
+    }
+
+    label
+}
+
+fn invalid(file: &str) -> Error {
+    Error::Collector(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected {} format", file),
+    ))
+}
+
+// Parses `/proc/<pid>/stat`, leaving resident memory to be read from status.  The name is in
+// parentheses and may itself contain spaces or parentheses, so fields are counted from the
+// last closing parenthesis.
+fn parse_stat(pid: u32, text: &str) -> Option<ProcStat> {
+    let open = text.find('(')?;
+    let close = text.rfind(')')?;
+    let name = text.get(open + 1..close)?;
+
+    // Fields following the name, starting with state (field 3 in proc(5)).
+    let fields: Vec<&str> = text[close + 1..].split_whitespace().collect();
+    let field = |n: usize| -> Option<u64> { fields.get(n - 3)?.parse().ok() };
+
+    Some(ProcStat {
+        pid,
+        name: name.to_string(),
+        ticks: field(14)? + field(15)?,
+        rss_bytes: 0,
+    })
+}
+
+// Reads VmRSS from `/proc/<pid>/status`, in bytes.
+fn parse_vm_rss(text: &str) -> Option<u64> {
+    text.lines().find_map(|line| {
+        let kib = line.strip_prefix("VmRSS:")?;
+        let kib: u64 = kib.trim().trim_end_matches("kB").trim().parse().ok()?;
+        Some(kib * 1024)
+    })
+}
+
+// Sums the aggregate CPU line of `/proc/stat`, giving ticks elapsed across all cores.
+fn parse_total_ticks(text: &str) -> Result<u64, Error> {
+    let line = text
+        .lines()
+        .find(|line| line.starts_with("cpu "))
+        .ok_or_else(|| invalid("stat"))?;
+
+    Ok(line
+        .split_whitespace()
+        .skip(1)
+        .filter_map(|f| f.parse::<u64>().ok())
+        .sum())
+}
+
+// Reads MemTotal from `/proc/meminfo`, in bytes.
+fn parse_mem_total(text: &str) -> Result<u64, Error> {
+    text.lines()
+        .find_map(|line| {
+            let kib = line.strip_prefix("MemTotal:")?;
+            kib.trim().trim_end_matches("kB").trim().parse::<u64>().ok()
+        })
+        .filter(|kib| *kib > 0)
+        .map(|kib| kib * 1024)
+        .ok_or_else(|| invalid("meminfo"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::TempPath;
+    use std::fs;
+    use std::path::Path;
+
+    // Builds a fake /proc tree in a temporary directory, removed when dropped.
+    struct FakeProc {
+        root: TempPath,
+    }
+
+    impl FakeProc {
+        fn new(name: &str) -> Self {
+            let root = TempPath::dir(&format!("top-{}", name));
+            fs::write(root.join("meminfo"), "MemTotal: 1000 kB\n").unwrap();
+            FakeProc { root }
+        }
+
+        fn total_ticks(&self, ticks: u64) {
+            let text = format!("cpu  {} 0 0 0\ncpu0 {} 0 0 0\n", ticks, ticks);
+            fs::write(self.root.join("stat"), text).unwrap();
+        }
+
+        fn process(&self, pid: u32, name: &str, ticks: u64, rss_kib: u64) {
+            let dir = self.root.join(pid.to_string());
+            fs::create_dir_all(&dir).unwrap();
+            // The RSS field in stat is in pages, and ignored.
+            let text = format!(
+                "{} ({}) S 1 1 1 0 -1 0 0 0 0 0 {} 0 0 0 20 0 1 0 100 1000 99 0\n",
+                pid, name, ticks
+            );
+            fs::write(dir.join("stat"), text).unwrap();
+            let status = format!("Name:\t{}\nVmRSS:\t{} kB\n", name, rss_kib);
+            fs::write(dir.join("status"), status).unwrap();
+        }
+
+        fn path(&self) -> &Path {
+            &self.root
+        }
+    }
+
+    fn find(metrics: &[Metric], id: metric::MetricId) -> Option<(&str, f32)> {
+        metrics
+            .iter()
+            .find(|m| m.id == id)
+            .map(|m| (m.label.as_deref().unwrap_or_default(), m.value))
+    }
+
+    #[test]
+    fn parse_stat_handles_odd_names() {
+        let text = "42 (a (b) c) R 1 1 1 0 -1 0 0 0 0 0 7 3 0 0 20 0 1 0 100 1000 5 0";
+
+        let actual = parse_stat(42, text).unwrap();
+
+        assert_eq!(
+            actual,
+            ProcStat {
+                pid: 42,
+                name: "a (b) c".to_string(),
+                ticks: 10,
+                rss_bytes: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_vm_rss_in_bytes() {
+        let text = "Name:\tfirefox\nVmPeak:\t 9000 kB\nVmRSS:\t  2048 kB\n";
+
+        assert_eq!(parse_vm_rss(text), Some(2048 * 1024));
+        assert_eq!(parse_vm_rss("Name:\tkthreadd\n"), None);
+    }
+
+    #[test]
+    fn parse_stat_rejects_truncated() {
+        assert_eq!(parse_stat(1, "1 (init) S 0"), None);
+    }
+
+    #[test]
+    fn truncate_respects_char_boundaries() {
+        assert_eq!(truncate("kworker/0:1").as_str(), "kworker/");
+        assert_eq!(truncate("ééééé").as_str(), "éééé");
+    }
+
+    #[test]
+    fn first_collection_reports_memory_only() {
+        let proc = FakeProc::new("first");
+        proc.total_ticks(100);
+        proc.process(1, "init", 10, 10);
+        let mut collector = TopCollector::new(proc.path());
+        let mut metrics = Vec::new();
+
+        collector.collect(&mut metrics).unwrap();
+
+        assert_eq!(find(&metrics, metric::top_cpu(0)), None);
+        assert_eq!(
+            find(&metrics, metric::top_mem(0)),
+            Some(("init", 10.0 * 1024.0 / 1_024_000.0))
+        );
+    }
+
+    #[test]
+    fn ranks_by_cpu_delta() {
+        let proc = FakeProc::new("delta");
+        proc.total_ticks(1000);
+        proc.process(1, "idle", 500, 1);
+        proc.process(2, "busy", 10, 2);
+        let mut collector = TopCollector::new(proc.path());
+        collector.collect(&mut Vec::new()).unwrap();
+
+        proc.total_ticks(1100);
+        proc.process(1, "idle", 505, 1);
+        proc.process(2, "busy", 60, 2);
+        let mut metrics = Vec::new();
+        collector.collect(&mut metrics).unwrap();
+
+        assert_eq!(find(&metrics, metric::top_cpu(0)), Some(("busy", 0.5)));
+        assert_eq!(find(&metrics, metric::top_cpu(1)), Some(("idle", 0.05)));
+        assert_eq!(find(&metrics, metric::top_mem(0)).unwrap().0, "busy");
+    }
+
+    #[test]
+    fn limits_to_top_count() {
+        let proc = FakeProc::new("count");
+        proc.total_ticks(100);
+        for pid in 1..=TOP_COUNT as u32 + 2 {
+            proc.process(pid, "worker", 0, pid as u64);
+        }
+        let mut collector = TopCollector::new(proc.path());
+        let mut metrics = Vec::new();
+
+        collector.collect(&mut metrics).unwrap();
+
+        assert_eq!(metrics.len(), TOP_COUNT);
+    }
+}
@@ -0,0 +1,751 @@
+use crate::alert::AlertConfig;
+use crate::collector::{self, CommandConfig, UsedMemory};
+use crate::hosts::HostsConfig;
+use crate::Error;
+use serde::{de, Deserialize, Deserializer};
+use shared::layout::{self, Align, Format, Layout, Widget};
+use shared::metric::{self, MetricId};
+use shared::settings::{ColorMode, Page, Settings};
+use shared::theme::{Theme, ThemeName, Threshold};
+use std::path::{Path, PathBuf};
+
+/// Daemon configuration, loaded from a TOML file.  All values are optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Device settings to apply upon connection.
+    pub device: DeviceConfig,
+    /// Color theme to apply upon connection.
+    pub theme: ThemeConfig,
+    /// Memory collector options.
+    pub memory: MemoryConfig,
+    /// Cgroup to report on, in place of the whole system.
+    pub cgroup: CgroupConfig,
+    /// Top processes page options.
+    pub top: TopConfig,
+    /// Remote host to report on, in place of the local host.
+    #[serde(deserialize_with = "deserialize_scrape")]
+    pub scrape: ScrapeConfig,
+    /// External commands reporting custom metrics.
+    pub command: Vec<CommandConfig>,
+    /// Local socket for other programs to push metrics and notifications.
+    pub api: ApiConfig,
+    /// Prometheus endpoint serving collected metrics.
+    pub exporter: ExporterConfig,
+    /// TCP relay between collectors and a host with the device attached.
+    #[serde(deserialize_with = "deserialize_relay")]
+    pub relay: RelayConfig,
+    /// Hosts shown on the device's hosts page.
+    #[serde(deserialize_with = "deserialize_hosts")]
+    pub hosts: HostsConfig,
+    /// Rules putting metrics into an alert state on the device.
+    #[serde(deserialize_with = "deserialize_alerts")]
+    pub alert: Vec<AlertConfig>,
+    /// Widgets to display on the performance page, replacing the default layout.
+    #[serde(deserialize_with = "deserialize_layout")]
+    pub layout: Option<Layout>,
+}
+
+/// Persistent device settings; only the values present are changed on the device.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceConfig {
+    /// Backlight brightness in percent.
+    pub brightness: Option<u8>,
+    /// Seconds without data before the screen is blanked, 0 to never blank.
+    pub blank_timeout: Option<u16>,
+    /// Shows a clock until the screen is blanked.
+    pub idle_clock: Option<bool>,
+    /// Page displayed at power on.
+    pub default_page: Option<Page>,
+    /// Day/night color selection.
+    pub color_mode: Option<ColorMode>,
+}
+
+/// Selects a built-in theme, optionally overriding its threshold levels.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeConfig {
+    /// Built-in theme name.
+    pub name: Option<ThemeName>,
+    /// Percent load where bars change to the warning color.
+    pub warn: Option<u8>,
+    /// Percent load where bars change to the critical color.
+    pub crit: Option<u8>,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MemoryConfig {
+    /// Definition of used memory, for the memory load metric.
+    pub used: UsedMemory,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CgroupConfig {
+    /// Path of a cgroup v2, relative to /sys/fs/cgroup unless absolute.
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TopConfig {
+    /// Reports the top processes even when the top page is not the default, for switching
+    /// to it through the API.
+    pub enabled: bool,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScrapeConfig {
+    /// Prometheus endpoint of a node_exporter, e.g. "http://host:9100/metrics".
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    /// Path of a Unix socket to create, accepting JSON lines requests.
+    pub socket: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExporterConfig {
+    /// Address to serve `/metrics` on, e.g. "127.0.0.1:9187".
+    pub listen: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RelayConfig {
+    /// Address to accept collectors on, relaying their frames to the local device.
+    pub listen: Option<String>,
+    /// Address of a relay to send frames to, in place of a local device.
+    pub connect: Option<String>,
+    /// Secret shared by collectors and the relay.
+    pub secret: String,
+}
+
+/// Layout widget, as described in the config file.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+enum WidgetConfig {
+    Label {
+        row: u8,
+        #[serde(default = "default_align")]
+        align: Align,
+        text: String,
+    },
+    Value {
+        row: u8,
+        #[serde(default = "default_align")]
+        align: Align,
+        metric: String,
+        #[serde(default = "default_format")]
+        format: Format,
+        #[serde(default)]
+        suffix: String,
+    },
+    Bar {
+        row: u8,
+        metric: String,
+    },
+    DoubleBar {
+        row: u8,
+        left: String,
+        right: String,
+    },
+    Sparkline {
+        row: u8,
+        metric: String,
+    },
+    Memory {
+        row: u8,
+        used: String,
+        cache: String,
+        swap: String,
+    },
+    Status {
+        row: u8,
+    },
+    Pressure {
+        row: u8,
+    },
+    Frequency {
+        row: u8,
+    },
+    Battery {
+        row: u8,
+    },
+}
+
+fn default_align() -> Align {
+    Align::Left
+}
+
+fn default_format() -> Format {
+    Format::Percent
+}
+
+impl WidgetConfig {
+    fn to_widget(&self) -> Result<Widget, String> {
+        fn metric(name: &str) -> Result<MetricId, String> {
+            metric::by_name(name).ok_or_else(|| format!("unknown metric {:?}", name))
+        }
+
+        fn text<const N: usize>(text: &str) -> Result<heapless::String<N>, String> {
+            let mut result = heapless::String::new();
+            result
+                .push_str(text)
+                .map_err(|_| format!("{:?} is longer than {} characters", text, N))?;
+            Ok(result)
+        }
+
+        let widget = match self {
+            WidgetConfig::Label {
+                row,
+                align,
+                text: t,
+            } => Widget::Label {
+                row: *row,
+                align: *align,
+                text: text(t)?,
+            },
+            WidgetConfig::Value {
+                row,
+                align,
+                metric: m,
+                format,
+                suffix,
+            } => Widget::Value {
+                row: *row,
+                align: *align,
+                metric: metric(m)?,
+                format: *format,
+                suffix: text(suffix)?,
+            },
+            WidgetConfig::Bar { row, metric: m } => Widget::Bar {
+                row: *row,
+                metric: metric(m)?,
+            },
+            WidgetConfig::DoubleBar { row, left, right } => Widget::DoubleBar {
+                row: *row,
+                left: metric(left)?,
+                right: metric(right)?,
+            },
+            WidgetConfig::Sparkline { row, metric: m } => Widget::Sparkline {
+                row: *row,
+                metric: metric(m)?,
+            },
+            WidgetConfig::Memory {
+                row,
+                used,
+                cache,
+                swap,
+            } => Widget::Memory {
+                row: *row,
+                used: metric(used)?,
+                cache: metric(cache)?,
+                swap: metric(swap)?,
+            },
+            WidgetConfig::Status { row } => Widget::Status { row: *row },
+            WidgetConfig::Pressure { row } => Widget::Pressure { row: *row },
+            WidgetConfig::Frequency { row } => Widget::Frequency { row: *row },
+            WidgetConfig::Battery { row } => Widget::Battery { row: *row },
+        };
+
+        if widget.row() >= layout::ROWS {
+            return Err(format!("row {} is off screen", widget.row()));
+        }
+
+        Ok(widget)
+    }
+}
+
+// Converts widgets from the config file into the layout sent to the device.
+fn deserialize_layout<'de, D>(deserializer: D) -> Result<Option<Layout>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let configs = Vec::<WidgetConfig>::deserialize(deserializer)?;
+
+    let mut layout = Layout {
+        widgets: heapless::Vec::new(),
+    };
+    for config in &configs {
+        let widget = config.to_widget().map_err(de::Error::custom)?;
+        layout.widgets.push(widget).map_err(|_| {
+            de::Error::custom(format!("layout exceeds {} widgets", layout::MAX_WIDGETS))
+        })?;
+    }
+
+    Ok(Some(layout))
+}
+
+// Validates alert rules from the config file.
+fn deserialize_alerts<'de, D>(deserializer: D) -> Result<Vec<AlertConfig>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let alerts = Vec::<AlertConfig>::deserialize(deserializer)?;
+    for alert in &alerts {
+        alert.validate().map_err(de::Error::custom)?;
+    }
+
+    Ok(alerts)
+}
+
+// Validates that the scrape URL is supported.
+fn deserialize_scrape<'de, D>(deserializer: D) -> Result<ScrapeConfig, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let scrape = ScrapeConfig::deserialize(deserializer)?;
+    if let Some(url) = &scrape.url {
+        collector::validate_url(url).map_err(de::Error::custom)?;
+    }
+
+    Ok(scrape)
+}
+
+// Validates that hosts fit on the device.
+fn deserialize_hosts<'de, D>(deserializer: D) -> Result<HostsConfig, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let hosts = HostsConfig::deserialize(deserializer)?;
+    hosts.validate().map_err(de::Error::custom)?;
+
+    Ok(hosts)
+}
+
+// Validates that the relay is configured as either a sink or a collector.
+fn deserialize_relay<'de, D>(deserializer: D) -> Result<RelayConfig, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let relay = RelayConfig::deserialize(deserializer)?;
+    if relay.listen.is_some() && relay.connect.is_some() {
+        return Err(de::Error::custom(
+            "relay may either listen or connect, not both",
+        ));
+    }
+
+    Ok(relay)
+}
+
+impl Config {
+    /// Loads configuration from the TOML file at `path`.
+    pub fn load(path: &Path) -> Result<Config, Error> {
+        let text = std::fs::read_to_string(path).map_err(Error::IO)?;
+        Config::parse(&text)
+    }
+
+    /// Parses configuration from TOML text.
+    pub fn parse(text: &str) -> Result<Config, Error> {
+        toml::from_str(text).map_err(Error::Config)
+    }
+
+    /// Returns true if the top page may be shown, so top processes should be collected.
+    pub fn shows_top(&self) -> bool {
+        self.top.enabled || self.device.default_page == Some(Page::Top)
+    }
+}
+
+impl DeviceConfig {
+    /// Returns true if no device settings have been configured.
+    pub fn is_empty(&self) -> bool {
+        *self == DeviceConfig::default()
+    }
+
+    /// Overlays the configured values onto `settings`.
+    pub fn apply(&self, settings: Settings) -> Settings {
+        Settings {
+            brightness: self.brightness.unwrap_or(settings.brightness),
+            blank_timeout_secs: self.blank_timeout.unwrap_or(settings.blank_timeout_secs),
+            idle_clock: self.idle_clock.unwrap_or(settings.idle_clock),
+            default_page: self.default_page.unwrap_or(settings.default_page),
+            color_mode: self.color_mode.unwrap_or(settings.color_mode),
+            theme: settings.theme,
+        }
+    }
+}
+
+impl ThemeConfig {
+    /// Returns the configured theme, or None if no theme was configured.
+    pub fn theme(&self) -> Option<Theme> {
+        if *self == ThemeConfig::default() {
+            return None;
+        }
+
+        let mut theme = self.name.unwrap_or(ThemeName::Default).theme();
+        if let Some(level) = self.warn {
+            let color = theme.warn.unwrap_or(Threshold::DEFAULT_WARN).color;
+            theme.warn = Some(Threshold { level, color });
+        }
+        if let Some(level) = self.crit {
+            let color = theme.crit.unwrap_or(Threshold::DEFAULT_CRIT).color;
+            theme.crit = Some(Threshold { level, color });
+        }
+
+        Some(theme)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::hosts::ViewConfig;
+
+    #[test]
+    fn parse_empty() {
+        let config = Config::parse("").unwrap();
+
+        assert!(config.device.is_empty());
+    }
+
+    #[test]
+    fn parse_device() {
+        let config = Config::parse(
+            r#"
+            [device]
+            brightness = 30
+            color_mode = "night"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.device,
+            DeviceConfig {
+                brightness: Some(30),
+                color_mode: Some(ColorMode::Night),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn shows_top_when_enabled_or_default() {
+        assert!(!Config::parse("").unwrap().shows_top());
+        assert!(Config::parse("[top]\nenabled = true\n")
+            .unwrap()
+            .shows_top());
+        assert!(Config::parse("[device]\ndefault_page = \"top\"\n")
+            .unwrap()
+            .shows_top());
+    }
+
+    #[test]
+    fn parse_rejects_unknown_field() {
+        let result = Config::parse("[device]\nbrightnes = 30\n");
+
+        assert!(matches!(result, Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn parse_rejects_unknown_theme() {
+        let result = Config::parse("[theme]\nname = \"mauve\"\n");
+
+        assert!(matches!(result, Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn theme_unconfigured() {
+        let config = Config::parse("").unwrap();
+
+        assert_eq!(config.theme.theme(), None);
+    }
+
+    #[test]
+    fn theme_by_name() {
+        let config = Config::parse("[theme]\nname = \"high_contrast\"\n").unwrap();
+
+        assert_eq!(config.theme.theme(), Some(Theme::HIGH_CONTRAST));
+    }
+
+    #[test]
+    fn theme_threshold_overrides() {
+        let config = Config::parse("[theme]\nwarn = 50\ncrit = 80\n").unwrap();
+
+        let theme = config.theme.theme().unwrap();
+
+        assert_eq!(theme.day, Theme::DEFAULT.day);
+        assert_eq!(
+            theme.warn,
+            Some(Threshold {
+                level: 50,
+                color: Threshold::DEFAULT_WARN.color
+            })
+        );
+        assert_eq!(
+            theme.crit,
+            Some(Threshold {
+                level: 80,
+                color: Threshold::DEFAULT_CRIT.color
+            })
+        );
+    }
+
+    #[test]
+    fn parse_layout() {
+        let config = Config::parse(
+            r#"
+            [[layout]]
+            kind = "label"
+            row = 0
+            text = "CPU"
+
+            [[layout]]
+            kind = "value"
+            row = 0
+            align = "right"
+            metric = "cpu_avg"
+            format = "percent_tenths"
+            suffix = "% Avg"
+
+            [[layout]]
+            kind = "sparkline"
+            row = 1
+            metric = "cpu_load"
+            "#,
+        )
+        .unwrap();
+
+        let widgets = config.layout.unwrap().widgets;
+        assert_eq!(widgets.len(), 3);
+        assert_eq!(
+            widgets[1],
+            Widget::Value {
+                row: 0,
+                align: Align::Right,
+                metric: metric::CPU_AVG,
+                format: Format::PercentTenths,
+                suffix: "% Avg".into(),
+            }
+        );
+        assert_eq!(
+            widgets[2],
+            Widget::Sparkline {
+                row: 1,
+                metric: metric::CPU_LOAD,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_layout_rejects_unknown_metric() {
+        let result = Config::parse("[[layout]]\nkind = \"bar\"\nrow = 0\nmetric = \"gpu\"\n");
+
+        assert!(matches!(result, Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn parse_layout_rejects_long_label() {
+        let result = Config::parse(
+            "[[layout]]\nkind = \"label\"\nrow = 0\ntext = \"Much too long for a label\"\n",
+        );
+
+        assert!(matches!(result, Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn parse_layout_rejects_offscreen_row() {
+        let result = Config::parse("[[layout]]\nkind = \"bar\"\nrow = 9\nmetric = \"mem_load\"\n");
+
+        assert!(matches!(result, Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn parse_memory() {
+        let config = Config::parse("[memory]\nused = \"buffers_cache\"\n").unwrap();
+
+        assert_eq!(config.memory.used, UsedMemory::BuffersCache);
+    }
+
+    #[test]
+    fn parse_memory_defaults_to_available() {
+        let config = Config::parse("").unwrap();
+
+        assert_eq!(config.memory.used, UsedMemory::Available);
+    }
+
+    #[test]
+    fn parse_cgroup() {
+        let config = Config::parse("[cgroup]\npath = \"system.slice/ci.slice\"\n").unwrap();
+
+        assert_eq!(
+            config.cgroup.path,
+            Some(PathBuf::from("system.slice/ci.slice"))
+        );
+    }
+
+    #[test]
+    fn parse_scrape() {
+        let config = Config::parse("[scrape]\nurl = \"http://nas:9100/metrics\"\n").unwrap();
+
+        assert_eq!(
+            config.scrape.url.as_deref(),
+            Some("http://nas:9100/metrics")
+        );
+    }
+
+    #[test]
+    fn parse_scrape_rejects_https() {
+        let result = Config::parse("[scrape]\nurl = \"https://nas:9100/metrics\"\n");
+
+        assert!(matches!(result, Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn parse_api() {
+        let config = Config::parse("[api]\nsocket = \"/run/hw-gauge.sock\"\n").unwrap();
+
+        assert_eq!(config.api.socket, Some(PathBuf::from("/run/hw-gauge.sock")));
+    }
+
+    #[test]
+    fn parse_exporter() {
+        let config = Config::parse("[exporter]\nlisten = \"127.0.0.1:9187\"\n").unwrap();
+
+        assert_eq!(config.exporter.listen.as_deref(), Some("127.0.0.1:9187"));
+    }
+
+    #[test]
+    fn parse_hosts() {
+        let config = Config::parse(
+            "[hosts]\nview = \"rotate\"\n\n[[hosts.host]]\nname = \"desk\"\n\n[[hosts.host]]\nname = \"nas\"\nurl = \"http://nas:9100/metrics\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(config.hosts.view, ViewConfig::Rotate);
+        assert_eq!(config.hosts.rotate, 5);
+        assert_eq!(config.hosts.host.len(), 2);
+        assert_eq!(config.hosts.host[0].url, None);
+        assert_eq!(
+            config.hosts.host[1].url.as_deref(),
+            Some("http://nas:9100/metrics")
+        );
+    }
+
+    #[test]
+    fn parse_hosts_rejects_long_name() {
+        let result = Config::parse("[[hosts.host]]\nname = \"build-server-01\"\n");
+
+        assert!(matches!(result, Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn parse_relay() {
+        let config =
+            Config::parse("[relay]\nconnect = \"desk:7878\"\nsecret = \"hunter2\"\n").unwrap();
+
+        assert_eq!(config.relay.connect.as_deref(), Some("desk:7878"));
+        assert_eq!(config.relay.secret, "hunter2");
+    }
+
+    #[test]
+    fn parse_relay_rejects_listen_and_connect() {
+        let result = Config::parse("[relay]\nlisten = \"0.0.0.0:7878\"\nconnect = \"desk:7878\"\n");
+
+        assert!(matches!(result, Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn parse_alert() {
+        let config = Config::parse(
+            r#"
+            [[alert]]
+            metric = "cpu_avg"
+            above = 95
+            for = 120
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.alert,
+            vec![AlertConfig {
+                metric: "cpu_avg".to_string(),
+                above: Some(95.0),
+                below: None,
+                clear: None,
+                hold_secs: 120,
+            }]
+        );
+    }
+
+    #[test]
+    fn parse_alert_rejects_missing_threshold() {
+        let result = Config::parse("[[alert]]\nmetric = \"mem_load\"\n");
+
+        assert!(matches!(result, Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn parse_command() {
+        let config = Config::parse(
+            r#"
+            [[command]]
+            command = "redis-cli llen jobs"
+            interval = 30
+
+            [[command.metric]]
+            slot = 1
+            label = "queue"
+            min = 0
+            max = 500
+
+            [[layout]]
+            kind = "bar"
+            row = 0
+            metric = "custom_1"
+            "#,
+        )
+        .unwrap();
+
+        let command = &config.command[0];
+        assert_eq!(command.interval, 30);
+        assert_eq!(command.timeout, 5);
+        assert_eq!(command.metrics[0].slot, 1);
+        assert_eq!(command.metrics[0].max, Some(500.0));
+        assert_eq!(
+            config.layout.unwrap().widgets[0],
+            Widget::Bar {
+                row: 0,
+                metric: metric::custom(1),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_command_rejects_long_label() {
+        let result = Config::parse(
+            "[[command]]\ncommand = \"true\"\n[[command.metric]]\nslot = 0\nlabel = \"much too long\"\n",
+        );
+
+        assert!(matches!(result, Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn apply_overlays_present_values() {
+        let device = DeviceConfig {
+            blank_timeout: Some(0),
+            idle_clock: Some(false),
+            ..Default::default()
+        };
+
+        let actual = device.apply(Settings::default());
+
+        assert_eq!(
+            actual,
+            Settings {
+                blank_timeout_secs: 0,
+                idle_clock: false,
+                ..Settings::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,431 @@
+use crate::collector::{self, Collector};
+use crate::error::Backoff;
+use crate::exporter::Exporter;
+use crate::stop::StopSignal;
+use crate::{hosts, relay, tasks, Config, Error, Services};
+use crate::{MAX_RECONNECT_DELAY, RECONNECT_DELAY};
+#[cfg(target_os = "linux")]
+use crate::{RESUME_RETRY_DELAY, RESUME_RETRY_PERIOD};
+use once_cell::sync::OnceCell;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+#[cfg(target_os = "linux")]
+use std::time::Instant;
+use tokio::runtime::Runtime;
+
+#[cfg(unix)]
+use crate::api;
+#[cfg(target_os = "linux")]
+use crate::power;
+
+/// Makes a collector for each connection, so collectors start afresh as the device
+/// reconnects.
+pub(crate) type CollectorFactory = Box<dyn Fn() -> Box<dyn Collector> + Send + Sync>;
+
+/// Builds a daemon, for programs embedding it in place of running the daemon binaries.
+#[derive(Default)]
+pub struct DaemonBuilder {
+    config: Config,
+    collectors: Vec<CollectorFactory>,
+}
+
+impl DaemonBuilder {
+    /// Sets the config, which otherwise defaults to that of a daemon run without a config
+    /// file.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Adds a collector, alongside those selected by the config.  `factory` is called upon
+    /// each connection to the device.
+    pub fn collector<F, C>(mut self, factory: F) -> Self
+    where
+        F: Fn() -> C + Send + Sync + 'static,
+        C: Collector + 'static,
+    {
+        self.collectors
+            .push(Box::new(move || Box::new(factory()) as Box<dyn Collector>));
+        self
+    }
+
+    pub fn build(self) -> Daemon {
+        Daemon {
+            config: self.config,
+            collectors: self.collectors,
+            stop: Arc::default(),
+            reconnect: Mutex::new(Backoff::new(RECONNECT_DELAY, MAX_RECONNECT_DELAY)),
+            runtime: OnceCell::new(),
+            exporter: OnceCell::new(),
+            #[cfg(unix)]
+            api: OnceCell::new(),
+            hosts: OnceCell::new(),
+            #[cfg(target_os = "linux")]
+            power: OnceCell::new(),
+            sink: OnceCell::new(),
+        }
+    }
+}
+
+/// Sends metrics to the device, reconnecting as it comes and goes, until stopped.  Services
+/// such as the exporter, API socket and relay listener are started upon the first
+/// connection, and outlive the rest.
+pub struct Daemon {
+    config: Config,
+    collectors: Vec<CollectorFactory>,
+    // Stops the daemon, interrupting blocking samples and retry delays, and cancelling tasks.
+    stop: Arc<StopSignal>,
+    // Backoff between connection attempts, reset once connected.
+    reconnect: Mutex<Backoff>,
+    // Runtime for each connection's tasks, built upon the first and reused by the rest.
+    runtime: OnceCell<Runtime>,
+    // Services started upon the first connection, None when not configured or failing to
+    // start.
+    exporter: OnceCell<Option<Exporter>>,
+    #[cfg(unix)]
+    api: OnceCell<Option<api::Server>>,
+    hosts: OnceCell<Option<hosts::HostMonitor>>,
+    #[cfg(target_os = "linux")]
+    power: OnceCell<Option<power::PowerMonitor>>,
+    sink: OnceCell<Arc<relay::Sink>>,
+}
+
+/// Stops a daemon from another thread, such as a signal handler's.
+#[derive(Clone)]
+pub struct DaemonHandle {
+    stop: Arc<StopSignal>,
+}
+
+impl DaemonHandle {
+    /// Stops the daemon, clearing the device screen and releasing its port.
+    pub fn stop(&self) {
+        self.stop.stop();
+    }
+}
+
+impl Daemon {
+    pub fn builder() -> DaemonBuilder {
+        DaemonBuilder::default()
+    }
+
+    /// Returns a handle stopping the daemon from other threads.
+    pub fn handle(&self) -> DaemonHandle {
+        DaemonHandle {
+            stop: Arc::clone(&self.stop),
+        }
+    }
+
+    /// Stops the daemon, clearing the device screen and releasing its port.  Once stopped,
+    /// the daemon does not run again.
+    pub fn stop(&self) {
+        self.stop.stop();
+    }
+
+    /// Connects to the device, and sends metrics until stopped, or an error occurs.
+    pub fn run_once(&self) -> Result<(), Error> {
+        self.run_with(&self.config)
+    }
+
+    /// Connects to the device, and sends metrics until stopped.  Errors are logged, and
+    /// retried after `retry_delay`.
+    pub fn run(&self) {
+        loop {
+            match self.run_once() {
+                Ok(()) => break,
+                Err(err) => {
+                    log::warn!("{}", err);
+                    let delay = self.retry_delay();
+                    log::info!("Retrying in {:?}", delay);
+                    if self.wait_for_stop(delay) {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the delay before retrying a failed `run_once`, backing off exponentially
+    /// until a connection succeeds.  Shortened just after the host resumes from sleep.
+    pub fn retry_delay(&self) -> Duration {
+        let delay = self
+            .reconnect
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .failed();
+
+        #[cfg(target_os = "linux")]
+        if let Some(Some(power)) = self.power.get() {
+            if power.resumed_within(RESUME_RETRY_PERIOD, Instant::now()) {
+                return delay.min(RESUME_RETRY_DELAY);
+            }
+        }
+
+        delay
+    }
+
+    /// Blocks for `timeout`, returning true early if the daemon is stopped.  Also returns
+    /// early, with false, when the host resumes from sleep.
+    pub fn wait_for_stop(&self, timeout: Duration) -> bool {
+        self.stop.wait(timeout)
+    }
+
+    /// Connects to the device, and sends metrics from the collectors selected by `config`
+    /// until stopped, or an error occurs.
+    pub(crate) fn run_with(&self, config: &Config) -> Result<(), Error> {
+        if self.stop.is_stopped() {
+            return Ok(());
+        }
+
+        let exporter = self.exporter(config);
+        let runtime = self.runtime.get_or_try_init(|| {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_time()
+                .build()
+                .map_err(Error::IO)
+        })?;
+        let result = if config.relay.listen.is_some() {
+            runtime.block_on(self.relay_sink(config, exporter.clone()))
+        } else {
+            runtime.block_on(self.connect_and_send(config, exporter.clone()))
+        };
+
+        if let Some(exporter) = exporter {
+            exporter.update_link(|link| {
+                if link.connected && result.is_err() {
+                    link.errors += 1;
+                }
+                link.connected = false;
+            });
+        }
+
+        result
+    }
+
+    /// Sends metrics to the device until stopped, or an error occurs.
+    async fn connect_and_send(
+        &self,
+        config: &Config,
+        exporter: Option<Exporter>,
+    ) -> Result<(), Error> {
+        #[cfg(unix)]
+        let api = self.api_server(config);
+        #[cfg(not(unix))]
+        if config.api.socket.is_some() {
+            log::warn!("API socket is not supported on this platform");
+        }
+
+        let link = crate::open_link(config)?;
+        self.record_connected(exporter.as_ref());
+
+        #[cfg_attr(not(unix), allow(unused_mut))]
+        let mut collectors = self.collectors(config);
+        #[cfg(unix)]
+        if let Some(server) = &api {
+            collectors.push(Box::new(server.clone()));
+        }
+        let services = Services {
+            exporter,
+            hosts: self.host_monitor(config),
+            #[cfg(unix)]
+            api,
+            #[cfg(target_os = "linux")]
+            power: self.power_monitor(),
+        };
+
+        tasks::serve(link, collectors, config, services, Arc::clone(&self.stop)).await
+    }
+
+    /// Relays frames from remote collectors to the device until stopped, or an error occurs.
+    async fn relay_sink(&self, config: &Config, exporter: Option<Exporter>) -> Result<(), Error> {
+        let sink = self.sink.get_or_try_init(|| {
+            let listen = config.relay.listen.as_deref().unwrap_or_default();
+            let sink = relay::Sink::bind(listen, &config.relay.secret).map_err(Error::IO)?;
+            if config.relay.secret.is_empty() {
+                log::warn!("Relay secret is empty, any collector may connect");
+            }
+            log::info!("Accepting relay collectors on {}", sink.local_addr());
+            Ok::<_, Error>(Arc::new(sink))
+        })?;
+
+        let pinfo = crate::detect_port()?;
+        let link = crate::serial_link(&pinfo.port_name)?;
+        self.record_connected(exporter.as_ref());
+        log::info!("Relaying to detected device on port: {}", pinfo.port_name);
+
+        tasks::relay(link, Arc::clone(sink), exporter, Arc::clone(&self.stop)).await
+    }
+
+    /// Makes the collectors selected by `config` for a connection, followed by those added to
+    /// the builder.
+    fn collectors(&self, config: &Config) -> Vec<Box<dyn Collector>> {
+        let mut collectors = collector::from_config(config, &self.stop);
+        for factory in &self.collectors {
+            collectors.push(Box::new(collector::Retrying::new(factory())));
+        }
+        collectors
+    }
+
+    fn record_connected(&self, exporter: Option<&Exporter>) {
+        self.reconnect
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .succeeded();
+        if let Some(exporter) = exporter {
+            exporter.update_link(|link| {
+                link.connected = true;
+                link.connections += 1;
+            });
+        }
+    }
+
+    /// Starts the API server if configured, upon first call.  Failure to start is logged
+    /// rather than retried.
+    #[cfg(unix)]
+    fn api_server(&self, config: &Config) -> Option<api::Server> {
+        self.api
+            .get_or_init(|| {
+                let path = config.api.socket.as_ref()?;
+                match api::Server::start(path) {
+                    Ok(server) => {
+                        log::info!("Accepting API requests on {}", path.display());
+                        Some(server)
+                    }
+                    Err(err) => {
+                        log::error!("Failed to create API socket {}: {}", path.display(), err);
+                        None
+                    }
+                }
+            })
+            .clone()
+    }
+
+    /// Starts monitoring hosts if configured, upon first call.
+    fn host_monitor(&self, config: &Config) -> Option<hosts::HostMonitor> {
+        self.hosts
+            .get_or_init(|| {
+                if config.hosts.host.is_empty() {
+                    return None;
+                }
+                Some(hosts::HostMonitor::start(
+                    &config.hosts,
+                    config.memory.used,
+                    &self.stop,
+                ))
+            })
+            .clone()
+    }
+
+    /// Starts monitoring logind for sleep and lock, upon first call.  Failure to connect to
+    /// the system bus is logged rather than retried.
+    #[cfg(target_os = "linux")]
+    fn power_monitor(&self) -> Option<power::PowerMonitor> {
+        self.power
+            .get_or_init(|| {
+                let stop = Arc::clone(&self.stop);
+                match power::PowerMonitor::start(None, move || stop.wake()) {
+                    Ok(monitor) => Some(monitor),
+                    Err(err) => {
+                        log::warn!("Failed to monitor logind, sleep is not shown: {}", err);
+                        None
+                    }
+                }
+            })
+            .clone()
+    }
+
+    /// Starts the Prometheus exporter if configured, upon first call.  Failure to start is
+    /// logged rather than retried.
+    fn exporter(&self, config: &Config) -> Option<Exporter> {
+        self.exporter
+            .get_or_init(|| {
+                let listen = config.exporter.listen.as_ref()?;
+                match Exporter::start(listen) {
+                    Ok(exporter) => {
+                        log::info!(
+                            "Serving metrics on http://{}/metrics",
+                            exporter.local_addr()
+                        );
+                        Some(exporter)
+                    }
+                    Err(err) => {
+                        log::error!("Failed to listen for scrapes on {}: {}", listen, err);
+                        None
+                    }
+                }
+            })
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::{run, FakeDevice};
+    use shared::message::FromHost;
+    use shared::metric::{self, Metric};
+    use std::thread;
+    use std::time::Instant;
+
+    struct ConstCollector(f32);
+
+    impl Collector for ConstCollector {
+        fn collect(&mut self, metrics: &mut Vec<Metric>) -> Result<(), Error> {
+            metrics.push(Metric::new(metric::custom(0), self.0));
+            Ok(())
+        }
+    }
+
+    fn sent_metrics(device: &FakeDevice) -> Vec<Metric> {
+        device
+            .messages()
+            .into_iter()
+            .filter_map(|msg| match msg {
+                FromHost::UpdateMetrics(batch) => Some(batch.metrics),
+                _ => None,
+            })
+            .flatten()
+            .collect()
+    }
+
+    #[test]
+    fn serve_sends_builder_collector_metrics() {
+        let daemon = Daemon::builder().collector(|| ConstCollector(2.0)).build();
+        let config = Config::default();
+        let device = FakeDevice::default();
+
+        let stopper = {
+            let handle = daemon.handle();
+            let device = device.clone();
+            thread::spawn(move || {
+                let deadline = Instant::now() + Duration::from_secs(10);
+                while sent_metrics(&device).is_empty() && Instant::now() < deadline {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                handle.stop();
+            })
+        };
+        let result = run(tasks::serve(
+            device.link(),
+            daemon.collectors(&config),
+            &config,
+            Services::default(),
+            Arc::clone(&daemon.stop),
+        ));
+        stopper.join().unwrap();
+
+        assert!(result.is_ok());
+        assert!(sent_metrics(&device).contains(&Metric::new(metric::custom(0), 2.0)));
+    }
+
+    #[test]
+    fn stopping_one_daemon_leaves_others_running() {
+        let stopped = Daemon::builder().build();
+        let running = Daemon::builder().build();
+
+        stopped.handle().stop();
+
+        assert!(stopped.run_once().is_ok());
+        assert!(stopped.wait_for_stop(Duration::from_secs(5)));
+        assert!(!running.wait_for_stop(Duration::from_millis(10)));
+    }
+}
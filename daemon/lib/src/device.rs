@@ -0,0 +1,198 @@
+use crate::Error;
+use shared::message::{
+    FromDevice, FromHost, Notification, NotifyLevel, MAX_FRAME_BYTES, NOTIFY_TEXT_LEN,
+};
+use std::io::{self, BufReader, Read, Write};
+
+/// Serializes a message into a COBS frame, and writes it to the device.
+pub(crate) fn write_message<W: Write + ?Sized>(w: &mut W, msg: &FromHost) -> Result<(), Error> {
+    let msg_bytes = postcard::to_allocvec_cobs(msg).map_err(Error::Encode)?;
+    w.write_all(&msg_bytes).map_err(Error::DeviceGone)
+}
+
+/// Deserializes a COBS frame read from the device.
+pub(crate) fn decode_message(frame: &mut [u8]) -> Result<FromDevice, Error> {
+    postcard::from_bytes_cobs(frame).map_err(Error::Decode)
+}
+
+/// Reads a single COBS frame, including its terminator.
+///
+/// Bytes are read one at a time so that nothing beyond the frame terminator is consumed.
+pub(crate) fn read_frame<R: Read + ?Sized>(r: &mut R) -> io::Result<Vec<u8>> {
+    let mut frame = Vec::new();
+    let mut byte = [0u8; 1];
+
+    loop {
+        r.read_exact(&mut byte)?;
+        frame.push(byte[0]);
+        if byte[0] == 0 {
+            return Ok(frame);
+        }
+        if frame.len() >= MAX_FRAME_BYTES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "frame exceeds maximum length",
+            ));
+        }
+    }
+}
+
+/// Builds a notification, truncating text too long to fit a message.
+pub(crate) fn notification(text: &str, level: NotifyLevel, duration_secs: u16) -> Notification {
+    let mut truncated = heapless::String::<NOTIFY_TEXT_LEN>::new();
+    for c in text.chars() {
+        if truncated.push(c).is_err() {
+            break;
+        }
+    }
+
+    Notification {
+        text: truncated,
+        level,
+        duration_secs,
+    }
+}
+
+/// Converts an error reading a frame, reporting oversized frames as such rather than as
+/// `failed` reading the stream.
+pub(crate) fn frame_error(err: io::Error, failed: fn(io::Error) -> Error) -> Error {
+    if err.kind() == io::ErrorKind::InvalidData {
+        Error::FrameTooLong
+    } else {
+        failed(err)
+    }
+}
+
+/// Returns true if a read or write gave up waiting, rather than failing.
+pub(crate) fn is_timeout(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// Reads COBS frames from a stream with a read timeout, keeping partially read frames
+/// across timeouts.
+pub(crate) struct FrameReader<R> {
+    inner: BufReader<R>,
+    frame: Vec<u8>,
+}
+
+impl<R: Read> FrameReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        FrameReader {
+            inner: BufReader::new(inner),
+            frame: Vec::new(),
+        }
+    }
+
+    /// Reads the next frame, including its terminator.
+    pub(crate) fn read_frame(&mut self) -> io::Result<Vec<u8>> {
+        let mut byte = [0u8; 1];
+        loop {
+            self.inner.read_exact(&mut byte)?;
+            self.frame.push(byte[0]);
+            if byte[0] == 0 {
+                return Ok(std::mem::take(&mut self.frame));
+            }
+            if self.frame.len() >= MAX_FRAME_BYTES {
+                self.frame.clear();
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "frame exceeds maximum length",
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shared::settings::Settings;
+    use std::collections::VecDeque;
+    use std::io::Cursor;
+
+    // Reads queued chunks, timing out between them as a serial port would.
+    struct ChunkedReader(VecDeque<Vec<u8>>);
+
+    impl Read for ChunkedReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.pop_front() {
+                Some(chunk) if !chunk.is_empty() => {
+                    buf[..chunk.len()].copy_from_slice(&chunk);
+                    Ok(chunk.len())
+                }
+                Some(_) => Err(io::ErrorKind::TimedOut.into()),
+                None => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn decode_message_decodes_frame() {
+        let settings = Settings::default();
+        let mut bytes = postcard::to_allocvec_cobs(&FromDevice::Settings(settings)).unwrap();
+
+        let actual = decode_message(&mut bytes).unwrap();
+
+        assert!(matches!(actual, FromDevice::Settings(s) if s == settings));
+    }
+
+    #[test]
+    fn read_frame_stops_at_terminator() {
+        let mut bytes =
+            postcard::to_allocvec_cobs(&FromDevice::Settings(Settings::default())).unwrap();
+        let frame_len = bytes.len();
+        bytes.extend_from_slice(&[1, 2, 3]);
+        let mut cursor = Cursor::new(bytes);
+
+        read_frame(&mut cursor).unwrap();
+
+        assert_eq!(cursor.position() as usize, frame_len);
+    }
+
+    #[test]
+    fn decode_message_rejects_garbage() {
+        let result = decode_message(&mut [0x05, 0xff, 0x00]);
+
+        assert!(matches!(result, Err(Error::Decode(_))));
+    }
+
+    #[test]
+    fn frame_reader_keeps_partial_frame() {
+        let bytes = postcard::to_allocvec_cobs(&FromDevice::Settings(Settings::default())).unwrap();
+        let (head, tail) = bytes.split_at(2);
+        let chunks = vec![head.to_vec(), Vec::new(), tail.to_vec()];
+        let mut frames = FrameReader::new(ChunkedReader(chunks.into_iter().collect()));
+
+        assert!(is_timeout(&frames.read_frame().unwrap_err()));
+        assert_eq!(frames.read_frame().unwrap(), bytes);
+        assert_eq!(
+            frames.read_frame().unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn frame_reader_rejects_oversized_frame() {
+        let mut frames = FrameReader::new(Cursor::new(vec![1; MAX_FRAME_BYTES]));
+
+        let err = frame_error(frames.read_frame().unwrap_err(), Error::DeviceGone);
+
+        assert!(matches!(err, Error::FrameTooLong));
+        let eof = frame_error(frames.read_frame().unwrap_err(), Error::DeviceGone);
+        assert!(matches!(eof, Error::DeviceGone(_)));
+    }
+
+    #[test]
+    fn write_message_frames_with_terminator() {
+        let mut out = Vec::new();
+
+        write_message(&mut out, &FromHost::GetSettings).unwrap();
+
+        assert_eq!(out.last(), Some(&0));
+        let msg: FromHost = postcard::from_bytes_cobs(&mut out).unwrap();
+        assert!(matches!(msg, FromHost::GetSettings));
+    }
+}
@@ -0,0 +1,141 @@
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+/// Errors from the daemon, distinguishing failures of the device link, which must be
+/// reopened, from failures of a single collector, which are retried in place.
+#[derive(Debug)]
+pub enum Error {
+    /// No device was found on any serial port.
+    PortNotFound,
+    /// Writing to or reading from the device failed, typically as it was unplugged.
+    DeviceGone(io::Error),
+    /// A collector failed to read its source, leaving the other collectors working.
+    Collector(io::Error),
+    IO(io::Error),
+    Serial(serialport::Error),
+    Config(toml::de::Error),
+    /// A frame received from the device or a relay peer was malformed.
+    Decode(postcard::Error),
+    /// A frame received from the device or a relay peer exceeded the maximum length.
+    FrameTooLong,
+    /// A message could not be encoded into a frame.
+    Encode(postcard::Error),
+}
+
+/// How the daemon recovers from an error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Recovery {
+    /// Skip the collector until it is retried, keeping the device connected.
+    RetryCollector,
+    /// Close the device link, and reopen it after backing off.
+    Reconnect,
+    /// Discard the malformed frame, keeping the link open.
+    DiscardFrame,
+}
+
+impl Error {
+    /// Returns how to recover from the error.
+    pub fn recovery(&self) -> Recovery {
+        match self {
+            Error::Collector(_) => Recovery::RetryCollector,
+            Error::Decode(_) | Error::FrameTooLong => Recovery::DiscardFrame,
+            _ => Recovery::Reconnect,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::PortNotFound => write!(f, "device not found on any USB serial port"),
+            Error::DeviceGone(err) => write!(f, "device link failed: {}", err),
+            Error::Collector(err) => write!(f, "collector failed: {}", err),
+            Error::IO(err) => write!(f, "I/O error: {}", err),
+            Error::Serial(err) => write!(f, "serial port error: {}", err),
+            Error::Config(err) => write!(f, "invalid config: {}", err),
+            Error::Decode(err) => write!(f, "malformed frame: {}", err),
+            Error::FrameTooLong => write!(f, "frame exceeds maximum length"),
+            Error::Encode(err) => write!(f, "failed to encode message: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::PortNotFound | Error::FrameTooLong => None,
+            Error::DeviceGone(err) | Error::Collector(err) | Error::IO(err) => Some(err),
+            Error::Serial(err) => Some(err),
+            Error::Config(err) => Some(err),
+            Error::Decode(err) | Error::Encode(err) => Some(err),
+        }
+    }
+}
+
+/// Delay between attempts, doubling after each failure up to a limit, and reset by success.
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    // Delay before the next attempt, None until the first failure.
+    next: Option<Duration>,
+}
+
+impl Backoff {
+    pub const fn new(initial: Duration, max: Duration) -> Self {
+        Backoff {
+            initial,
+            max,
+            next: None,
+        }
+    }
+
+    /// Records a failure, returning the delay before trying again.
+    pub fn failed(&mut self) -> Duration {
+        let delay = self.next.unwrap_or(self.initial);
+        self.next = Some((delay * 2).min(self.max));
+        delay
+    }
+
+    /// Records a success, so the next failure is retried after the initial delay.
+    pub fn succeeded(&mut self) {
+        self.next = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::error::Error as _;
+
+    #[test]
+    fn backoff_doubles_to_max() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(5));
+
+        let delays: Vec<u64> = (0..5).map(|_| backoff.failed().as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 5, 5]);
+
+        backoff.succeeded();
+        assert_eq!(backoff.failed(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn recovery_policy() {
+        let err = || io::Error::from(io::ErrorKind::NotFound);
+
+        assert_eq!(Error::Collector(err()).recovery(), Recovery::RetryCollector);
+        assert_eq!(Error::DeviceGone(err()).recovery(), Recovery::Reconnect);
+        assert_eq!(Error::PortNotFound.recovery(), Recovery::Reconnect);
+        assert_eq!(Error::FrameTooLong.recovery(), Recovery::DiscardFrame);
+    }
+
+    #[test]
+    fn display_and_source() {
+        let err = Error::DeviceGone(io::Error::from(io::ErrorKind::BrokenPipe));
+
+        assert_eq!(err.to_string(), "device link failed: broken pipe");
+        assert!(err.source().is_some());
+        assert!(Error::PortNotFound.source().is_none());
+    }
+}
@@ -0,0 +1,301 @@
+use shared::metric::{self, Metric, MetricId};
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+
+/// Counters describing the serial link to the device.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LinkStats {
+    pub connected: bool,
+    // Successful connections, the first included.
+    pub connections: u64,
+    pub frames_sent: u64,
+    pub bytes_sent: u64,
+    // Connections ended by an error.
+    pub errors: u64,
+}
+
+#[derive(Default)]
+struct State {
+    // Metrics most recently sent to the device.
+    samples: Vec<Metric>,
+    link: LinkStats,
+}
+
+/// Serves the metrics sent to the device, and statistics about the link, for Prometheus
+/// to scrape.
+#[derive(Clone)]
+pub struct Exporter {
+    state: Arc<Mutex<State>>,
+    addr: SocketAddr,
+}
+
+impl Exporter {
+    /// Listens for HTTP requests on `addr`, serving `/metrics` from a background thread.
+    pub fn start(addr: &str) -> io::Result<Exporter> {
+        let server = tiny_http::Server::http(addr).map_err(io::Error::other)?;
+        let addr = server
+            .server_addr()
+            .to_ip()
+            .ok_or_else(|| io::Error::other("not an IP listener"))?;
+
+        let state = Arc::new(Mutex::new(State::default()));
+        let shared = Arc::clone(&state);
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let response = if request.url() == "/metrics" {
+                    let header = tiny_http::Header::from_bytes(
+                        &b"Content-Type"[..],
+                        &b"text/plain; version=0.0.4"[..],
+                    )
+                    .expect("valid header");
+                    tiny_http::Response::from_string(render(&lock(&shared))).with_header(header)
+                } else {
+                    tiny_http::Response::from_string("Not found\n").with_status_code(404)
+                };
+                if let Err(err) = request.respond(response) {
+                    log::debug!("Failed to respond to scrape: {}", err);
+                }
+            }
+        });
+
+        Ok(Exporter { state, addr })
+    }
+
+    /// Returns the address being listened on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Records the metrics most recently sent to the device.
+    pub fn record_samples(&self, metrics: &[Metric]) {
+        lock(&self.state).samples = metrics.to_vec();
+    }
+
+    /// Updates link statistics.
+    pub fn update_link(&self, f: impl FnOnce(&mut LinkStats)) {
+        f(&mut lock(&self.state).link);
+    }
+}
+
+/// Counts bytes and COBS frames written to the device, passing reads through.
+pub struct CountingPort<W> {
+    inner: W,
+    frames: u64,
+    bytes: u64,
+}
+
+impl<W> CountingPort<W> {
+    pub fn new(inner: W) -> Self {
+        CountingPort {
+            inner,
+            frames: 0,
+            bytes: 0,
+        }
+    }
+
+    /// Returns frame and byte counts since the last call.
+    pub fn take_counts(&mut self) -> (u64, u64) {
+        let counts = (self.frames, self.bytes);
+        self.frames = 0;
+        self.bytes = 0;
+        counts
+    }
+}
+
+impl<R: Read> Read for CountingPort<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<W: Write> Write for CountingPort<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        // Every frame ends with a zero byte, which COBS guarantees appears nowhere else.
+        self.frames += buf[..written].iter().filter(|b| **b == 0).count() as u64;
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// State holds plain data, so remains usable if a thread panicked while holding it.
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Returns the configuration name of a metric, if it has one.
+fn metric_name(id: MetricId) -> Option<String> {
+    if id.0 >= metric::FIRST_CUSTOM.0 {
+        return Some(format!("custom_{}", id.0 - metric::FIRST_CUSTOM.0));
+    }
+    metric::info(id).map(|info| info.name.to_string())
+}
+
+// Formats a sample value as Prometheus expects.
+fn sample_value(value: f32) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+// Renders state in the Prometheus text exposition format.
+fn render(state: &State) -> String {
+    let mut out = String::new();
+    let link = &state.link;
+
+    let _ = writeln!(
+        out,
+        "# HELP hw_gauge_metric Latest value of each metric sent to the device."
+    );
+    let _ = writeln!(out, "# TYPE hw_gauge_metric gauge");
+    for (i, sample) in state.samples.iter().enumerate() {
+        // Metrics from several sources may share an ID, which Prometheus rejects, so only
+        // the latest is rendered.
+        if state.samples[i + 1..].iter().any(|m| m.id == sample.id) {
+            continue;
+        }
+        if let Some(name) = metric_name(sample.id) {
+            let _ = writeln!(
+                out,
+                "hw_gauge_metric{{metric=\"{}\"}} {}",
+                name,
+                sample_value(sample.value)
+            );
+        }
+    }
+
+    let families: [(&str, &str, &str, u64); 5] = [
+        (
+            "hw_gauge_connected",
+            "gauge",
+            "Whether the device is connected.",
+            link.connected as u64,
+        ),
+        (
+            "hw_gauge_connections_total",
+            "counter",
+            "Connections made to the device.",
+            link.connections,
+        ),
+        (
+            "hw_gauge_frames_sent_total",
+            "counter",
+            "Message frames written to the device.",
+            link.frames_sent,
+        ),
+        (
+            "hw_gauge_bytes_sent_total",
+            "counter",
+            "Bytes written to the device.",
+            link.bytes_sent,
+        ),
+        (
+            "hw_gauge_device_errors_total",
+            "counter",
+            "Connections to the device ended by an error.",
+            link.errors,
+        ),
+    ];
+    for (name, kind, help, value) in families.iter() {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        let _ = writeln!(out, "{} {}", name, value);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::TcpStream;
+
+    fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.0\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn render_samples_and_link() {
+        let state = State {
+            samples: vec![
+                Metric::new(metric::CPU_LOAD, 0.25),
+                Metric::new(metric::custom(3), f32::NAN),
+                Metric::new(metric::top_cpu(0), 0.5),
+            ],
+            link: LinkStats {
+                connected: true,
+                connections: 2,
+                frames_sent: 40,
+                bytes_sent: 900,
+                errors: 1,
+            },
+        };
+
+        let text = render(&state);
+
+        assert!(text.contains("hw_gauge_metric{metric=\"cpu_load\"} 0.25\n"));
+        assert!(text.contains("hw_gauge_metric{metric=\"custom_3\"} NaN\n"));
+        assert!(!text.contains("top"));
+        assert!(text.contains("hw_gauge_connected 1\n"));
+        assert!(text.contains("hw_gauge_frames_sent_total 40\n"));
+        assert!(text.contains("# TYPE hw_gauge_device_errors_total counter\n"));
+    }
+
+    #[test]
+    fn render_keeps_latest_duplicate() {
+        let state = State {
+            samples: vec![
+                Metric::new(metric::custom(0), 1.0),
+                Metric::new(metric::CPU_LOAD, 0.25),
+                Metric::new(metric::custom(0), 2.0),
+            ],
+            link: LinkStats::default(),
+        };
+
+        let text = render(&state);
+
+        assert_eq!(text.matches("metric=\"custom_0\"").count(), 1);
+        assert!(text.contains("hw_gauge_metric{metric=\"custom_0\"} 2\n"));
+        assert!(text.contains("hw_gauge_metric{metric=\"cpu_load\"} 0.25\n"));
+    }
+
+    #[test]
+    fn counting_port_counts_frames() {
+        let mut writer = CountingPort::new(Vec::new());
+
+        writer.write_all(&[1, 2, 0, 3, 0]).unwrap();
+
+        assert_eq!(writer.take_counts(), (2, 5));
+        assert_eq!(writer.take_counts(), (0, 0));
+    }
+
+    #[test]
+    fn serves_metrics() {
+        let exporter = Exporter::start("127.0.0.1:0").unwrap();
+        exporter.record_samples(&[Metric::new(metric::MEM_LOAD, 0.5)]);
+        exporter.update_link(|link| link.connected = true);
+
+        let metrics = get(exporter.local_addr(), "/metrics");
+        let missing = get(exporter.local_addr(), "/");
+
+        assert!(metrics.starts_with("HTTP/1.0 200"));
+        assert!(metrics.contains("hw_gauge_metric{metric=\"mem_load\"} 0.5\n"));
+        assert!(metrics.contains("hw_gauge_connected 1\n"));
+        assert!(missing.starts_with("HTTP/1.0 404"));
+    }
+}
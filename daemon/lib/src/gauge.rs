@@ -0,0 +1,220 @@
+use crate::device::{self, FrameReader};
+use crate::tasks::Link;
+use crate::Error;
+use shared::layout::Layout;
+use shared::message::{FromDevice, FromHost, NotifyLevel, PerfData};
+use shared::metric::{Metric, MetricBatch};
+use shared::settings::{Page, Settings};
+use shared::theme::Theme;
+use std::io::{self, Read, Write};
+
+/// Device to send to directly from another program, in place of running the daemon.
+#[derive(Clone, Debug)]
+pub struct Gauge {
+    target: Target,
+}
+
+#[derive(Clone, Debug)]
+enum Target {
+    Serial(String),
+    Relay { addr: String, secret: String },
+}
+
+impl Gauge {
+    /// Finds the device among the USB serial ports.
+    pub fn detect() -> Result<Gauge, Error> {
+        let pinfo = crate::detect_port()?;
+        Ok(Gauge::serial(&pinfo.port_name))
+    }
+
+    /// Device attached to the serial port named `port_name`, such as `/dev/ttyACM0`.
+    pub fn serial(port_name: &str) -> Gauge {
+        Gauge {
+            target: Target::Serial(port_name.to_string()),
+        }
+    }
+
+    /// Device attached to the host running a relay sink at `addr`, authenticating with
+    /// `secret`.
+    pub fn relay(addr: &str, secret: &str) -> Gauge {
+        Gauge {
+            target: Target::Relay {
+                addr: addr.to_string(),
+                secret: secret.to_string(),
+            },
+        }
+    }
+
+    /// Connects to the device.  Only one session, or daemon, may use the device at a time.
+    pub fn open(&self) -> Result<GaugeSession, Error> {
+        let link = match &self.target {
+            Target::Serial(port_name) => crate::serial_link(port_name)?,
+            Target::Relay { addr, secret } => crate::relay_link(addr, secret)?,
+        };
+        Ok(GaugeSession::new(link))
+    }
+}
+
+/// Connection to the device, writing each message as it is given.  Closing the session, or
+/// dropping it, clears the device screen.
+pub struct GaugeSession {
+    writer: Box<dyn Write + Send>,
+    frames: FrameReader<Box<dyn Read + Send>>,
+    closed: bool,
+}
+
+impl GaugeSession {
+    fn new(link: Link) -> Self {
+        GaugeSession {
+            writer: link.writer,
+            frames: FrameReader::new(link.reader),
+            closed: false,
+        }
+    }
+
+    /// Shows the fixed set of metrics understood by all firmware versions.
+    pub fn show_perf(&mut self, perf: PerfData) -> Result<(), Error> {
+        self.send(&FromHost::ShowPerf(perf))
+    }
+
+    /// Updates metrics by ID, shown once all have been sent.
+    pub fn update_metrics(&mut self, metrics: &[Metric]) -> Result<(), Error> {
+        for batch in MetricBatch::split(metrics) {
+            self.send(&FromHost::UpdateMetrics(batch))?;
+        }
+        Ok(())
+    }
+
+    /// Briefly shows `text` over the current page, truncated if too long.
+    pub fn notify(
+        &mut self,
+        text: &str,
+        level: NotifyLevel,
+        duration_secs: u16,
+    ) -> Result<(), Error> {
+        let notification = device::notification(text, level, duration_secs);
+        self.send(&FromHost::Notify(notification))
+    }
+
+    /// Applies a color theme, which the device persists.
+    pub fn set_theme(&mut self, theme: Theme) -> Result<(), Error> {
+        self.send(&FromHost::SetTheme(theme))
+    }
+
+    /// Replaces the widgets shown on the performance page.
+    pub fn set_layout(&mut self, layout: Layout) -> Result<(), Error> {
+        self.send(&FromHost::SetLayout(layout))
+    }
+
+    /// Switches the page shown, until the next switch or power cycle.
+    pub fn show_page(&mut self, page: Page) -> Result<(), Error> {
+        self.send(&FromHost::ShowPage(page))
+    }
+
+    /// Sets the device clock to the host's local time, shown while idle.
+    pub fn sync_time(&mut self) -> Result<(), Error> {
+        self.send(&FromHost::SetTime(crate::local_time()))
+    }
+
+    /// Returns the device's current settings.
+    pub fn settings(&mut self) -> Result<Settings, Error> {
+        self.send(&FromHost::GetSettings)?;
+        let FromDevice::Settings(settings) = self.reply()?;
+        Ok(settings)
+    }
+
+    /// Applies and persists settings, returning those stored by the device.
+    pub fn set_settings(&mut self, settings: Settings) -> Result<Settings, Error> {
+        self.send(&FromHost::SetSettings(settings))?;
+        let FromDevice::Settings(settings) = self.reply()?;
+        Ok(settings)
+    }
+
+    /// Clears the device screen, and closes the connection.
+    pub fn close(mut self) -> Result<(), Error> {
+        self.closed = true;
+        self.send(&FromHost::ClearScreen)
+    }
+
+    fn send(&mut self, msg: &FromHost) -> Result<(), Error> {
+        device::write_message(&mut self.writer, msg)
+    }
+
+    // Reads the device's reply to a request, which older firmware does not send.
+    fn reply(&mut self) -> Result<FromDevice, Error> {
+        let mut frame = self.frames.read_frame().map_err(|err| {
+            if device::is_timeout(&err) {
+                Error::DeviceGone(io::Error::new(err.kind(), "no reply from device"))
+            } else {
+                device::frame_error(err, Error::DeviceGone)
+            }
+        })?;
+        device::decode_message(&mut frame)
+    }
+}
+
+impl Drop for GaugeSession {
+    fn drop(&mut self) {
+        if !self.closed {
+            self.send(&FromHost::ClearScreen).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::FakeDevice;
+    use shared::metric;
+
+    fn session(device: &FakeDevice) -> GaugeSession {
+        GaugeSession::new(device.link())
+    }
+
+    #[test]
+    fn session_sends_messages() {
+        let device = FakeDevice::default();
+        let mut session = session(&device);
+
+        session
+            .update_metrics(&[Metric::new(metric::CPU_LOAD, 0.5)])
+            .unwrap();
+        session.notify("Deployed", NotifyLevel::Info, 5).unwrap();
+        session.show_page(Page::Top).unwrap();
+        session.close().unwrap();
+
+        let messages = device.messages();
+        assert!(matches!(
+            &messages[..],
+            [
+                FromHost::UpdateMetrics(batch),
+                FromHost::Notify(notification),
+                FromHost::ShowPage(Page::Top),
+                FromHost::ClearScreen,
+            ] if batch.commit && notification.text == "Deployed"
+        ));
+    }
+
+    #[test]
+    fn session_reads_settings() {
+        let device = FakeDevice::default();
+        let settings = Settings::default();
+        device.queue_reply(&FromDevice::Settings(settings));
+        let mut session = session(&device);
+
+        assert_eq!(session.settings().unwrap(), settings);
+        assert!(matches!(session.settings(), Err(Error::DeviceGone(_))));
+    }
+
+    #[test]
+    fn drop_clears_screen() {
+        let device = FakeDevice::default();
+
+        session(&device).sync_time().unwrap();
+
+        assert!(matches!(
+            &device.messages()[..],
+            [FromHost::SetTime(_), FromHost::ClearScreen]
+        ));
+    }
+}
@@ -0,0 +1,287 @@
+use crate::collector::{self, Collector, ScrapeCollector, UsedMemory};
+use crate::stop::StopSignal;
+use serde::Deserialize;
+use shared::message::{HostSummary, HostView, Hosts, HOST_NAME_LEN, MAX_HOSTS};
+use shared::metric::{self, Metric, MetricId};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Hosts that have not reported for this long are marked stale on the device.
+pub const STALE_AFTER: Duration = Duration::from_secs(10);
+
+const DEFAULT_ROTATE_SECS: u16 = 5;
+
+/// Hosts shown on the device's hosts page, as described in the config file.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostsConfig {
+    /// Shows all hosts at once, or cycles through them.
+    pub view: ViewConfig,
+    /// Seconds each host is shown for, while rotating.
+    pub rotate: u16,
+    pub host: Vec<HostConfig>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ViewConfig {
+    #[default]
+    Overview,
+    Rotate,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HostConfig {
+    /// Name shown on the device.
+    pub name: String,
+    /// Prometheus endpoint of the host's node_exporter, or None for this host.
+    pub url: Option<String>,
+}
+
+impl Default for HostsConfig {
+    fn default() -> Self {
+        HostsConfig {
+            view: ViewConfig::default(),
+            rotate: DEFAULT_ROTATE_SECS,
+            host: Vec::new(),
+        }
+    }
+}
+
+impl HostsConfig {
+    /// Checks the hosts fit on the device, returning a description of the problem if not.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.host.len() > MAX_HOSTS {
+            return Err(format!("more than {} hosts", MAX_HOSTS));
+        }
+        // Names are stored in fixed-size buffers on the device, so are limited in bytes.
+        if let Some(host) = self.host.iter().find(|h| h.name.len() > HOST_NAME_LEN) {
+            return Err(format!(
+                "host name {:?} is longer than {} bytes",
+                host.name, HOST_NAME_LEN
+            ));
+        }
+        for url in self.host.iter().filter_map(|h| h.url.as_ref()) {
+            collector::validate_url(url)?;
+        }
+        if self.view == ViewConfig::Rotate && self.rotate == 0 {
+            return Err("hosts must rotate at least every second".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+/// Tracks the latest loads of each configured host, scraping remote hosts from background
+/// threads.
+#[derive(Clone)]
+pub struct HostMonitor {
+    entries: Arc<Mutex<Vec<Entry>>>,
+    view: HostView,
+}
+
+struct Entry {
+    name: heapless::String<HOST_NAME_LEN>,
+    local: bool,
+    cpu_load: f32,
+    memory_load: f32,
+    // When loads were last reported, None until the first report.
+    updated: Option<Instant>,
+}
+
+impl HostMonitor {
+    /// Starts monitoring validated hosts, scraping each remote host from its own thread so
+    /// that slow hosts do not delay the others.  Threads exit once `stop` is signaled.
+    pub fn start(config: &HostsConfig, used: UsedMemory, stop: &Arc<StopSignal>) -> Self {
+        let entries = config
+            .host
+            .iter()
+            .map(|host| Entry {
+                // Names are validated to fit.
+                name: host.name.chars().take(HOST_NAME_LEN).collect(),
+                local: host.url.is_none(),
+                cpu_load: 0.0,
+                memory_load: 0.0,
+                updated: None,
+            })
+            .collect();
+        let view = match config.view {
+            ViewConfig::Overview => HostView::Overview,
+            ViewConfig::Rotate => HostView::Rotate(config.rotate),
+        };
+        let monitor = HostMonitor {
+            entries: Arc::new(Mutex::new(entries)),
+            view,
+        };
+
+        for (index, host) in config.host.iter().enumerate() {
+            if let Some(url) = &host.url {
+                let mut collector = ScrapeCollector::new(url, used, Arc::clone(stop));
+                let monitor = monitor.clone();
+                let stop = Arc::clone(stop);
+                thread::spawn(move || {
+                    while !stop.is_stopped() {
+                        // Blocks for the scrape period, failed scrapes are reported as NaN.
+                        let mut metrics = Vec::new();
+                        if collector.collect(&mut metrics).is_ok() {
+                            monitor.record(index, &metrics, Instant::now());
+                        }
+                    }
+                });
+            }
+        }
+
+        monitor
+    }
+
+    /// Records metrics collected on this host, for hosts without a URL.
+    pub fn record_local(&self, metrics: &[Metric], now: Instant) {
+        let local: Vec<usize> = lock(&self.entries)
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.local)
+            .map(|(index, _)| index)
+            .collect();
+        for index in local {
+            self.record(index, metrics, now);
+        }
+    }
+
+    /// Returns the hosts to show on the device at `now`.
+    pub fn hosts(&self, now: Instant) -> Hosts {
+        let mut hosts = Hosts {
+            hosts: heapless::Vec::new(),
+            view: self.view,
+        };
+        for entry in lock(&self.entries).iter() {
+            let stale = match entry.updated {
+                Some(updated) => now.saturating_duration_since(updated) >= STALE_AFTER,
+                None => true,
+            };
+            let summary = HostSummary {
+                name: entry.name.clone(),
+                cpu_load: entry.cpu_load,
+                memory_load: entry.memory_load,
+                stale,
+            };
+            // Hosts are validated to fit.
+            hosts.hosts.push(summary).ok();
+        }
+
+        hosts
+    }
+
+    // Updates a host from its metrics, hosts with failed sources are left to go stale.
+    fn record(&self, index: usize, metrics: &[Metric], now: Instant) {
+        let value = |id: MetricId| {
+            metrics
+                .iter()
+                .find(|m| m.id == id)
+                .map(|m| m.value)
+                .filter(|v| !v.is_nan())
+        };
+        let (Some(cpu_load), Some(memory_load)) =
+            (value(metric::CPU_LOAD), value(metric::MEM_LOAD))
+        else {
+            return;
+        };
+
+        if let Some(entry) = lock(&self.entries).get_mut(index) {
+            entry.cpu_load = cpu_load;
+            entry.memory_load = memory_load;
+            entry.updated = Some(now);
+        }
+    }
+}
+
+// Entries hold plain data, so remain usable if a thread panicked while holding them.
+fn lock(entries: &Mutex<Vec<Entry>>) -> MutexGuard<'_, Vec<Entry>> {
+    entries.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config(names: &[&str]) -> HostsConfig {
+        HostsConfig {
+            host: names
+                .iter()
+                .map(|name| HostConfig {
+                    name: name.to_string(),
+                    url: None,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn validate_limits() {
+        assert!(config(&["desk", "nas"]).validate().is_ok());
+        assert!(config(&["a", "b", "c", "d", "e"]).validate().is_err());
+        assert!(config(&["thirteen-char"]).validate().is_err());
+        assert!(config(&["überprüfung"]).validate().is_err());
+
+        let mut https = config(&["nas"]);
+        https.host[0].url = Some("https://nas:9100/metrics".to_string());
+        assert!(https.validate().is_err());
+
+        let mut rotate = config(&["desk"]);
+        rotate.view = ViewConfig::Rotate;
+        rotate.rotate = 0;
+        assert!(rotate.validate().is_err());
+    }
+
+    #[test]
+    fn local_host_goes_stale() {
+        let monitor =
+            HostMonitor::start(&config(&["desk"]), UsedMemory::Available, &Arc::default());
+        let now = Instant::now();
+
+        assert!(monitor.hosts(now).hosts[0].stale);
+        monitor.record_local(
+            &[
+                Metric::new(metric::CPU_LOAD, 0.25),
+                Metric::new(metric::MEM_LOAD, 0.5),
+            ],
+            now,
+        );
+
+        let host = &monitor.hosts(now + Duration::from_secs(9)).hosts[0];
+        assert_eq!(host.name.as_str(), "desk");
+        assert_eq!((host.cpu_load, host.memory_load), (0.25, 0.5));
+        assert!(!host.stale);
+        assert!(monitor.hosts(now + STALE_AFTER).hosts[0].stale);
+    }
+
+    #[test]
+    fn failed_source_does_not_update() {
+        let monitor =
+            HostMonitor::start(&config(&["desk"]), UsedMemory::Available, &Arc::default());
+        let now = Instant::now();
+
+        monitor.record_local(
+            &[
+                Metric::new(metric::CPU_LOAD, f32::NAN),
+                Metric::new(metric::MEM_LOAD, 0.5),
+            ],
+            now,
+        );
+
+        assert!(monitor.hosts(now).hosts[0].stale);
+    }
+
+    #[test]
+    fn rotate_view() {
+        let mut config = config(&["desk"]);
+        config.view = ViewConfig::Rotate;
+        config.rotate = 8;
+
+        let monitor = HostMonitor::start(&config, UsedMemory::Available, &Arc::default());
+
+        assert_eq!(monitor.hosts(Instant::now()).view, HostView::Rotate(8));
+    }
+}
@@ -1,92 +1,177 @@
-use avg::Averager;
+use chrono::Timelike;
+use exporter::{CountingPort, Exporter};
 use once_cell::sync::Lazy;
 use serialport::{SerialPort, SerialPortInfo, SerialPortType};
 use shared::message;
-use std::io;
-use std::sync::Mutex;
-use std::thread;
+use shared::time::LocalTime;
 use std::time::Duration;
-use systemstat::{data::CPULoad, Platform, System};
-
+use tasks::Link;
+
+#[cfg(unix)]
+pub use api::{notify, DEFAULT_NOTIFY_SECS};
+pub use collector::Collector;
+pub use config::Config;
+pub use daemon::{Daemon, DaemonBuilder, DaemonHandle};
+pub use error::{Error, Recovery};
+pub use gauge::{Gauge, GaugeSession};
+pub use shared::layout::Layout;
+pub use shared::message::{NotifyLevel, PerfData};
+pub use shared::metric::{Metric, MetricId};
+pub use shared::settings::{Page, Settings};
+pub use shared::theme::Theme;
+
+mod alert;
+#[cfg(unix)]
+mod api;
 mod avg;
+mod collector;
+mod config;
+mod daemon;
+mod device;
+mod error;
+mod exporter;
+mod gauge;
+mod hosts;
+#[cfg(target_os = "linux")]
+mod power;
+mod relay;
+mod stop;
+mod tasks;
+#[cfg(test)]
+mod testing;
+
+/// Delay between attempts to reconnect to the device, doubling with each failure.
+pub const RECONNECT_DELAY: Duration = Duration::from_secs(1);
 
 /// Delay between attempts to detect device USB Serial port.
-pub const DETECT_RETRY_DELAY: Duration = Duration::from_secs(10);
+#[deprecated(note = "use RECONNECT_DELAY, which now doubles with each failure")]
+pub const DETECT_RETRY_DELAY: Duration = RECONNECT_DELAY;
+
+/// Longest delay between attempts to reconnect to the device.
+pub const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Delay between attempts shortly after the host resumes from sleep, while the device
+/// reconnects.
+#[cfg(target_os = "linux")]
+const RESUME_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// How long after resuming from sleep that attempts use the shorter delay.
+#[cfg(target_os = "linux")]
+const RESUME_RETRY_PERIOD: Duration = Duration::from_secs(30);
 
 const USB_VENDOR_ID: u16 = 0x1209; // pid.codes VID.
 const USB_PRODUCT_ID: u16 = 0x0001; // In house private testing only.
 
-const SEND_PERIOD: Duration = Duration::from_secs(1);
-const CPU_POLL_PERIOD: Duration = Duration::from_secs(1);
-const AVG_CPU_SAMPLES: usize = 15; // Seconds of data for CPU average.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
 
-#[derive(PartialEq)]
-enum RunMode {
-    Run,
-    Stop,
+// Daemon stopped by `stop`, and run by `detectsend_loop` with each call's config.
+static DEFAULT: Lazy<Daemon> = Lazy::new(|| Daemon::builder().build());
+
+/// Stops the daemon, clearing the device screen and releasing its port.  May be called from
+/// any thread, including signal handlers' threads.
+pub fn stop() {
+    DEFAULT.stop();
 }
 
-struct ServiceContext {
-    run_mode: RunMode,
+/// Blocks for `timeout`, returning true early if the daemon is stopped.  Also returns early,
+/// with false, when the host resumes from sleep.
+pub fn wait_for_stop(timeout: Duration) -> bool {
+    DEFAULT.wait_for_stop(timeout)
 }
 
-static CONTEXT: Lazy<Mutex<ServiceContext>> = Lazy::new(|| {
-    Mutex::new(ServiceContext {
-        run_mode: RunMode::Run,
-    })
-});
+/// Returns the delay before retrying a failed `detectsend_loop`, backing off exponentially
+/// until a connection succeeds.  Shortened just after the host resumes from sleep.
+pub fn retry_delay() -> Duration {
+    DEFAULT.retry_delay()
+}
 
-#[derive(Debug)]
-pub enum Error {
-    PortNotFound,
-    IO(io::Error),
-    Serial(serialport::Error),
+/// Connects to the device, and sends metrics from the collectors selected by `config` until
+/// stopped, or an error occurs.
+pub fn detectsend_loop(config: &Config) -> Result<(), Error> {
+    DEFAULT.run_with(config)
 }
 
-pub fn stop() {
-    match CONTEXT.lock() {
-        Ok(mut context) => context.run_mode = RunMode::Stop,
-        Err(_) => {
-            log::error!("Failed to lock context in stop (fatal)");
-            panic!("Failed to lock context in stop (fatal)");
+// Long lived services fed by the connection's tasks, None when not configured.
+#[derive(Clone, Default)]
+struct Services {
+    exporter: Option<Exporter>,
+    hosts: Option<hosts::HostMonitor>,
+    #[cfg(unix)]
+    api: Option<api::Server>,
+    #[cfg(target_os = "linux")]
+    power: Option<power::PowerMonitor>,
+}
+
+impl Services {
+    /// Returns why the host is asleep, or None while it is in use.
+    fn asleep(&self) -> Option<message::SleepReason> {
+        #[cfg(target_os = "linux")]
+        if let Some(power) = &self.power {
+            return power.asleep();
         }
-    };
+
+        None
+    }
 }
 
-pub fn detectsend_loop() -> Result<(), Error> {
+/// Opens the link to the device, either directly or through a relay.
+fn open_link(config: &Config) -> Result<Link, Error> {
+    if let Some(addr) = &config.relay.connect {
+        let link = relay_link(addr, &config.relay.secret)?;
+        log::info!("Sending to device through relay at {}", addr);
+        return Ok(link);
+    }
+
     let pinfo = detect_port()?;
-    let mut port = open_port(&pinfo)?;
+    let link = serial_link(&pinfo.port_name)?;
     log::info!("Sending to detected device on port: {}", pinfo.port_name);
+    Ok(link)
+}
+
+/// Opens the device on the serial port named `port_name`.
+fn serial_link(port_name: &str) -> Result<Link, Error> {
+    let port = open_port(port_name)?;
+    let reader = port.try_clone().map_err(Error::Serial)?;
+    Ok(Link {
+        reader,
+        writer: port,
+    })
+}
+
+/// Connects to the device through the relay sink at `addr`.
+fn relay_link(addr: &str, secret: &str) -> Result<Link, Error> {
+    let stream = relay::connect(addr, secret)?;
+    // Reads only wait for replies once connected, so may time out as a port's would.
+    stream
+        .set_read_timeout(Some(REPLY_TIMEOUT))
+        .map_err(Error::IO)?;
+    let reader = stream.try_clone().map_err(Error::IO)?;
+    Ok(Link {
+        reader: Box::new(reader),
+        writer: Box::new(stream),
+    })
+}
 
-    let mut cpu_avg = Averager::new(AVG_CPU_SAMPLES);
-    loop {
-        write_perf_data(&mut port, &mut cpu_avg, daytime())?;
-
-        match CONTEXT.lock() {
-            Ok(context) => {
-                if context.run_mode == RunMode::Stop {
-                    return Ok(());
-                }
-            }
-            Err(_) => {
-                log::error!("Failed to lock context in detect/send loop (fatal)");
-                return Ok(());
-            }
-        };
-
-        // TODO factor in start time for correct period.
-        std::thread::sleep(SEND_PERIOD - CPU_POLL_PERIOD);
+fn record_counts<W>(exporter: Option<&Exporter>, port: &mut CountingPort<W>) {
+    if let Some(exporter) = exporter {
+        let (frames, bytes) = port.take_counts();
+        exporter.update_link(|link| {
+            link.frames_sent += frames;
+            link.bytes_sent += bytes;
+        });
     }
 }
 
 /// Returns true if local time is between 6am and 6pm.
 fn daytime() -> bool {
-    let now = time::OffsetDateTime::now_local();
-    if let Ok(now) = now {
-        return 6 < now.hour() && now.hour() < 18;
-    }
+    let hour = chrono::Local::now().hour();
+    6 < hour && hour < 18
+}
 
-    false
+/// Returns the host's wall clock time, with its current UTC offset applied.
+fn local_time() -> LocalTime {
+    let now = chrono::Local::now();
+    LocalTime(now.timestamp() + i64::from(now.offset().local_minus_utc()))
 }
 
 /// Looks for our monitor hardware on available serial ports.
@@ -103,8 +188,9 @@ fn detect_port() -> Result<SerialPortInfo, Error> {
 }
 
 /// Opens serial port, and sets DTR.
-fn open_port(port_info: &SerialPortInfo) -> Result<Box<dyn SerialPort>, Error> {
-    let mut port = serialport::new(port_info.port_name.clone(), 115200)
+fn open_port(port_name: &str) -> Result<Box<dyn SerialPort>, Error> {
+    let mut port = serialport::new(port_name, 115200)
+        .timeout(REPLY_TIMEOUT)
         .open()
         .map_err(Error::Serial)?;
     port.write_data_terminal_ready(true)
@@ -112,55 +198,3 @@ fn open_port(port_info: &SerialPortInfo) -> Result<Box<dyn SerialPort>, Error> {
 
     Ok(port)
 }
-
-/// CPU load.
-fn write_perf_data(
-    w: &mut Box<dyn SerialPort>,
-    cpu_avg: &mut Averager,
-    daytime: bool,
-) -> Result<(), Error> {
-    fn busy_fraction(load: &CPULoad) -> f32 {
-        1.0f32 - load.idle
-    }
-
-    // Capture CPU metrics.
-    let sys = System::new();
-    let cpu_load = sys.cpu_load().map_err(Error::IO)?;
-    let load_agg = sys.cpu_load_aggregate().map_err(Error::IO)?;
-    thread::sleep(CPU_POLL_PERIOD);
-
-    // Load across all cores.
-    let load_agg = load_agg.done().map_err(Error::IO)?;
-
-    // Select least idle core.
-    let cpu_load = cpu_load.done().map_err(Error::IO)?;
-    let min_idle = cpu_load
-        .iter()
-        .min_by(|a, b| a.idle.partial_cmp(&b.idle).unwrap())
-        .unwrap_or(&load_agg);
-
-    // Average all cores load over time.
-    let all_cores_load = busy_fraction(&load_agg);
-    cpu_avg.add_sample(all_cores_load as f64);
-
-    // Memory usage.
-    let mem = sys.memory().map_err(Error::IO)?;
-    let memory_load = 1.0 - ((mem.free.as_u64() as f32) / (mem.total.as_u64() as f32));
-
-    let perf = message::PerfData {
-        all_cores_load: busy_fraction(&load_agg),
-        all_cores_avg: cpu_avg.average().unwrap_or_default() as f32,
-        peak_core_load: busy_fraction(min_idle),
-        memory_load,
-        daytime,
-    };
-
-    // Serialize into FromHost message.
-    let msg = message::FromHost::ShowPerf(perf);
-    let msg_bytes = postcard::to_allocvec_cobs(&msg).expect("COB serialization failed");
-
-    match w.write_all(&msg_bytes) {
-        Ok(_) => Ok(()),
-        Err(err) => Err(Error::IO(err)),
-    }
-}
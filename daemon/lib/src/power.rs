@@ -0,0 +1,405 @@
+use shared::message::SleepReason;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use zbus::blocking::{connection, Connection, MessageIterator};
+use zbus::zvariant::{OwnedFd, OwnedObjectPath, OwnedValue};
+use zbus::{message, MatchRule, Message};
+
+const LOGIND: &str = "org.freedesktop.login1";
+const LOGIND_PATH: &str = "/org/freedesktop/login1";
+const MANAGER: &str = "org.freedesktop.login1.Manager";
+const SEAT_PATH: &str = "/org/freedesktop/login1/seat/seat0";
+const SEAT: &str = "org.freedesktop.login1.Seat";
+const SESSION: &str = "org.freedesktop.login1.Session";
+const PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+
+/// Time allowed for the sleep screen to be sent before the host is allowed to sleep.
+const SLEEP_GRACE: Duration = Duration::from_millis(250);
+
+/// Tracks whether the host is asleep or its session locked, from logind signals.
+#[derive(Clone)]
+pub struct PowerMonitor {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    sleeping: bool,
+    locked: bool,
+    // When the host last resumed from sleep.
+    resumed: Option<Instant>,
+}
+
+impl PowerMonitor {
+    /// Listens for logind signals on the system bus, or the bus at `address`.  `on_change`
+    /// is called from a background thread as the host sleeps, resumes, locks or unlocks.
+    pub fn start<F>(address: Option<&str>, on_change: F) -> zbus::Result<Self>
+    where
+        F: Fn() + Send + 'static,
+    {
+        let conn = match address {
+            Some(address) => connection::Builder::address(address)?.build()?,
+            None => Connection::system()?,
+        };
+        let mut rules = vec![MatchRule::builder()
+            .msg_type(message::Type::Signal)
+            .sender(LOGIND)?
+            .path(LOGIND_PATH)?
+            .interface(MANAGER)?
+            .member("PrepareForSleep")?
+            .build()];
+        // Other sessions, such as SSH logins or a greeter, may lock without the screen
+        // being locked.
+        match own_session(&conn) {
+            Some(session) => rules.push(
+                MatchRule::builder()
+                    .msg_type(message::Type::Signal)
+                    .sender(LOGIND)?
+                    .path(session)?
+                    .build(),
+            ),
+            None => log::info!("No logind session found, lock is not shown"),
+        }
+
+        // Merges the signals matching each rule, which are received on their own threads.
+        let (msg_tx, msgs) = mpsc::channel();
+        for rule in rules {
+            let messages = MessageIterator::for_match_rule(rule, &conn, None)?;
+            let msg_tx = msg_tx.clone();
+            thread::spawn(move || {
+                for msg in messages {
+                    if msg_tx.send(msg).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(msg_tx);
+
+        let monitor = PowerMonitor {
+            state: Arc::new(Mutex::new(State::default())),
+        };
+
+        let listener = monitor.clone();
+        thread::spawn(move || {
+            // Delays sleep until the device has been told, where logind allows it.
+            let mut inhibitor = inhibit_sleep(&conn);
+            let mut was_sleeping = false;
+            for msg in msgs {
+                let msg = match msg {
+                    Ok(msg) => msg,
+                    Err(err) => {
+                        log::warn!("Failed to receive logind signal: {:?}", err);
+                        continue;
+                    }
+                };
+                match listener.handle(&msg, Instant::now()) {
+                    Ok(false) => {}
+                    Ok(true) => {
+                        on_change();
+                        let sleeping = listener.lock().sleeping;
+                        if sleeping {
+                            thread::sleep(SLEEP_GRACE);
+                            drop(inhibitor.take());
+                        } else if was_sleeping {
+                            inhibitor = inhibitor.or_else(|| inhibit_sleep(&conn));
+                        }
+                        was_sleeping = sleeping;
+                    }
+                    Err(err) => log::warn!("Failed to parse logind signal: {:?}", err),
+                }
+            }
+            log::warn!("Lost connection to logind, sleep and lock are no longer shown");
+        });
+
+        Ok(monitor)
+    }
+
+    /// Returns why the host is asleep, or None while it is in use.
+    pub fn asleep(&self) -> Option<SleepReason> {
+        let state = self.lock();
+        if state.sleeping {
+            Some(SleepReason::Suspend)
+        } else if state.locked {
+            Some(SleepReason::Lock)
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if the host resumed from sleep less than `period` before `now`.
+    pub fn resumed_within(&self, period: Duration, now: Instant) -> bool {
+        match self.lock().resumed {
+            Some(resumed) => now.saturating_duration_since(resumed) < period,
+            None => false,
+        }
+    }
+
+    // Updates the state from a logind signal, returning true if it changed.
+    fn handle(&self, msg: &Message, now: Instant) -> zbus::Result<bool> {
+        let header = msg.header();
+        let (Some(interface), Some(member)) = (header.interface(), header.member()) else {
+            return Ok(false);
+        };
+
+        let mut state = self.lock();
+        match (interface.as_str(), member.as_str()) {
+            (MANAGER, "PrepareForSleep") => {
+                let sleeping: bool = msg.body().deserialize()?;
+                if !sleeping {
+                    state.resumed = Some(now);
+                }
+                Ok(replace(&mut state.sleeping, sleeping))
+            }
+            (SESSION, "Lock") => Ok(replace(&mut state.locked, true)),
+            (SESSION, "Unlock") => Ok(replace(&mut state.locked, false)),
+            // Desktops that lock the screen themselves only report it through LockedHint.
+            (PROPERTIES, "PropertiesChanged") => {
+                let (changed_interface, changed, _): (
+                    String,
+                    HashMap<String, OwnedValue>,
+                    Vec<String>,
+                ) = msg.body().deserialize()?;
+                match changed.get("LockedHint") {
+                    Some(locked) if changed_interface == SESSION => {
+                        let locked = bool::try_from(locked)?;
+                        Ok(replace(&mut state.locked, locked))
+                    }
+                    _ => Ok(false),
+                }
+            }
+            _ => Ok(false),
+        }
+    }
+
+    // State holds plain data, so remains usable if a thread panicked while holding it.
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// Sets `flag` to `value`, returning true if it changed.
+fn replace(flag: &mut bool, value: bool) -> bool {
+    std::mem::replace(flag, value) != value
+}
+
+// Returns the logind session of the daemon, or of its user's display when run as a user
+// service, falling back to the session active on the first seat when run as a system service.
+fn own_session(conn: &Connection) -> Option<OwnedObjectPath> {
+    let own = conn
+        .call_method(
+            Some(LOGIND),
+            LOGIND_PATH,
+            Some(MANAGER),
+            "GetSession",
+            &("auto",),
+        )
+        .and_then(|reply| reply.body().deserialize::<OwnedObjectPath>());
+    match own {
+        Ok(session) => return Some(session),
+        Err(err) => log::debug!("Daemon is not in a logind session: {:?}", err),
+    }
+
+    let active = conn
+        .call_method(
+            Some(LOGIND),
+            SEAT_PATH,
+            Some(PROPERTIES),
+            "Get",
+            &(SEAT, "ActiveSession"),
+        )
+        .and_then(|reply| reply.body().deserialize::<OwnedValue>())
+        .and_then(|value| Ok(<(String, OwnedObjectPath)>::try_from(value)?));
+    match active {
+        Ok((_, session)) => Some(session),
+        Err(err) => {
+            log::debug!("No active logind session: {:?}", err);
+            None
+        }
+    }
+}
+
+// Takes a logind delay inhibitor, which holds off sleep until dropped, or for at most the
+// InhibitDelayMaxSec configured for logind.
+fn inhibit_sleep(conn: &Connection) -> Option<OwnedFd> {
+    let reply = conn.call_method(
+        Some(LOGIND),
+        LOGIND_PATH,
+        Some(MANAGER),
+        "Inhibit",
+        &("sleep", "hw-gauge", "Showing sleep on the device", "delay"),
+    );
+    match reply.and_then(|reply| reply.body().deserialize::<OwnedFd>()) {
+        Ok(fd) => Some(fd),
+        Err(err) => {
+            log::debug!("Sleep is not inhibited: {:?}", err);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+    use zbus::zvariant::ObjectPath;
+
+    const OWN_SESSION: &str = "/org/freedesktop/login1/session/_31";
+
+    // Private bus, standing in for the system bus so tests do not depend on logind.
+    struct Bus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl Bus {
+        // Starts a private bus, which requires dbus-daemon to be installed.
+        fn start() -> Bus {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("power tests require dbus-daemon");
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap())
+                .read_line(&mut address)
+                .unwrap();
+            Bus {
+                daemon,
+                address: address.trim().to_string(),
+            }
+        }
+
+        // Connects as logind, to emit its signals.  GetSession returns OWN_SESSION, and other
+        // method calls, such as taking inhibitors, are refused.
+        fn logind(&self) -> Connection {
+            let conn = connection::Builder::address(self.address.as_str())
+                .unwrap()
+                .build()
+                .unwrap();
+            conn.request_name(LOGIND).unwrap();
+
+            let calls = MatchRule::builder()
+                .msg_type(message::Type::MethodCall)
+                .build();
+            let calls = MessageIterator::for_match_rule(calls, &conn, None).unwrap();
+            let responder = conn.clone();
+            thread::spawn(move || {
+                for call in calls.flatten() {
+                    let header = call.header();
+                    if header.member().is_some_and(|m| m.as_str() == "GetSession") {
+                        let session = ObjectPath::try_from(OWN_SESSION).unwrap();
+                        responder.reply(&header, &session).ok();
+                    } else {
+                        let error = "org.freedesktop.DBus.Error.AccessDenied";
+                        responder.reply_error(&header, error, &"test").ok();
+                    }
+                }
+            });
+
+            conn
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            self.daemon.kill().ok();
+            self.daemon.wait().ok();
+        }
+    }
+
+    fn emit<B>(logind: &Connection, path: &str, interface: &str, member: &str, body: &B)
+    where
+        B: serde::Serialize + zbus::zvariant::DynamicType,
+    {
+        logind
+            .emit_signal(None::<&str>, path, interface, member, body)
+            .unwrap();
+    }
+
+    #[test]
+    fn tracks_sleep_and_lock() {
+        let bus = Bus::start();
+        let logind = bus.logind();
+        let (changed_tx, changed) = mpsc::channel();
+        let monitor = PowerMonitor::start(Some(&bus.address), move || {
+            changed_tx.send(()).ok();
+        })
+        .unwrap();
+        let session = OWN_SESSION;
+        let timeout = Duration::from_secs(5);
+
+        assert_eq!(monitor.asleep(), None);
+        emit(&logind, session, SESSION, "Lock", &());
+        changed.recv_timeout(timeout).unwrap();
+        assert_eq!(monitor.asleep(), Some(SleepReason::Lock));
+
+        emit(&logind, LOGIND_PATH, MANAGER, "PrepareForSleep", &true);
+        changed.recv_timeout(timeout).unwrap();
+        assert_eq!(monitor.asleep(), Some(SleepReason::Suspend));
+
+        emit(&logind, LOGIND_PATH, MANAGER, "PrepareForSleep", &false);
+        changed.recv_timeout(timeout).unwrap();
+        assert_eq!(monitor.asleep(), Some(SleepReason::Lock));
+        assert!(monitor.resumed_within(Duration::from_secs(60), Instant::now()));
+
+        let unlocked: HashMap<&str, zbus::zvariant::Value> =
+            vec![("LockedHint", false.into())].into_iter().collect();
+        emit(
+            &logind,
+            session,
+            PROPERTIES,
+            "PropertiesChanged",
+            &(SESSION, unlocked, Vec::<&str>::new()),
+        );
+        changed.recv_timeout(timeout).unwrap();
+        assert_eq!(monitor.asleep(), None);
+    }
+
+    #[test]
+    fn ignores_other_sessions() {
+        let bus = Bus::start();
+        let logind = bus.logind();
+        let (changed_tx, changed) = mpsc::channel();
+        let monitor = PowerMonitor::start(Some(&bus.address), move || {
+            changed_tx.send(()).ok();
+        })
+        .unwrap();
+
+        emit(
+            &logind,
+            "/org/freedesktop/login1/session/c2",
+            SESSION,
+            "Lock",
+            &(),
+        );
+
+        assert!(changed.recv_timeout(Duration::from_millis(200)).is_err());
+        assert_eq!(monitor.asleep(), None);
+    }
+
+    #[test]
+    fn ignores_other_senders() {
+        let bus = Bus::start();
+        let (changed_tx, changed) = mpsc::channel();
+        let monitor = PowerMonitor::start(Some(&bus.address), move || {
+            changed_tx.send(()).ok();
+        })
+        .unwrap();
+        let impostor = connection::Builder::address(bus.address.as_str())
+            .unwrap()
+            .build()
+            .unwrap();
+
+        emit(&impostor, LOGIND_PATH, MANAGER, "PrepareForSleep", &true);
+
+        assert!(changed.recv_timeout(Duration::from_millis(200)).is_err());
+        assert_eq!(monitor.asleep(), None);
+        assert!(!monitor.resumed_within(Duration::from_secs(60), Instant::now()));
+    }
+}
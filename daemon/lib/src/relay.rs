@@ -0,0 +1,415 @@
+use crate::device::{self, is_timeout, FrameReader};
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use shared::message::{FromDevice, FromHost};
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{self, error::TryRecvError};
+use tokio_util::sync::CancellationToken;
+
+// Bytes of random challenge sent to each collector.
+const NONCE_LEN: usize = 16;
+
+/// Time allowed for each handshake message, and for replies relayed from the device.
+const RELAY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Collectors sending nothing for this long are disconnected, freeing the device for others.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Interval between checks for collectors connecting, or for the sink to stop.
+const POLL_PERIOD: Duration = Duration::from_millis(200);
+
+// Interval between checks for a reply from the device.
+const REPLY_POLL_PERIOD: Duration = Duration::from_millis(10);
+
+/// Messages exchanged to authenticate a collector, before it sends device frames.
+#[derive(Debug, Deserialize, Serialize)]
+enum Handshake {
+    // Random nonce, sent by the sink.
+    Challenge([u8; NONCE_LEN]),
+    // HMAC-SHA256 of the nonce keyed with the shared secret, sent by the collector.
+    Response([u8; 32]),
+    Accepted,
+    Rejected,
+}
+
+/// Accepts collectors on a TCP port, relaying their frames to the device attached to this
+/// host.  One collector is served at a time.
+pub struct Sink {
+    listener: TcpListener,
+    addr: SocketAddr,
+    secret: String,
+}
+
+// Distinguishes failures of the collector connection, which the sink outlives, from
+// failures of the device.
+enum Failure {
+    Collector(Error),
+    Device(Error),
+}
+
+impl Sink {
+    /// Listens for collectors on `addr`, which must authenticate with `secret`.
+    pub fn bind(addr: &str, secret: &str) -> io::Result<Sink> {
+        let listener = TcpListener::bind(addr)?;
+        // Polled, so the sink can be stopped while waiting for collectors.
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+
+        Ok(Sink {
+            listener,
+            addr,
+            secret: secret.to_string(),
+        })
+    }
+
+    /// Returns the address being listened on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Relays messages from each authenticated collector in turn to the device's writer
+    /// through `writes`, and device replies from `replies` back to the collector, until
+    /// `cancel` is cancelled.  Returns an error if the device's writer or reader has ended.
+    pub fn serve(
+        &self,
+        writes: &mpsc::Sender<FromHost>,
+        replies: &mut mpsc::Receiver<FromDevice>,
+        cancel: &CancellationToken,
+    ) -> Result<(), Error> {
+        loop {
+            if cancel.is_cancelled() {
+                return Ok(());
+            }
+            let (mut stream, peer) = match self.listener.accept() {
+                Ok(accepted) => accepted,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(POLL_PERIOD);
+                    continue;
+                }
+                Err(err) => return Err(Error::IO(err)),
+            };
+
+            if let Err(err) = self.authenticate(&mut stream) {
+                log::warn!("Collector {} failed to authenticate: {}", peer, err);
+                continue;
+            }
+            log::info!("Relaying frames from collector {}", peer);
+            match forward(stream, writes, replies, cancel) {
+                Ok(()) => return Ok(()),
+                Err(Failure::Collector(err)) => {
+                    log::info!("Collector {} disconnected: {}", peer, err)
+                }
+                Err(Failure::Device(err)) => return Err(err),
+            }
+        }
+    }
+
+    // Challenges the collector to prove it holds the shared secret.
+    fn authenticate(&self, stream: &mut TcpStream) -> Result<(), Error> {
+        stream.set_nonblocking(false).map_err(Error::IO)?;
+        stream
+            .set_read_timeout(Some(RELAY_TIMEOUT))
+            .map_err(Error::IO)?;
+        stream
+            .set_write_timeout(Some(RELAY_TIMEOUT))
+            .map_err(Error::IO)?;
+
+        let mut nonce = [0u8; NONCE_LEN];
+        getrandom::getrandom(&mut nonce).map_err(|err| Error::IO(err.into()))?;
+        send(stream, &Handshake::Challenge(nonce))?;
+
+        let valid = match receive(stream)? {
+            Handshake::Response(mac) => {
+                hmac_sha256::HMAC::verify(nonce, self.secret.as_bytes(), &mac)
+            }
+            _ => false,
+        };
+        if !valid {
+            send(stream, &Handshake::Rejected)?;
+            return Err(Error::IO(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "incorrect secret",
+            )));
+        }
+
+        send(stream, &Handshake::Accepted)
+    }
+}
+
+/// Connects to the sink at `addr`, authenticating with `secret`.  The returned stream
+/// carries frames to the sink's device, and the device's replies back.
+pub fn connect(addr: &str, secret: &str) -> Result<TcpStream, Error> {
+    let mut stream = TcpStream::connect(addr).map_err(Error::IO)?;
+    stream.set_nodelay(true).map_err(Error::IO)?;
+    stream
+        .set_read_timeout(Some(RELAY_TIMEOUT))
+        .map_err(Error::IO)?;
+    stream
+        .set_write_timeout(Some(RELAY_TIMEOUT))
+        .map_err(Error::IO)?;
+
+    let nonce = match receive(&mut stream)? {
+        Handshake::Challenge(nonce) => nonce,
+        other => {
+            return Err(Error::IO(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected challenge from relay, received {:?}", other),
+            )))
+        }
+    };
+    let mac = hmac_sha256::HMAC::mac(nonce, secret.as_bytes());
+    send(&mut stream, &Handshake::Response(mac))?;
+
+    match receive(&mut stream)? {
+        Handshake::Accepted => Ok(stream),
+        _ => Err(Error::IO(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "relay rejected secret",
+        ))),
+    }
+}
+
+// Relays frames from the collector until it disconnects, idles, or sends a malformed frame.
+fn forward(
+    stream: TcpStream,
+    writes: &mpsc::Sender<FromHost>,
+    replies: &mut mpsc::Receiver<FromDevice>,
+    cancel: &CancellationToken,
+) -> Result<(), Failure> {
+    let collector = |err| Failure::Collector(Error::Collector(err));
+    stream
+        .set_read_timeout(Some(POLL_PERIOD))
+        .map_err(collector)?;
+    let mut reply_stream = stream.try_clone().map_err(collector)?;
+    let mut frames = FrameReader::new(stream);
+    let mut received = Instant::now();
+
+    loop {
+        if cancel.is_cancelled() {
+            return Ok(());
+        }
+        let mut frame = match frames.read_frame() {
+            Ok(frame) => frame,
+            Err(err) if is_timeout(&err) => {
+                if received.elapsed() >= IDLE_TIMEOUT {
+                    return Err(collector(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "collector idle",
+                    )));
+                }
+                continue;
+            }
+            Err(err) => {
+                return Err(Failure::Collector(device::frame_error(
+                    err,
+                    Error::Collector,
+                )))
+            }
+        };
+        received = Instant::now();
+
+        // Only well formed messages are passed on to the device.
+        let message: FromHost = postcard::from_bytes_cobs(&mut frame)
+            .map_err(|err| Failure::Collector(Error::Decode(err)))?;
+        let request = matches!(message, FromHost::GetSettings | FromHost::SetSettings(_));
+        if request {
+            // Replies arriving after an earlier request timed out would be mistaken for this
+            // request's.
+            while replies.try_recv().is_ok() {}
+        }
+        writes
+            .blocking_send(message)
+            .map_err(|_| Failure::Device(device_gone()))?;
+
+        if request {
+            match device_reply(replies, cancel)? {
+                Some(reply) => {
+                    let frame = postcard::to_allocvec_cobs(&reply)
+                        .map_err(|err| Failure::Collector(Error::Encode(err)))?;
+                    reply_stream.write_all(&frame).map_err(collector)?;
+                }
+                // Older firmware does not reply, leaving the collector to time out.
+                None => log::debug!("No reply from device to settings request"),
+            }
+        }
+    }
+}
+
+// Waits for the device to reply to a request, returning None if it does not reply in time.
+fn device_reply(
+    replies: &mut mpsc::Receiver<FromDevice>,
+    cancel: &CancellationToken,
+) -> Result<Option<FromDevice>, Failure> {
+    let deadline = Instant::now() + crate::REPLY_TIMEOUT;
+    loop {
+        match replies.try_recv() {
+            Ok(reply) => return Ok(Some(reply)),
+            Err(TryRecvError::Disconnected) => return Err(Failure::Device(device_gone())),
+            Err(TryRecvError::Empty) if cancel.is_cancelled() || Instant::now() >= deadline => {
+                return Ok(None)
+            }
+            Err(TryRecvError::Empty) => thread::sleep(REPLY_POLL_PERIOD),
+        }
+    }
+}
+
+// Error for the device's writer or reader having ended, which report why themselves.
+fn device_gone() -> Error {
+    Error::DeviceGone(io::ErrorKind::BrokenPipe.into())
+}
+
+fn send(stream: &mut TcpStream, msg: &Handshake) -> Result<(), Error> {
+    let frame = postcard::to_allocvec_cobs(msg).map_err(Error::Encode)?;
+    stream.write_all(&frame).map_err(Error::IO)
+}
+
+fn receive(stream: &mut TcpStream) -> Result<Handshake, Error> {
+    let mut frame =
+        device::read_frame(stream).map_err(|err| device::frame_error(err, Error::IO))?;
+    postcard::from_bytes_cobs(&mut frame).map_err(Error::Decode)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::FakeDevice;
+    use shared::settings::{Page, Settings};
+    use std::io::Read;
+    use std::thread::JoinHandle;
+
+    const SECRET: &str = "hunter2";
+
+    // Stands in for the device's writer and reader tasks, replying to settings requests,
+    // until the sink stops.  A broken device ends at once.
+    fn spawn_device(
+        mut device: FakeDevice,
+        mut writes: mpsc::Receiver<FromHost>,
+        replies: mpsc::Sender<FromDevice>,
+    ) -> JoinHandle<()> {
+        thread::spawn(move || {
+            if device.broken {
+                return;
+            }
+            while let Some(msg) = writes.blocking_recv() {
+                if matches!(msg, FromHost::GetSettings) {
+                    let settings = FromDevice::Settings(Settings::default());
+                    replies.blocking_send(settings).unwrap();
+                }
+                device::write_message(&mut device, &msg).unwrap();
+            }
+        })
+    }
+
+    struct Running {
+        addr: String,
+        cancel: CancellationToken,
+        handle: JoinHandle<Result<(), Error>>,
+    }
+
+    impl Running {
+        fn stop(self) -> Result<(), Error> {
+            self.cancel.cancel();
+            self.handle.join().unwrap()
+        }
+    }
+
+    fn start_sink(device: FakeDevice) -> Running {
+        let sink = Sink::bind("127.0.0.1:0", SECRET).unwrap();
+        let addr = sink.local_addr().to_string();
+        let (writes, write_queue) = mpsc::channel(1);
+        let (replies_tx, mut replies) = mpsc::channel(1);
+        spawn_device(device, write_queue, replies_tx);
+        let cancel = CancellationToken::new();
+        let handle = thread::spawn({
+            let cancel = cancel.clone();
+            move || sink.serve(&writes, &mut replies, &cancel)
+        });
+
+        Running {
+            addr,
+            cancel,
+            handle,
+        }
+    }
+
+    #[test]
+    fn relays_frames_and_replies() {
+        let device = FakeDevice::default();
+        let sink = start_sink(device.clone());
+
+        let mut stream = connect(&sink.addr, SECRET).unwrap();
+        device::write_message(&mut stream, &FromHost::ClearScreen).unwrap();
+        device::write_message(&mut stream, &FromHost::GetSettings).unwrap();
+        let mut reply = device::read_frame(&mut stream).unwrap();
+        let reply = device::decode_message(&mut reply).unwrap();
+
+        assert!(matches!(reply, FromDevice::Settings(s) if s == Settings::default()));
+        assert!(matches!(
+            device.wait_for(2)[..],
+            [FromHost::ClearScreen, FromHost::GetSettings]
+        ));
+        assert!(sink.stop().is_ok());
+    }
+
+    #[test]
+    fn rejects_incorrect_secret() {
+        let sink = start_sink(FakeDevice::default());
+
+        let result = connect(&sink.addr, "hunter3");
+
+        assert!(
+            matches!(result, Err(Error::IO(err)) if err.kind() == io::ErrorKind::PermissionDenied)
+        );
+        sink.stop().unwrap();
+    }
+
+    #[test]
+    fn accepts_collector_after_disconnect() {
+        let device = FakeDevice::default();
+        let sink = start_sink(device.clone());
+
+        let mut first = connect(&sink.addr, SECRET).unwrap();
+        device::write_message(&mut first, &FromHost::ClearScreen).unwrap();
+        device.wait_for(1);
+        drop(first);
+        let mut second = connect(&sink.addr, SECRET).unwrap();
+        device::write_message(&mut second, &FromHost::ShowPage(Page::Top)).unwrap();
+
+        assert!(matches!(
+            device.wait_for(2)[..],
+            [FromHost::ClearScreen, FromHost::ShowPage(Page::Top)]
+        ));
+        sink.stop().unwrap();
+    }
+
+    #[test]
+    fn disconnects_malformed_frames() {
+        let device = FakeDevice::default();
+        let sink = start_sink(device.clone());
+
+        let mut stream = connect(&sink.addr, SECRET).unwrap();
+        stream.write_all(&[0x05, 0xff, 0x00]).unwrap();
+        let mut buf = [0u8; 1];
+
+        assert_eq!(stream.read(&mut buf).unwrap(), 0);
+        assert!(device.messages().is_empty());
+        sink.stop().unwrap();
+    }
+
+    #[test]
+    fn device_failure_ends_serve() {
+        let device = FakeDevice::broken();
+        let sink = start_sink(device);
+
+        let mut stream = connect(&sink.addr, SECRET).unwrap();
+        device::write_message(&mut stream, &FromHost::ClearScreen).unwrap();
+
+        assert!(matches!(
+            sink.handle.join().unwrap(),
+            Err(Error::DeviceGone(_))
+        ));
+    }
+}
@@ -0,0 +1,154 @@
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// Signals the daemon to stop, waking any threads waiting on it, and cancelling any tasks.
+#[derive(Default)]
+pub struct StopSignal {
+    state: Mutex<State>,
+    wake: Condvar,
+    token: CancellationToken,
+}
+
+#[derive(Default)]
+struct State {
+    stopped: bool,
+    // Incremented by each early wake, so waiters can tell they were woken.
+    wakes: u64,
+}
+
+impl StopSignal {
+    /// Stops the daemon, waking all waiting threads and cancelling all tasks.
+    pub fn stop(&self) {
+        self.lock().stopped = true;
+        self.wake.notify_all();
+        self.token.cancel();
+    }
+
+    /// Wakes all waiting threads early without stopping, such as when the host resumes from
+    /// sleep, or a connection ends.
+    pub fn wake(&self) {
+        self.lock().wakes += 1;
+        self.wake.notify_all();
+    }
+
+    /// Returns true once stopped.
+    pub fn is_stopped(&self) -> bool {
+        self.lock().stopped
+    }
+
+    /// Returns a token cancelled upon stopping, which may also be cancelled on its own to end
+    /// a single connection.
+    pub fn child_token(&self) -> CancellationToken {
+        self.token.child_token()
+    }
+
+    /// Blocks for `timeout`, in place of sleeping.  Returns early if woken, and true if
+    /// stopped.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        self.wait_until(timeout, None)
+    }
+
+    /// As `wait`, also returning early once `cancel` is cancelled, provided that cancelling it
+    /// is followed by a wake.
+    pub fn wait_unless_cancelled(&self, timeout: Duration, cancel: &CancellationToken) -> bool {
+        self.wait_until(timeout, Some(cancel))
+    }
+
+    fn wait_until(&self, timeout: Duration, cancel: Option<&CancellationToken>) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.lock();
+        let wakes = state.wakes;
+        // Checked with the lock held, so a cancel before a wake is never missed.
+        while !state.stopped
+            && state.wakes == wakes
+            && !cancel.is_some_and(CancellationToken::is_cancelled)
+        {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            state = self
+                .wake
+                .wait_timeout(state, remaining)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+
+        state.stopped
+    }
+
+    // Holds plain flags, so remains usable if a thread panicked while holding them.
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    // Waits on the signal from another thread, returning its result and how long it took.
+    fn spawn_waiter(signal: &Arc<StopSignal>) -> thread::JoinHandle<(bool, Duration)> {
+        let signal = signal.clone();
+        thread::spawn(move || {
+            let start = Instant::now();
+            (signal.wait(Duration::from_secs(60)), start.elapsed())
+        })
+    }
+
+    #[test]
+    fn wait_times_out() {
+        let signal = StopSignal::default();
+        let start = Instant::now();
+
+        assert!(!signal.wait(Duration::from_millis(20)));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert!(!signal.is_stopped());
+    }
+
+    #[test]
+    fn stop_wakes_waiters() {
+        let signal = Arc::new(StopSignal::default());
+        let waiter = spawn_waiter(&signal);
+
+        thread::sleep(Duration::from_millis(20));
+        signal.stop();
+
+        let (stopped, elapsed) = waiter.join().unwrap();
+        assert!(stopped);
+        assert!(elapsed < Duration::from_secs(10));
+        assert!(signal.wait(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn stop_cancels_child_tokens() {
+        let signal = StopSignal::default();
+        let connection = signal.child_token();
+        let other = signal.child_token();
+
+        connection.cancel();
+        assert!(!signal.is_stopped());
+        assert!(!other.is_cancelled());
+
+        signal.stop();
+        assert!(other.is_cancelled());
+        assert!(signal.child_token().is_cancelled());
+    }
+
+    #[test]
+    fn wake_does_not_stop() {
+        let signal = Arc::new(StopSignal::default());
+        let waiter = spawn_waiter(&signal);
+
+        thread::sleep(Duration::from_millis(20));
+        signal.wake();
+
+        let (stopped, elapsed) = waiter.join().unwrap();
+        assert!(!stopped);
+        assert!(elapsed < Duration::from_secs(10));
+        assert!(!signal.is_stopped());
+    }
+}
@@ -0,0 +1,657 @@
+use crate::collector::Collector;
+use crate::config::DeviceConfig;
+use crate::exporter::{CountingPort, Exporter};
+use crate::relay::Sink;
+use crate::stop::StopSignal;
+use crate::{alert, device, record_counts, Config, Error, Recovery, Services};
+use shared::message::{FromDevice, FromHost, SleepReason};
+use shared::metric::{self, Metric, MetricBatch};
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{self, error::SendError};
+use tokio::sync::watch;
+use tokio::task::{self, JoinHandle};
+use tokio_util::sync::CancellationToken;
+
+/// Shortest period between sends, should collectors be failing rather than blocking.
+const SEND_PERIOD: Duration = Duration::from_secs(1);
+
+// Longest wait while the host sleeps, though waits end as soon as it resumes.
+const ASLEEP_POLL_PERIOD: Duration = Duration::from_secs(60);
+
+// Period between setting the device clock, which drifts and misses UTC offset changes.
+const TIME_SYNC_PERIOD: Duration = Duration::from_secs(60);
+
+// Messages queued for the device writer, enough for a send split into several batches.
+const WRITE_QUEUE_LEN: usize = 16;
+
+/// Link to the device, either a serial port or a relay connection, split so that waiting for
+/// replies does not hold up writes.
+pub(crate) struct Link {
+    pub reader: Box<dyn Read + Send>,
+    pub writer: Box<dyn Write + Send>,
+}
+
+/// Tasks writing to and reading from the device, with the queue of messages to write.
+struct DeviceTasks {
+    writes: mpsc::Sender<FromHost>,
+    writer: JoinHandle<Result<(), Error>>,
+    reader: JoinHandle<Result<(), Error>>,
+}
+
+/// Output of the collector task.
+enum Collected {
+    Metrics(Vec<Metric>),
+    Asleep(SleepReason),
+}
+
+/// Serves the device over `link` until `stop` is signaled, or a task fails.  The device
+/// settings, theme and layout are sent first, then metrics from `collectors`.  Upon stopping,
+/// the device screen is cleared so it does not show stale metrics.
+///
+/// Collecting, writing and reading each block, so run as blocking tasks, passing messages
+/// through channels.  Any task failing cancels the connection's token, ending the others.
+pub(crate) async fn serve(
+    link: Link,
+    collectors: Vec<Box<dyn Collector>>,
+    config: &Config,
+    services: Services,
+    stop: Arc<StopSignal>,
+) -> Result<(), Error> {
+    let cancel = stop.child_token();
+    let (device, mut replies) = DeviceTasks::spawn(link, services.exporter.clone(), &cancel);
+    let writes = &device.writes;
+
+    let mut collector = None;
+    let mut control = None;
+    let setup = send_setup(writes, &mut replies, config).await;
+    if setup.is_ok() {
+        let (samples_tx, samples) = mpsc::channel(1);
+        collector = Some(task::spawn_blocking({
+            let cancel = cancel.clone();
+            let stop = Arc::clone(&stop);
+            let services = services.clone();
+            move || collect_metrics(collectors, services, &stop, samples_tx, cancel)
+        }));
+        let (asleep_tx, asleep) = watch::channel(None);
+        control = spawn_control(services.clone(), writes.clone(), asleep, cancel.clone());
+        send_samples(samples, writes, &asleep_tx, config, &services, &cancel)
+            .await
+            .ok();
+    }
+
+    // Ends the remaining tasks, with waking ending any cancellable waits.
+    cancel.cancel();
+    stop.wake();
+    let finished = device.finish(&stop).await;
+    if let Some(control) = control {
+        join(control).await;
+    }
+    let collected = match collector {
+        Some(collector) => join(collector).await,
+        None => Ok(()),
+    };
+    finished.and(collected)
+}
+
+/// Relays messages from remote collectors accepted by `sink` to the device over `link`,
+/// until `stop` is signaled, or the device fails.  Upon stopping, the device screen is
+/// cleared, discarding any batch a collector was part way through.
+pub(crate) async fn relay(
+    link: Link,
+    sink: Arc<Sink>,
+    exporter: Option<Exporter>,
+    stop: Arc<StopSignal>,
+) -> Result<(), Error> {
+    let cancel = stop.child_token();
+    let (device, mut replies) = DeviceTasks::spawn(link, exporter, &cancel);
+    let relayed = join(task::spawn_blocking({
+        let writes = device.writes.clone();
+        let cancel = cancel.clone();
+        move || sink.serve(&writes, &mut replies, &cancel)
+    }))
+    .await;
+
+    cancel.cancel();
+    device.finish(&stop).await.and(relayed)
+}
+
+impl DeviceTasks {
+    /// Spawns the writer and reader of `link`, returning them with the receiver of device
+    /// replies.  Either failing cancels `cancel`.
+    fn spawn(
+        link: Link,
+        exporter: Option<Exporter>,
+        cancel: &CancellationToken,
+    ) -> (Self, mpsc::Receiver<FromDevice>) {
+        let Link { reader, writer } = link;
+        let (writes, write_queue) = mpsc::channel(WRITE_QUEUE_LEN);
+        let (replies_tx, replies) = mpsc::channel(1);
+        let writer = task::spawn_blocking({
+            let cancel = cancel.clone();
+            let port = CountingPort::new(writer);
+            move || write_messages(port, write_queue, exporter.as_ref(), cancel)
+        });
+        let reader = task::spawn_blocking({
+            let cancel = cancel.clone();
+            move || read_messages(reader, replies_tx, cancel)
+        });
+
+        let tasks = DeviceTasks {
+            writes,
+            writer,
+            reader,
+        };
+        (tasks, replies)
+    }
+
+    /// Clears the device screen if stopped, then waits for the writer to drain its queue, once
+    /// other senders are dropped, and for the reader, which must have been cancelled.
+    async fn finish(self, stop: &StopSignal) -> Result<(), Error> {
+        if stop.is_stopped() {
+            log::info!("Stopping, clearing device screen");
+            self.writes.send(FromHost::ClearScreen).await.ok();
+        }
+        drop(self.writes);
+
+        let written = join(self.writer).await;
+        let read = join(self.reader).await;
+        written.and(read)
+    }
+}
+
+// Sends device settings, theme and layout.  Only fails if the writer has stopped, whose
+// result then explains why.
+async fn send_setup(
+    writes: &mpsc::Sender<FromHost>,
+    replies: &mut mpsc::Receiver<FromDevice>,
+    config: &Config,
+) -> Result<(), SendError<FromHost>> {
+    if !config.device.is_empty() {
+        // Older firmware does not reply to settings requests, so this is not fatal.
+        if let Err(err) = sync_settings(writes, replies, &config.device).await {
+            log::warn!("Failed to sync device settings: {}", err);
+        }
+    }
+
+    if let Some(theme) = config.theme.theme() {
+        // The device only persists the theme if it has changed.
+        writes.send(FromHost::SetTheme(theme)).await?;
+    }
+    if let Some(layout) = &config.layout {
+        writes.send(FromHost::SetLayout(layout.clone())).await?;
+    }
+
+    Ok(())
+}
+
+/// Updates device settings to match the config.  Settings are only written when they
+/// differ, as the device persists them to flash.
+async fn sync_settings(
+    writes: &mpsc::Sender<FromHost>,
+    replies: &mut mpsc::Receiver<FromDevice>,
+    device: &DeviceConfig,
+) -> Result<(), Error> {
+    send_request(writes, FromHost::GetSettings).await?;
+    let FromDevice::Settings(current) = reply(replies).await?;
+
+    let desired = device.apply(current);
+    if desired == current {
+        log::debug!("Device settings up to date: {:?}", current);
+        return Ok(());
+    }
+
+    log::info!("Updating device settings: {:?}", desired);
+    send_request(writes, FromHost::SetSettings(desired)).await?;
+    reply(replies).await?;
+
+    Ok(())
+}
+
+async fn send_request(writes: &mpsc::Sender<FromHost>, msg: FromHost) -> Result<(), Error> {
+    writes
+        .send(msg)
+        .await
+        .map_err(|_| Error::DeviceGone(io::ErrorKind::BrokenPipe.into()))
+}
+
+// Waits for the device to reply to a request.
+async fn reply(replies: &mut mpsc::Receiver<FromDevice>) -> Result<FromDevice, Error> {
+    match tokio::time::timeout(crate::REPLY_TIMEOUT, replies.recv()).await {
+        Ok(Some(msg)) => Ok(msg),
+        Ok(None) => Err(Error::DeviceGone(io::ErrorKind::BrokenPipe.into())),
+        Err(_) => Err(Error::DeviceGone(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "no reply from device",
+        ))),
+    }
+}
+
+// Forwards collected metrics to the device writer until cancelled, or the collector task
+// ends.  While the host is asleep or locked, the device shows so in place of metrics.
+async fn send_samples(
+    mut samples: mpsc::Receiver<Collected>,
+    writes: &mpsc::Sender<FromHost>,
+    asleep: &watch::Sender<Option<SleepReason>>,
+    config: &Config,
+    services: &Services,
+    cancel: &CancellationToken,
+) -> Result<(), SendError<FromHost>> {
+    let mut alert_monitor = alert::AlertMonitor::new(&config.alert);
+    let mut time_synced: Option<Instant> = None;
+    loop {
+        let collected = tokio::select! {
+            _ = cancel.cancelled() => return Ok(()),
+            collected = samples.recv() => match collected {
+                Some(collected) => collected,
+                None => return Ok(()),
+            },
+        };
+
+        let metrics = match collected {
+            Collected::Asleep(reason) => {
+                if *asleep.borrow() != Some(reason) {
+                    log::info!("Host asleep ({:?}), pausing metrics", reason);
+                    writes.send(FromHost::Sleep(reason)).await?;
+                    asleep.send_replace(Some(reason));
+                }
+                continue;
+            }
+            Collected::Metrics(metrics) => metrics,
+        };
+        if asleep.send_replace(None).is_some() {
+            log::info!("Host awake, resuming metrics");
+        }
+
+        if time_synced.is_none_or(|synced| synced.elapsed() >= TIME_SYNC_PERIOD) {
+            writes.send(FromHost::SetTime(crate::local_time())).await?;
+            time_synced = Some(Instant::now());
+        }
+
+        let now = Instant::now();
+        if let Some(hosts) = &services.hosts {
+            hosts.record_local(&metrics, now);
+            writes.send(FromHost::SetHosts(hosts.hosts(now))).await?;
+        }
+
+        // Alerts are resent with each sample, so a lost frame cannot leave the device
+        // alerting after the condition clears.
+        let alerts = alert_monitor.update(&metrics, now);
+        writes.send(FromHost::SetAlerts(alerts)).await?;
+        for msg in metric_messages(&metrics) {
+            writes.send(msg).await?;
+        }
+        if let Some(exporter) = &services.exporter {
+            exporter.record_samples(&metrics);
+        }
+    }
+}
+
+/// Returns messages sending metrics in as many batches as required, committing them together.
+fn metric_messages(metrics: &[Metric]) -> impl Iterator<Item = FromHost> + '_ {
+    MetricBatch::split(metrics).map(FromHost::UpdateMetrics)
+}
+
+/// Collects metrics until cancelled, blocking in collectors which set the send period.  While
+/// the host is asleep, collectors are not run.
+fn collect_metrics(
+    mut collectors: Vec<Box<dyn Collector>>,
+    services: Services,
+    stop: &StopSignal,
+    samples: mpsc::Sender<Collected>,
+    cancel: CancellationToken,
+) -> Result<(), Error> {
+    while !cancel.is_cancelled() {
+        if let Some(reason) = services.asleep() {
+            if samples.blocking_send(Collected::Asleep(reason)).is_err() {
+                break;
+            }
+            stop.wait_unless_cancelled(ASLEEP_POLL_PERIOD, &cancel);
+            continue;
+        }
+
+        let started = Instant::now();
+        let mut metrics = vec![Metric::new(
+            metric::DAYTIME,
+            if crate::daytime() { 1.0 } else { 0.0 },
+        )];
+        for collector in collectors.iter_mut() {
+            if let Err(err) = collector.collect(&mut metrics) {
+                cancel.cancel();
+                return Err(err);
+            }
+        }
+        // Samples cut short by stopping are not worth sending.
+        if cancel.is_cancelled() {
+            break;
+        }
+        if samples.blocking_send(Collected::Metrics(metrics)).is_err() {
+            break;
+        }
+        stop.wait_unless_cancelled(SEND_PERIOD.saturating_sub(started.elapsed()), &cancel);
+    }
+
+    Ok(())
+}
+
+/// Writes queued messages to the device until the queue is closed.  Failure to write cancels
+/// the connection.
+fn write_messages<W: Write>(
+    mut port: CountingPort<W>,
+    mut queue: mpsc::Receiver<FromHost>,
+    exporter: Option<&Exporter>,
+    cancel: CancellationToken,
+) -> Result<(), Error> {
+    while let Some(msg) = queue.blocking_recv() {
+        if let Err(err) = device::write_message(&mut port, &msg) {
+            cancel.cancel();
+            return Err(err);
+        }
+        record_counts(exporter, &mut port);
+    }
+
+    Ok(())
+}
+
+/// Reads messages from the device until cancelled, passing them on as replies.  Failure to
+/// read, other than timing out, cancels the connection.
+fn read_messages<R: Read>(
+    reader: R,
+    replies: mpsc::Sender<FromDevice>,
+    cancel: CancellationToken,
+) -> Result<(), Error> {
+    let mut frames = device::FrameReader::new(reader);
+    while !cancel.is_cancelled() {
+        let mut frame = match frames.read_frame() {
+            Ok(frame) => frame,
+            Err(err) if device::is_timeout(&err) => continue,
+            Err(err) => match device::frame_error(err, Error::DeviceGone) {
+                err if err.recovery() == Recovery::DiscardFrame => {
+                    log::debug!("Ignored message from device: {}", err);
+                    continue;
+                }
+                err => {
+                    cancel.cancel();
+                    return Err(err);
+                }
+            },
+        };
+        match device::decode_message(&mut frame) {
+            Ok(msg) => {
+                if replies.try_send(msg).is_err() {
+                    log::debug!("Dropped unrequested message from device");
+                }
+            }
+            Err(err) => log::debug!("Ignored message from device: {}", err),
+        }
+    }
+
+    Ok(())
+}
+
+/// Spawns the task forwarding API requests to the device, if the API is configured.
+#[cfg(unix)]
+fn spawn_control(
+    services: Services,
+    writes: mpsc::Sender<FromHost>,
+    mut asleep: watch::Receiver<Option<SleepReason>>,
+    cancel: CancellationToken,
+) -> Option<JoinHandle<()>> {
+    let server = services.api?;
+    Some(tokio::spawn(async move {
+        loop {
+            // Requests are held while the host is asleep, to be shown once it wakes.
+            let next = async {
+                asleep.wait_for(Option::is_none).await.ok();
+                server.received().await
+            };
+            let received = tokio::select! {
+                _ = cancel.cancelled() => return,
+                received = next => received,
+            };
+
+            let page = received.page.map(FromHost::ShowPage);
+            let notifications = received.notifications.into_iter().map(FromHost::Notify);
+            for msg in page.into_iter().chain(notifications) {
+                if writes.send(msg).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }))
+}
+
+/// The API is only supported on unix.
+#[cfg(not(unix))]
+fn spawn_control(
+    _services: Services,
+    _writes: mpsc::Sender<FromHost>,
+    _asleep: watch::Receiver<Option<SleepReason>>,
+    _cancel: CancellationToken,
+) -> Option<JoinHandle<()>> {
+    None
+}
+
+// Waits for a task, resuming its panic should it have panicked.
+async fn join<T>(handle: JoinHandle<T>) -> T {
+    match handle.await {
+        Ok(result) => result,
+        Err(err) => std::panic::resume_unwind(err.into_panic()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::{run, FakeDevice};
+    use std::thread;
+
+    // Returns at once when first called, then blocks as a slow collector would, until stopped.
+    struct BlockingCollector {
+        stop: Arc<StopSignal>,
+        calls: usize,
+    }
+
+    impl Collector for BlockingCollector {
+        fn collect(&mut self, metrics: &mut Vec<Metric>) -> Result<(), Error> {
+            if self.calls > 0 {
+                self.stop.wait(Duration::from_secs(60));
+            }
+            self.calls += 1;
+            metrics.push(Metric::new(metric::CPU_LOAD, 0.5));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn metric_messages_commit_final_batch() {
+        let metrics: Vec<_> = (0..10)
+            .map(|i| Metric::new(metric::MetricId(metric::FIRST_CUSTOM.0 + i), 1.0))
+            .collect();
+
+        let commits: Vec<bool> = metric_messages(&metrics)
+            .map(|msg| match msg {
+                FromHost::UpdateMetrics(batch) => batch.commit,
+                other => panic!("unexpected message {:?}", other),
+            })
+            .collect();
+
+        assert_eq!(commits, vec![false, true]);
+    }
+
+    #[test]
+    fn full_linux_sample_fits_device_queue() {
+        // Every well-known metric, plus the top processes, as a Linux host sends each second.
+        let mut metrics: Vec<Metric> = metric::WELL_KNOWN
+            .iter()
+            .map(|info| Metric::new(info.id, 0.5))
+            .collect();
+        for rank in 0..metric::TOP_COUNT {
+            for id in [metric::top_cpu(rank), metric::top_mem(rank)] {
+                let mut top = Metric::new(id, 0.1);
+                top.label = Some(metric::MetricLabel::from("kworker/"));
+                metrics.push(top);
+            }
+        }
+        let config = Config::parse("[[hosts.host]]\nname = \"desk\"").unwrap();
+        let stop = Arc::new(StopSignal::default());
+        let services = Services {
+            hosts: Some(crate::hosts::HostMonitor::start(
+                &config.hosts,
+                config.memory.used,
+                &stop,
+            )),
+            ..Default::default()
+        };
+
+        let (samples_tx, samples) = mpsc::channel(1);
+        samples_tx
+            .try_send(Collected::Metrics(metrics.clone()))
+            .unwrap();
+        drop(samples_tx);
+        let (writes, mut queue) = mpsc::channel(64);
+        let (asleep, _) = watch::channel(None);
+        let result = run(send_samples(
+            samples,
+            &writes,
+            &asleep,
+            &config,
+            &services,
+            &CancellationToken::new(),
+        ));
+        stop.stop();
+        drop(writes);
+        let mut sent = Vec::new();
+        while let Ok(msg) = queue.try_recv() {
+            sent.push(msg);
+        }
+
+        assert!(result.is_ok());
+        let batches = metrics.len().div_ceil(metric::MAX_BATCH);
+        let updates = sent
+            .iter()
+            .filter(|msg| matches!(msg, FromHost::UpdateMetrics(_)))
+            .count();
+        assert_eq!(updates, batches);
+        // SetTime, SetHosts and SetAlerts accompany the batches.
+        assert_eq!(sent.len(), batches + 3);
+        assert!(
+            sent.len() < shared::message::FRAME_QUEUE_LEN,
+            "{} frames per second overflow the device queue",
+            sent.len()
+        );
+        for msg in &sent {
+            let mut frame = Vec::new();
+            device::write_message(&mut frame, msg).unwrap();
+            assert!(frame.len() <= shared::message::MAX_FRAME_BYTES);
+        }
+    }
+
+    #[test]
+    fn alerts_resent_each_sample() {
+        let (samples_tx, samples) = mpsc::channel(2);
+        for _ in 0..2 {
+            let metrics = vec![Metric::new(metric::CPU_LOAD, 0.5)];
+            samples_tx.try_send(Collected::Metrics(metrics)).unwrap();
+        }
+        drop(samples_tx);
+        let (writes, mut queue) = mpsc::channel(64);
+        let (asleep, _) = watch::channel(None);
+        let result = run(send_samples(
+            samples,
+            &writes,
+            &asleep,
+            &Config::default(),
+            &Services::default(),
+            &CancellationToken::new(),
+        ));
+        drop(writes);
+        let mut alerts = 0;
+        while let Ok(msg) = queue.try_recv() {
+            if matches!(msg, FromHost::SetAlerts(_)) {
+                alerts += 1;
+            }
+        }
+
+        assert!(result.is_ok());
+        assert_eq!(alerts, 2);
+    }
+
+    #[test]
+    fn stop_clears_screen() {
+        let stop = Arc::new(StopSignal::default());
+        let collectors: Vec<Box<dyn Collector>> = vec![Box::new(BlockingCollector {
+            stop: stop.clone(),
+            calls: 0,
+        })];
+        let device = FakeDevice::default();
+
+        let stopper = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(100));
+                stop.stop();
+                Instant::now()
+            })
+        };
+        let result = run(serve(
+            device.link(),
+            collectors,
+            &Config::default(),
+            Services::default(),
+            stop,
+        ));
+        let elapsed = stopper.join().unwrap().elapsed();
+
+        assert!(result.is_ok());
+        assert!(elapsed < Duration::from_secs(10), "took {:?}", elapsed);
+        let messages = device.messages();
+        assert!(matches!(messages.first(), Some(FromHost::SetTime(_))));
+        assert!(messages
+            .iter()
+            .any(|m| matches!(m, FromHost::UpdateMetrics(_))));
+        assert!(matches!(messages.last(), Some(FromHost::ClearScreen)));
+    }
+
+    #[test]
+    fn relay_stop_clears_screen() {
+        let stop = Arc::new(StopSignal::default());
+        let sink = Arc::new(Sink::bind("127.0.0.1:0", "hunter2").unwrap());
+        let device = FakeDevice::default();
+
+        let stopper = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(100));
+                stop.stop();
+            })
+        };
+        let result = run(relay(device.link(), sink, None, stop));
+        stopper.join().unwrap();
+
+        assert!(result.is_ok());
+        assert!(matches!(device.messages()[..], [FromHost::ClearScreen]));
+    }
+
+    #[test]
+    fn device_failure_ends_serve() {
+        let stop = Arc::new(StopSignal::default());
+        let collectors: Vec<Box<dyn Collector>> = vec![Box::new(BlockingCollector {
+            stop: stop.clone(),
+            calls: 0,
+        })];
+        let device = FakeDevice::broken();
+
+        let result = run(serve(
+            device.link(),
+            collectors,
+            &Config::default(),
+            Services::default(),
+            stop.clone(),
+        ));
+
+        assert!(matches!(result, Err(Error::DeviceGone(_))));
+        assert!(!stop.is_stopped());
+    }
+}
@@ -0,0 +1,160 @@
+use crate::tasks::Link;
+use shared::message::{FromDevice, FromHost};
+use std::collections::VecDeque;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Longest a test waits for messages to be written.
+const WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Path in the temporary directory, unique to a test, and removed along with anything
+/// created at it when dropped, even if the test panics.
+pub(crate) struct TempPath(PathBuf);
+
+impl TempPath {
+    /// Reserves a path named for `name`, removing anything left by an earlier run.
+    pub(crate) fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("hw-gauge-{}-{}", name, std::process::id()));
+        remove(&path);
+        TempPath(path)
+    }
+
+    /// Creates an empty directory named for `name`.
+    pub(crate) fn dir(name: &str) -> Self {
+        let dir = TempPath::new(name);
+        fs::create_dir_all(&dir.0).unwrap();
+        dir
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<OsStr> for TempPath {
+    fn as_ref(&self) -> &OsStr {
+        self.0.as_os_str()
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        remove(&self.0);
+    }
+}
+
+fn remove(path: &Path) {
+    if path.is_dir() {
+        let _ = fs::remove_dir_all(path);
+    } else {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// Device recording frames written to it, and replying with queued bytes.  Reads time out
+/// once no reply is queued, and writes fail once broken.
+#[derive(Clone, Default)]
+pub(crate) struct FakeDevice {
+    written: Arc<Mutex<Vec<u8>>>,
+    replies: Arc<Mutex<VecDeque<u8>>>,
+    pub(crate) broken: bool,
+}
+
+impl FakeDevice {
+    /// Creates a device failing every write, as though unplugged.
+    pub(crate) fn broken() -> Self {
+        FakeDevice {
+            broken: true,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn link(&self) -> Link {
+        Link {
+            reader: Box::new(self.clone()),
+            writer: Box::new(self.clone()),
+        }
+    }
+
+    /// Queues `msg` to be read back, as though the device replied with it.
+    pub(crate) fn queue_reply(&self, msg: &FromDevice) {
+        let frame = postcard::to_allocvec_cobs(msg).unwrap();
+        lock(&self.replies).extend(frame);
+    }
+
+    /// Decodes the frames written so far, skipping any still part way through being written.
+    pub(crate) fn messages(&self) -> Vec<FromHost> {
+        lock(&self.written)
+            .split_inclusive(|b| *b == 0)
+            .filter(|f| f.ends_with(&[0]))
+            .map(|f| postcard::from_bytes_cobs(&mut f.to_vec()).unwrap())
+            .collect()
+    }
+
+    /// Waits for `count` messages to be written, returning those written by then.
+    pub(crate) fn wait_for(&self, count: usize) -> Vec<FromHost> {
+        let start = Instant::now();
+        while self.messages().len() < count && start.elapsed() < WAIT_TIMEOUT {
+            thread::sleep(Duration::from_millis(10));
+        }
+        self.messages()
+    }
+}
+
+impl Read for FakeDevice {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match lock(&self.replies).pop_front() {
+            Some(byte) if !buf.is_empty() => {
+                buf[0] = byte;
+                Ok(1)
+            }
+            _ => {
+                thread::sleep(Duration::from_millis(10));
+                Err(io::ErrorKind::TimedOut.into())
+            }
+        }
+    }
+}
+
+impl Write for FakeDevice {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.broken {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        lock(&self.written).extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Runs `future` to completion on a runtime of its own.
+pub(crate) fn run<F: std::future::Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
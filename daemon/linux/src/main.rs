@@ -1,16 +1,93 @@
-use log::{info, warn};
+use log::{error, info, warn};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::path::PathBuf;
 
 fn main() {
     env_logger::init();
 
-    loop {
-        match lib::detectsend_loop() {
-            Ok(()) => break,
+    // Sends a notification to an already running daemon.
+    if std::env::args().nth(1).as_deref() == Some("notify") {
+        if let Err(e) = notify(std::env::args().skip(2)) {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Optional path to TOML config file.
+    let config = match std::env::args_os().nth(1).map(PathBuf::from) {
+        Some(path) => match lib::Config::load(&path) {
+            Ok(config) => config,
             Err(e) => {
-                warn!("Error: {:?}", e,);
-                info!("Retrying in {:?}", lib::DETECT_RETRY_DELAY);
+                error!("Failed to load config {}: {}", path.display(), e);
+                std::process::exit(1);
             }
+        },
+        None => lib::Config::default(),
+    };
+
+    let daemon = lib::Daemon::builder().config(config).build();
+    if let Err(e) = handle_signals(daemon.handle()) {
+        error!("Failed to register signal handlers: {}", e);
+        std::process::exit(1);
+    }
+
+    daemon.run();
+    info!("Stopped");
+}
+
+/// Stops the daemon gracefully upon SIGINT or SIGTERM, clearing the device screen.  A second
+/// signal exits immediately, in case the device is not responding.
+fn handle_signals(daemon: lib::DaemonHandle) -> std::io::Result<()> {
+    let mut signals = Signals::new([SIGINT, SIGTERM])?;
+    std::thread::spawn(move || {
+        let mut signals = signals.forever();
+        if let Some(signal) = signals.next() {
+            info!("Received signal {}, stopping", signal);
+            daemon.stop();
+        }
+        if signals.next().is_some() {
+            warn!("Received second signal, exiting immediately");
+            std::process::exit(1);
+        }
+    });
+
+    Ok(())
+}
+
+const NOTIFY_USAGE: &str =
+    "usage: hw-gauge-daemon notify [--level info|warning|error] [--duration SECS] SOCKET TEXT...";
+
+/// Parses notify command arguments, and sends the notification via the API socket.
+fn notify(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut level = lib::NotifyLevel::Info;
+    let mut duration = lib::DEFAULT_NOTIFY_SECS;
+    let mut positional = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--level" => {
+                level = args
+                    .next()
+                    .and_then(|l| l.parse().ok())
+                    .ok_or(NOTIFY_USAGE)?;
+            }
+            "--duration" => {
+                duration = args
+                    .next()
+                    .and_then(|d| d.parse().ok())
+                    .ok_or(NOTIFY_USAGE)?;
+            }
+            _ => positional.push(arg),
         }
-        std::thread::sleep(lib::DETECT_RETRY_DELAY);
     }
+    if positional.len() < 2 {
+        return Err(NOTIFY_USAGE.to_string());
+    }
+
+    let socket = PathBuf::from(positional.remove(0));
+    let text = positional.join(" ");
+    lib::notify(&socket, &text, level, duration)
+        .map_err(|e| format!("Failed to notify {}: {}", socket.display(), e))
 }
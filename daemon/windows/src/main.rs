@@ -17,6 +17,7 @@ use windows_service::{
 const SERVICE_NAME: &str = "hw-gauge";
 const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
 const LOG_FILE: &str = "hw-gauge-service.log";
+const CONFIG_FILE: &str = "hw-gauge.toml";
 
 define_windows_service!(ffi_service_main, service_main);
 
@@ -37,15 +38,18 @@ fn service_main(_args: Vec<OsString>) {
 }
 
 fn service_wrapper() -> Result<(), windows_service::Error> {
+    let daemon = lib::Daemon::builder().config(load_config()).build();
+
     // Setup status tracking mutex and service event callback.
-    let event_handler = |control_event| -> ServiceControlHandlerResult {
+    let handle = daemon.handle();
+    let event_handler = move |control_event| -> ServiceControlHandlerResult {
         debug!(
             "Received Windows service control event: {:?}",
             control_event
         );
         match control_event {
             ServiceControl::Stop => {
-                lib::stop();
+                handle.stop();
                 ServiceControlHandlerResult::NoError
             }
             ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
@@ -65,15 +69,19 @@ fn service_wrapper() -> Result<(), windows_service::Error> {
         process_id: None,
     })?;
 
+    // Connection failures are logged as errors, so they stand out in the service log.
     loop {
-        match lib::detectsend_loop() {
+        match daemon.run_once() {
             Ok(()) => break,
             Err(e) => {
-                error!("{:?}", e);
-                info!("Retrying in {:?}", lib::DETECT_RETRY_DELAY);
+                error!("{}", e);
+                let delay = daemon.retry_delay();
+                info!("Retrying in {:?}", delay);
+                if daemon.wait_for_stop(delay) {
+                    break;
+                }
             }
         }
-        std::thread::sleep(lib::DETECT_RETRY_DELAY);
     }
 
     debug!("Notifying Windows that the service has stopped");
@@ -90,6 +98,29 @@ fn service_wrapper() -> Result<(), windows_service::Error> {
     Ok(())
 }
 
+/// Loads the config file alongside the service executable, if present.
+fn load_config() -> lib::Config {
+    let path = match std::env::current_exe() {
+        Ok(exe) => exe.with_file_name(CONFIG_FILE),
+        Err(e) => {
+            error!("Failed to locate service executable: {:?}", e);
+            return lib::Config::default();
+        }
+    };
+
+    if !path.exists() {
+        return lib::Config::default();
+    }
+
+    match lib::Config::load(&path) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Failed to load config {}: {}", path.display(), e);
+            lib::Config::default()
+        }
+    }
+}
+
 /// Opens the log file in TEMP/TMP and registers a global logger.
 fn init_logging() {
     let mut path = std::env::temp_dir();
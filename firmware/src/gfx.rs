@@ -1,50 +1,67 @@
+use crate::perf::{History, Metrics, MetricsMeta, HISTORY_LEN};
+use core::fmt::Write;
 use embedded_graphics::{
     mono_font::{MonoFont, MonoTextStyleBuilder},
-    pixelcolor::Rgb565,
+    pixelcolor::{raw::RawU16, Rgb565},
     prelude::*,
-    primitives::{PrimitiveStyle, PrimitiveStyleBuilder, Rectangle},
-    text::Text,
+    primitives::{Circle, PrimitiveStyle, PrimitiveStyleBuilder, Rectangle, Triangle},
+    text::{Baseline, Text},
+};
+use heapless::{String, Vec};
+use shared::{
+    layout::{Align, Format, Layout, Widget},
+    message::{Alerts, HostView, Hosts, Notification, NotifyLevel, SleepReason, HOST_NAME_LEN},
+    metric::{self, MetricId, Unit},
+    theme::{Color, Theme, Threshold},
+    time::DateTime,
 };
-use heapless::String;
-use shared::message;
 
 const DISP_WIDTH: i32 = 240;
+const DISP_HEIGHT: i32 = 135;
 const DISP_X_PAD: i32 = 3;
 const DISP_Y_PAD: i32 = 3;
 const FONT: MonoFont = embedded_graphics::mono_font::ascii::FONT_10X20;
+const SMALL_FONT: MonoFont = embedded_graphics::mono_font::ascii::FONT_6X10;
+const LIST_FONT: MonoFont = embedded_graphics::mono_font::ascii::FONT_7X13;
+const LIST_LINE_HEIGHT: i32 = 21;
+const LIST_BAR_HEIGHT: u32 = 2;
+const COLUMN_WIDTH: i32 = (DISP_WIDTH - DISP_X_PAD * 2) / 2;
+const PRESSURE_GAP: u32 = 3;
+const PRESSURE_WIDTH: u32 = (BAR_WIDTH - PRESSURE_GAP * 2) / 3;
+// Pressure levels where the strip changes to warning and critical colors, 0-1.0.
+const PRESSURE_WARN: f32 = 0.05;
+const PRESSURE_CRIT: f32 = 0.20;
+const FREQ_BAR_WIDTH: u32 = BAR_WIDTH / 2;
+const BATTERY_SIZE: Size = Size::new(22, 12);
+const BATTERY_NUB: Size = Size::new(2, 6);
+const BATTERY_WIDTH: i32 = 60;
+// Charge level where the battery is drawn in the critical color, 0-1.0.
+const BATTERY_LOW: f32 = 0.15;
 const LINE_Y_PAD: i32 = 6;
 const BAR_WIDTH: u32 = (DISP_WIDTH - DISP_X_PAD * 2) as u32;
 const BAR_HEIGHT: u32 = 15;
+const SWAP_BAR_GAP: i32 = 2;
+const SWAP_BAR_HEIGHT: u32 = 4;
+const SPARK_WIDTH: u32 = BAR_WIDTH / HISTORY_LEN as u32;
+const ALERT_ICON_SIZE: Size = Size::new(16, 14);
+const NOTICE_PAD: u32 = 4;
+const NOTICE_BORDER: u32 = 2;
+const NOTICE_LINES: usize = 3;
+const HOST_ROW_HEIGHT: i32 = 25;
+const HOST_BAR_HEIGHT: u32 = 8;
+const HOST_BAR_GAP: i32 = 2;
+const HOST_NAME_WIDTH: i32 = HOST_NAME_LEN as i32 * LIST_FONT.character_size.width as i32;
+const CLOCK_DIGIT_SIZE: Size = Size::new(36, 64);
+const CLOCK_SEGMENT_WIDTH: u32 = 6;
+const CLOCK_DIGIT_GAP: i32 = 8;
+const CLOCK_COLON_WIDTH: i32 = 16;
+const CLOCK_DATE_GAP: i32 = 10;
+const SLEEP_MOON_DIAMETER: u32 = 40;
+const SLEEP_GAP: i32 = 12;
 const BACKGROUND_COLOR: Rgb565 = Rgb565::BLACK;
+const SLEEP_COLOR: Rgb565 = Rgb565::new(12, 24, 12);
 const TEXT_COLOR: Rgb565 = Rgb565::WHITE;
 
-struct ColorScheme {
-    background: Rgb565,
-    cpu_text: Rgb565,
-    cpu_bar_avg: Rgb565,
-    cpu_bar_peak: Rgb565,
-    mem_text: Rgb565,
-    mem_bar: Rgb565,
-}
-
-const DAY_COLORS: ColorScheme = ColorScheme {
-    background: Rgb565::new(24, 48, 24),
-    cpu_text: Rgb565::BLACK,
-    cpu_bar_avg: Rgb565::new(10, 10, 22),
-    cpu_bar_peak: Rgb565::new(15, 30, 28),
-    mem_text: Rgb565::BLACK,
-    mem_bar: Rgb565::new(7, 43, 11),
-};
-
-const NIGHT_COLORS: ColorScheme = ColorScheme {
-    background: Rgb565::BLACK,
-    cpu_text: Rgb565::new(24, 48, 24),
-    cpu_bar_avg: Rgb565::new(10, 10, 22),
-    cpu_bar_peak: Rgb565::new(3, 3, 8),
-    mem_text: Rgb565::new(24, 48, 24),
-    mem_bar: Rgb565::new(0, 30, 3),
-};
-
 // Renders a simple text message, for errors, etc.
 pub fn draw_message<T>(display: &mut T, msg: &str) -> Result<(), T::Error>
 where
@@ -62,117 +79,841 @@ where
     Ok(())
 }
 
-// Renders the full performance display.
-pub fn draw_perf<T>(display: &mut T, perf: &message::PerfData) -> Result<(), T::Error>
+/// Renders the idle screen, a large clock showing hours and minutes with the date beneath.
+pub fn draw_clock<T>(display: &mut T, time: &DateTime) -> Result<(), T::Error>
+where
+    T: DrawTarget<Color = Rgb565>,
+{
+    let digit_width = CLOCK_DIGIT_SIZE.width as i32;
+    let clock_width = digit_width * 4 + CLOCK_DIGIT_GAP * 2 + CLOCK_COLON_WIDTH;
+    let clock_height = CLOCK_DIGIT_SIZE.height as i32;
+    let date_height = FONT.character_size.height as i32;
+    let top = (DISP_HEIGHT - clock_height - CLOCK_DATE_GAP - date_height) / 2;
+    let mut left = (DISP_WIDTH - clock_width) / 2;
+
+    display.clear(BACKGROUND_COLOR)?;
+    for (index, digit) in [
+        time.hour / 10,
+        time.hour % 10,
+        time.minute / 10,
+        time.minute % 10,
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        draw_digit(display, Point::new(left, top), digit)?;
+        left += digit_width;
+        match index {
+            0 | 2 => left += CLOCK_DIGIT_GAP,
+            1 => {
+                // Colon dots, at a third and two thirds of the digit height.
+                let dot = CLOCK_SEGMENT_WIDTH;
+                let x = left + (CLOCK_COLON_WIDTH - dot as i32) / 2;
+                for y in [clock_height / 3, clock_height * 2 / 3] {
+                    Rectangle::new(Point::new(x, top + y - dot as i32 / 2), Size::new(dot, dot))
+                        .into_styled(PrimitiveStyle::with_fill(TEXT_COLOR))
+                        .draw(display)?;
+                }
+                left += CLOCK_COLON_WIDTH;
+            }
+            _ => {}
+        }
+    }
+
+    let mut date: String<16> = String::new();
+    write!(
+        date,
+        "{} {}-{:02}-{:02}",
+        time.weekday.abbrev(),
+        time.year,
+        time.month,
+        time.day
+    )
+    .ok();
+    let text_style = MonoTextStyleBuilder::new()
+        .font(&FONT)
+        .text_color(TEXT_COLOR)
+        .build();
+    let date_width = date.len() as i32 * FONT.character_size.width as i32;
+    Text::with_baseline(
+        &date,
+        Point::new(
+            (DISP_WIDTH - date_width) / 2,
+            top + clock_height + CLOCK_DATE_GAP,
+        ),
+        text_style,
+        Baseline::Top,
+    )
+    .draw(display)?;
+
+    Ok(())
+}
+
+// Draws a seven segment digit, 0-9, with its top left corner at `origin`.
+fn draw_digit<T>(display: &mut T, origin: Point, digit: u8) -> Result<(), T::Error>
+where
+    T: DrawTarget<Color = Rgb565>,
+{
+    // Segments lit for each digit, from bit 0 for the top segment, clockwise, to bit 6 for
+    // the middle segment.
+    const SEGMENTS: [u8; 10] = [0x3f, 0x06, 0x5b, 0x4f, 0x66, 0x6d, 0x7d, 0x07, 0x7f, 0x6f];
+
+    let (width, height) = (CLOCK_DIGIT_SIZE.width, CLOCK_DIGIT_SIZE.height);
+    let seg = CLOCK_SEGMENT_WIDTH;
+    let half = height as i32 / 2;
+    let right = width as i32 - seg as i32;
+    let bottom = height as i32 - seg as i32;
+    let horizontal = Size::new(width, seg);
+    let upper = Size::new(seg, height / 2);
+    let lower = Size::new(seg, height - height / 2);
+    let segments = [
+        (Point::new(0, 0), horizontal),
+        (Point::new(right, 0), upper),
+        (Point::new(right, half), lower),
+        (Point::new(0, bottom), horizontal),
+        (Point::new(0, half), lower),
+        (Point::new(0, 0), upper),
+        (Point::new(0, half - seg as i32 / 2), horizontal),
+    ];
+
+    let lit = SEGMENTS[digit as usize % SEGMENTS.len()];
+    for (bit, (offset, size)) in segments.iter().enumerate() {
+        if lit & (1 << bit) != 0 {
+            Rectangle::new(origin + *offset, *size)
+                .into_styled(PrimitiveStyle::with_fill(TEXT_COLOR))
+                .draw(display)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Renders the screen shown while the host is asleep or locked, a crescent moon and the
+/// reason, in a dim color to suit the dimmed backlight.
+pub fn draw_sleep<T>(display: &mut T, reason: SleepReason) -> Result<(), T::Error>
 where
     T: DrawTarget<Color = Rgb565>,
 {
-    let colors = if perf.daytime {
-        DAY_COLORS
-    } else {
-        NIGHT_COLORS
+    let text = match reason {
+        SleepReason::Suspend => "Sleeping",
+        SleepReason::Lock => "Locked",
     };
+    let diameter = SLEEP_MOON_DIAMETER as i32;
+    let text_width = text.len() as i32 * FONT.character_size.width as i32;
+    let left = (DISP_WIDTH - diameter - SLEEP_GAP - text_width) / 2;
+    let top = (DISP_HEIGHT - diameter) / 2;
 
-    let cpu_text_style = MonoTextStyleBuilder::new()
+    display.clear(BACKGROUND_COLOR)?;
+    // The crescent is cut from a full moon by an offset circle of background.
+    Circle::new(Point::new(left, top), SLEEP_MOON_DIAMETER)
+        .into_styled(PrimitiveStyle::with_fill(SLEEP_COLOR))
+        .draw(display)?;
+    Circle::new(
+        Point::new(left + diameter / 3, top - diameter / 6),
+        SLEEP_MOON_DIAMETER,
+    )
+    .into_styled(PrimitiveStyle::with_fill(BACKGROUND_COLOR))
+    .draw(display)?;
+
+    let text_style = MonoTextStyleBuilder::new()
         .font(&FONT)
-        .text_color(colors.cpu_text)
+        .text_color(SLEEP_COLOR)
         .build();
+    Text::with_baseline(
+        text,
+        Point::new(left + diameter + SLEEP_GAP, top + diameter / 2),
+        text_style,
+        Baseline::Middle,
+    )
+    .draw(display)?;
+
+    Ok(())
+}
+
+/// Everything needed to render the performance page.
+pub struct PerfPage<'a> {
+    pub layout: &'a Layout,
+    pub metrics: &'a Metrics,
+    pub meta: &'a MetricsMeta,
+    pub history: &'a History,
+    pub theme: &'a Theme,
+    pub daytime: bool,
+    pub alerts: &'a Alerts,
+    // Phase of alert flashing, bars of alerting metrics are highlighted while true.
+    pub flash: bool,
+    pub hosts: &'a Hosts,
+}
+
+// Renders the full performance display, as described by the layout.
+pub fn draw_perf<T>(display: &mut T, page: &PerfPage) -> Result<(), T::Error>
+where
+    T: DrawTarget<Color = Rgb565>,
+{
+    let colors = page.theme.palette(page.daytime);
+
+    // Clear and begin drawing.
+    display.clear(rgb(colors.background))?;
+
+    for widget in &page.layout.widgets {
+        draw_widget(display, widget, page)?;
+    }
+    draw_alert_icon(display, page)?;
+
+    Ok(())
+}
+
+// Renders the animated widgets of the layout, can be used without clearing the screen first.
+pub fn draw_animated<T>(display: &mut T, page: &PerfPage) -> Result<(), T::Error>
+where
+    T: DrawTarget<Color = Rgb565>,
+{
+    for widget in page.layout.widgets.iter().filter(|w| w.is_animated()) {
+        draw_widget(display, widget, page)?;
+    }
+
+    Ok(())
+}
+
+// Renders the top page: processes ranked by CPU in the left column, and memory in the right.
+pub fn draw_top<T>(display: &mut T, page: &PerfPage) -> Result<(), T::Error>
+where
+    T: DrawTarget<Color = Rgb565>,
+{
+    let colors = page.theme.palette(page.daytime);
+    display.clear(rgb(colors.background))?;
 
-    let mem_text_style = MonoTextStyleBuilder::new()
+    let title_style = MonoTextStyleBuilder::new()
         .font(&FONT)
-        .text_color(colors.mem_text)
+        .text_color(rgb(colors.text))
         .build();
+    let list_style = MonoTextStyleBuilder::new()
+        .font(&LIST_FONT)
+        .text_color(rgb(colors.text))
+        .build();
+
+    let columns = [
+        ("CPU", metric::top_cpu as fn(usize) -> MetricId),
+        ("RAM", metric::top_mem),
+    ];
+    for (col, (title, id)) in columns.iter().enumerate() {
+        let x = DISP_X_PAD + col as i32 * COLUMN_WIDTH;
+        Text::new(title, text_point(x, 0), title_style).draw(display)?;
+
+        for rank in 0..metric::TOP_COUNT {
+            let id = id(rank);
+            let (Some(value), Some(name)) = (
+                page.metrics.get(id),
+                page.meta.get(id).and_then(|m| m.label.as_ref()),
+            ) else {
+                continue;
+            };
+
+            let top = line_y_offset(1) + rank as i32 * LIST_LINE_HEIGHT;
+            let mut text: String<24> = String::new();
+            let _ = write!(text, "{:<8}{}%", name, percent_string(value, false));
+            Text::new(
+                &text,
+                Point::new(x, top + LIST_FONT.baseline as i32),
+                list_style,
+            )
+            .draw(display)?;
+
+            // Proportional bar beneath each entry.
+            let width = (value.clamp(0.0, 1.0) * (COLUMN_WIDTH - DISP_X_PAD) as f32) as u32;
+            Rectangle::new(
+                Point::new(x, top + LIST_FONT.character_size.height as i32 + 1),
+                Size::new(width, LIST_BAR_HEIGHT),
+            )
+            .into_styled(
+                PrimitiveStyleBuilder::new()
+                    .fill_color(rgb(page.theme.bar_color(colors.bar, value)))
+                    .build(),
+            )
+            .draw(display)?;
+        }
+    }
+    draw_alert_icon(display, page)?;
+
+    Ok(())
+}
+
+// Renders the hosts page: either a row per host, or one host at a time, changing every
+// rotation period of `now_secs`.
+pub fn draw_hosts<T>(display: &mut T, page: &PerfPage, now_secs: u64) -> Result<(), T::Error>
+where
+    T: DrawTarget<Color = Rgb565>,
+{
+    let theme = page.theme;
+    let colors = theme.palette(page.daytime);
+    let warn = rgb(theme.warn.unwrap_or(Threshold::DEFAULT_WARN).color);
+    display.clear(rgb(colors.background))?;
 
-    let mem_bar_style = PrimitiveStyleBuilder::new()
-        .fill_color(colors.mem_bar)
+    let text_style = MonoTextStyleBuilder::new()
+        .font(&FONT)
+        .text_color(rgb(colors.text))
+        .build();
+    let stale_style = MonoTextStyleBuilder::new()
+        .font(&FONT)
+        .text_color(warn)
+        .build();
+    let background = PrimitiveStyleBuilder::new()
+        .fill_color(rgb(colors.background))
         .build();
+    let bar = |value: f32| Bar {
+        value,
+        style: PrimitiveStyleBuilder::new()
+            .fill_color(rgb(theme.bar_color(colors.bar, value)))
+            .build(),
+    };
 
-    // Clear and begin drawing.
-    display.clear(colors.background)?;
+    let hosts = &page.hosts.hosts;
+    if hosts.is_empty() {
+        Text::new("No hosts", text_point(DISP_X_PAD, 0), text_style).draw(display)?;
+        return Ok(());
+    }
 
-    // CPU heading.
-    Text::new("CPU", text_point(DISP_X_PAD, 0), cpu_text_style).draw(display)?;
+    match page.hosts.view {
+        HostView::Overview => {
+            Text::new("Hosts", text_point(DISP_X_PAD, 0), text_style).draw(display)?;
+            let stale = hosts.iter().filter(|h| h.stale).count();
+            if stale > 0 {
+                let mut text: String<12> = String::new();
+                let _ = write!(text, "{} stale", stale);
+                Text::new(&text, text_point_right(0, &text), stale_style).draw(display)?;
+            }
 
-    // Average CPU percent display, right aligned.
-    let mut avg = percent_string(perf.all_cores_avg, true);
-    avg.push_str("% Avg").unwrap();
-    Text::new(
-        avg.as_str(),
-        text_point_right(0, avg.as_str()),
-        cpu_text_style,
-    )
-    .draw(display)?;
+            let list_style = |color| {
+                MonoTextStyleBuilder::new()
+                    .font(&LIST_FONT)
+                    .text_color(color)
+                    .build()
+            };
+            let bar_x = DISP_X_PAD + HOST_NAME_WIDTH + DISP_X_PAD;
+            let bar_size = Size::new((DISP_WIDTH - DISP_X_PAD - bar_x) as u32, HOST_BAR_HEIGHT);
+            for (i, host) in hosts.iter().enumerate() {
+                let top = line_y_offset(1) + i as i32 * HOST_ROW_HEIGHT;
+                let name_y = top + (HOST_ROW_HEIGHT - LIST_FONT.character_size.height as i32) / 2;
+                let name_color = if host.stale { warn } else { rgb(colors.text) };
+                Text::new(
+                    &host.name,
+                    Point::new(DISP_X_PAD, name_y + LIST_FONT.baseline as i32),
+                    list_style(name_color),
+                )
+                .draw(display)?;
 
-    draw_cpu_bar_graph(display, perf)?;
+                // Loads of stale hosts are out of date, so are replaced by a marker.
+                if host.stale {
+                    Text::new(
+                        "STALE",
+                        Point::new(bar_x, name_y + LIST_FONT.baseline as i32),
+                        list_style(warn),
+                    )
+                    .draw(display)?;
+                    continue;
+                }
+                let bar_top =
+                    top + (HOST_ROW_HEIGHT - HOST_BAR_HEIGHT as i32 * 2 - HOST_BAR_GAP) / 2;
+                bar_graph(
+                    display,
+                    background,
+                    Point::new(bar_x, bar_top),
+                    bar_size,
+                    bar(host.cpu_load),
+                )?;
+                bar_graph(
+                    display,
+                    background,
+                    Point::new(bar_x, bar_top + HOST_BAR_HEIGHT as i32 + HOST_BAR_GAP),
+                    bar_size,
+                    bar(host.memory_load),
+                )?;
+            }
+        }
+        HostView::Rotate(secs) => {
+            let index = (now_secs / secs.max(1) as u64) as usize % hosts.len();
+            let host = &hosts[index];
 
-    // RAM heading.
-    Text::new("RAM", text_point(DISP_X_PAD, 2), mem_text_style).draw(display)?;
+            // Host name header, marked while the host has stopped reporting.
+            Text::new(&host.name, text_point(DISP_X_PAD, 0), text_style).draw(display)?;
+            if host.stale {
+                Text::new("STALE", text_point_right(0, "STALE"), stale_style).draw(display)?;
+            } else {
+                let mut text: String<8> = String::new();
+                let _ = write!(text, "{}/{}", index + 1, hosts.len());
+                Text::new(&text, text_point_right(0, &text), text_style).draw(display)?;
+            }
 
-    // Free memory percent display, right aligned.
-    let mut avg = percent_string(1.0 - perf.memory_load, false);
-    avg.push_str("% Free").unwrap();
-    Text::new(
-        avg.as_str(),
-        text_point_right(2, avg.as_str()),
-        mem_text_style,
+            let loads = [("CPU", host.cpu_load), ("RAM", host.memory_load)];
+            for (i, (label, value)) in loads.iter().enumerate() {
+                let row = 1 + i as i32 * 2;
+                Text::new(label, text_point(DISP_X_PAD, row), text_style).draw(display)?;
+                let mut text: String<16> = percent_string(*value, false);
+                text.push('%').ok();
+                Text::new(&text, text_point_right(row, &text), text_style).draw(display)?;
+                bar_graph(
+                    display,
+                    background,
+                    Point::new(DISP_X_PAD, line_y_offset(row + 1)),
+                    Size::new(BAR_WIDTH, BAR_HEIGHT),
+                    bar(*value),
+                )?;
+            }
+        }
+    }
+    draw_alert_icon(display, page)?;
+
+    Ok(())
+}
+
+// Renders a warning sign at the top center of the screen while any metric is alerting.
+fn draw_alert_icon<T>(display: &mut T, page: &PerfPage) -> Result<(), T::Error>
+where
+    T: DrawTarget<Color = Rgb565>,
+{
+    if !page.alerts.is_active() {
+        return Ok(());
+    }
+
+    let colors = page.theme.palette(page.daytime);
+    let crit = page.theme.crit.unwrap_or(Threshold::DEFAULT_CRIT).color;
+    let left = (DISP_WIDTH - ALERT_ICON_SIZE.width as i32) / 2;
+    let top = DISP_Y_PAD + 2;
+    let (width, height) = (ALERT_ICON_SIZE.width as i32, ALERT_ICON_SIZE.height as i32);
+    Triangle::new(
+        Point::new(left + width / 2, top),
+        Point::new(left, top + height),
+        Point::new(left + width, top + height),
     )
+    .into_styled(PrimitiveStyleBuilder::new().fill_color(rgb(crit)).build())
     .draw(display)?;
 
-    // Draw memory used bar.
-    bar_graph(
-        display,
-        Point::new(DISP_X_PAD, line_y_offset(3)),
-        Size::new(BAR_WIDTH, BAR_HEIGHT),
-        Bar {
-            value: perf.memory_load,
-            style: mem_bar_style,
-        },
-    )?;
+    let mark_style = MonoTextStyleBuilder::new()
+        .font(&SMALL_FONT)
+        .text_color(rgb(colors.background))
+        .build();
+    Text::new(
+        "!",
+        Point::new(
+            left + (width - SMALL_FONT.character_size.width as i32) / 2,
+            top + height - 2,
+        ),
+        mark_style,
+    )
+    .draw(display)?;
 
     Ok(())
 }
 
-// Renders the overlaid CPU bar graphs, can be used without clearing the screen first.
-pub fn draw_cpu_bar_graph<T>(display: &mut T, perf: &message::PerfData) -> Result<(), T::Error>
+// Renders a notification in a box along the bottom of the screen, over the current page.
+// Text is word-wrapped, and lines beyond the height of the box are not shown.
+pub fn draw_notice<T>(
+    display: &mut T,
+    notification: &Notification,
+    page: &PerfPage,
+) -> Result<(), T::Error>
 where
     T: DrawTarget<Color = Rgb565>,
 {
-    let colors = if perf.daytime {
-        DAY_COLORS
-    } else {
-        NIGHT_COLORS
+    let colors = page.theme.palette(page.daytime);
+    let accent = match notification.level {
+        NotifyLevel::Info => rgb(colors.text),
+        NotifyLevel::Warning => rgb(page.theme.warn.unwrap_or(Threshold::DEFAULT_WARN).color),
+        NotifyLevel::Error => rgb(page.theme.crit.unwrap_or(Threshold::DEFAULT_CRIT).color),
     };
 
-    let background = PrimitiveStyleBuilder::new()
-        .fill_color(colors.background)
+    let max_chars = ((BAR_WIDTH - NOTICE_PAD * 2) / LIST_FONT.character_size.width) as usize;
+    let lines = wrap(&notification.text, max_chars);
+    let height = lines.len().max(1) as u32 * LIST_FONT.character_size.height + NOTICE_PAD * 2;
+    let top = DISP_HEIGHT - DISP_Y_PAD - height as i32;
+
+    let box_style = PrimitiveStyleBuilder::new()
+        .fill_color(rgb(colors.background))
+        .stroke_color(accent)
+        .stroke_width(NOTICE_BORDER)
+        .build();
+    Rectangle::new(Point::new(DISP_X_PAD, top), Size::new(BAR_WIDTH, height))
+        .into_styled(box_style)
+        .draw(display)?;
+
+    let text_style = MonoTextStyleBuilder::new()
+        .font(&LIST_FONT)
+        .text_color(accent)
+        .build();
+    for (i, line) in lines.iter().enumerate() {
+        let origin = Point::new(
+            DISP_X_PAD + NOTICE_PAD as i32,
+            top + NOTICE_PAD as i32
+                + (i as u32 * LIST_FONT.character_size.height) as i32
+                + LIST_FONT.baseline as i32,
+        );
+        Text::new(line, origin, text_style).draw(display)?;
+    }
+
+    Ok(())
+}
+
+// Splits text into lines of at most `width` characters, breaking between words where
+// possible, and at newlines.
+fn wrap(text: &str, width: usize) -> Vec<&str, NOTICE_LINES> {
+    let mut lines = Vec::new();
+    let mut rest = text.trim();
+
+    while !rest.is_empty() && !lines.is_full() {
+        // Byte offset of the first character that does not fit, if any.
+        let limit = rest.char_indices().nth(width).map(|(i, _)| i);
+        let fits = &rest[..limit.unwrap_or(rest.len())];
+
+        let end = match fits.find('\n') {
+            Some(newline) => newline,
+            None => match limit {
+                None => rest.len(),
+                Some(limit) if rest[limit..].starts_with(' ') => limit,
+                Some(limit) => fits.rfind(' ').unwrap_or(limit),
+            },
+        };
+
+        lines.push(rest[..end].trim_end()).ok();
+        rest = rest[end..].trim_start();
+    }
+
+    lines
+}
+
+fn draw_widget<T>(display: &mut T, widget: &Widget, page: &PerfPage) -> Result<(), T::Error>
+where
+    T: DrawTarget<Color = Rgb565>,
+{
+    let theme = page.theme;
+    let colors = theme.palette(page.daytime);
+    let value = |metric| page.metrics.get(metric).unwrap_or_default();
+
+    let text_style = MonoTextStyleBuilder::new()
+        .font(&FONT)
+        .text_color(rgb(colors.text))
         .build();
 
-    let cpu_peak_bar_style = PrimitiveStyleBuilder::new()
-        .fill_color(colors.cpu_bar_peak)
+    let small_text_style = MonoTextStyleBuilder::new()
+        .font(&SMALL_FONT)
+        .text_color(rgb(colors.text))
         .build();
 
-    let cpu_avg_bar_style = PrimitiveStyleBuilder::new()
-        .fill_color(colors.cpu_bar_avg)
+    let background = PrimitiveStyleBuilder::new()
+        .fill_color(rgb(colors.background))
         .build();
 
-    double_bar_graph(
-        display,
-        background,
-        Point::new(DISP_X_PAD, line_y_offset(1)),
-        Size::new(BAR_WIDTH, BAR_HEIGHT),
-        Bar {
-            value: perf.all_cores_load,
-            style: cpu_avg_bar_style,
-        },
-        Bar {
-            value: perf.peak_core_load,
-            style: cpu_peak_bar_style,
-        },
-    )?;
+    let bar_style = |base: Color, value: f32| {
+        PrimitiveStyleBuilder::new()
+            .fill_color(rgb(theme.bar_color(base, value)))
+            .build()
+    };
+
+    // Bars of alerting metrics flash by filling their empty space with the critical color.
+    let bar_background = |metrics: &[MetricId]| {
+        if page.flash && metrics.iter().any(|id| page.alerts.contains(*id)) {
+            let crit = theme.crit.unwrap_or(Threshold::DEFAULT_CRIT).color;
+            PrimitiveStyleBuilder::new().fill_color(rgb(crit)).build()
+        } else {
+            background
+        }
+    };
+
+    match widget {
+        Widget::Label { row, align, text } => {
+            Text::new(text, aligned_point(*align, *row, text), text_style).draw(display)?;
+        }
+        Widget::Value {
+            row,
+            align,
+            metric,
+            format,
+            suffix,
+        } => {
+            let meta = page.meta.get(*metric);
+            let mut text = match page.metrics.get(*metric) {
+                Some(value) if value.is_nan() => String::from("ERR"),
+                Some(value) => {
+                    format_value(value, *format, meta.map(|m| m.unit).unwrap_or(Unit::None))
+                }
+                None => String::from("--"),
+            };
+            match meta.and_then(|m| m.label.as_ref()) {
+                Some(label) if suffix.is_empty() => {
+                    text.push(' ').ok();
+                    text.push_str(label).ok();
+                }
+                _ => {
+                    text.push_str(suffix).ok();
+                }
+            }
+            Text::new(&text, aligned_point(*align, *row, &text), text_style).draw(display)?;
+        }
+        Widget::Bar { row, metric } => {
+            let value = value(*metric);
+            bar_graph(
+                display,
+                bar_background(&[*metric]),
+                Point::new(DISP_X_PAD, line_y_offset(*row as i32)),
+                Size::new(BAR_WIDTH, BAR_HEIGHT),
+                Bar {
+                    value,
+                    style: bar_style(colors.bar, value),
+                },
+            )?;
+        }
+        Widget::DoubleBar { row, left, right } => {
+            let flash_background = bar_background(&[*left, *right]);
+            let (left, right) = (value(*left), value(*right));
+            double_bar_graph(
+                display,
+                flash_background,
+                Point::new(DISP_X_PAD, line_y_offset(*row as i32)),
+                Size::new(BAR_WIDTH, BAR_HEIGHT),
+                Bar {
+                    value: left,
+                    style: bar_style(colors.bar_avg, left),
+                },
+                Bar {
+                    value: right,
+                    style: bar_style(colors.bar_peak, right),
+                },
+            )?;
+        }
+        Widget::Memory {
+            row,
+            used,
+            cache,
+            swap,
+        } => {
+            let flash_background = bar_background(&[*used, *cache, *swap]);
+            let (used, cache, swap) = (value(*used), value(*cache), value(*swap));
+            let offset = Point::new(DISP_X_PAD, line_y_offset(*row as i32));
+
+            // Cache is stacked after used memory, thresholds apply to used memory alone.
+            double_bar_graph(
+                display,
+                flash_background,
+                offset,
+                Size::new(BAR_WIDTH, BAR_HEIGHT),
+                Bar {
+                    value: used,
+                    style: bar_style(colors.bar, used),
+                },
+                Bar {
+                    value: used + cache,
+                    style: bar_style(colors.bar_peak, 0.0),
+                },
+            )?;
+            bar_graph(
+                display,
+                background,
+                offset + Point::new(0, BAR_HEIGHT as i32 + SWAP_BAR_GAP),
+                Size::new(BAR_WIDTH, SWAP_BAR_HEIGHT),
+                Bar {
+                    value: swap,
+                    style: bar_style(colors.bar_avg, swap),
+                },
+            )?;
+        }
+        Widget::Status { row } => {
+            let text = status_string(page.metrics);
+            let point = Point::new(
+                DISP_X_PAD,
+                line_y_offset(*row as i32) + SMALL_FONT.baseline as i32,
+            );
+            Text::new(&text, point, small_text_style).draw(display)?;
+        }
+        Widget::Pressure { row } => {
+            let resources = [
+                ("CPU", metric::PSI_CPU_SOME, metric::PSI_CPU_FULL),
+                ("MEM", metric::PSI_MEM_SOME, metric::PSI_MEM_FULL),
+                ("IO", metric::PSI_IO_SOME, metric::PSI_IO_FULL),
+            ];
+            for (i, (name, some, full)) in resources.iter().enumerate() {
+                // Full stalls, where no task could make progress, count double.
+                let pressure = value(*some).max(value(*full) * 2.0);
+                let color = if pressure >= PRESSURE_CRIT {
+                    theme.crit.unwrap_or(Threshold::DEFAULT_CRIT).color
+                } else if pressure >= PRESSURE_WARN {
+                    theme.warn.unwrap_or(Threshold::DEFAULT_WARN).color
+                } else {
+                    colors.bar
+                };
+
+                let offset = Point::new(
+                    DISP_X_PAD + (i as u32 * (PRESSURE_WIDTH + PRESSURE_GAP)) as i32,
+                    line_y_offset(*row as i32),
+                );
+                Rectangle::new(offset, Size::new(PRESSURE_WIDTH, BAR_HEIGHT))
+                    .into_styled(PrimitiveStyleBuilder::new().fill_color(rgb(color)).build())
+                    .draw(display)?;
+
+                let mut text: String<16> = String::new();
+                let _ = write!(text, "{} {}%", name, percent_string(value(*some), false));
+                let label_style = MonoTextStyleBuilder::new()
+                    .font(&SMALL_FONT)
+                    .text_color(rgb(colors.background))
+                    .build();
+                Text::new(
+                    &text,
+                    offset + Point::new(2, (BAR_HEIGHT as i32 + SMALL_FONT.baseline as i32) / 2),
+                    label_style,
+                )
+                .draw(display)?;
+            }
+        }
+        Widget::Frequency { row } => {
+            let (avg, max) = (value(metric::CPU_FREQ_AVG), value(metric::CPU_FREQ_MAX));
+            let offset = Point::new(DISP_X_PAD, line_y_offset(*row as i32));
+            double_bar_graph(
+                display,
+                background,
+                offset,
+                Size::new(FREQ_BAR_WIDTH, BAR_HEIGHT),
+                Bar {
+                    value: avg,
+                    style: bar_style(colors.bar_avg, 0.0),
+                },
+                Bar {
+                    value: max,
+                    style: bar_style(colors.bar_peak, 0.0),
+                },
+            )?;
+
+            // Animated frames do not clear the screen, so clear behind the text.
+            let text_offset = offset + Point::new(FREQ_BAR_WIDTH as i32 + DISP_X_PAD, 0);
+            let text_area = Size::new(BAR_WIDTH - FREQ_BAR_WIDTH - DISP_X_PAD as u32, BAR_HEIGHT);
+            let baseline = Point::new(2, (BAR_HEIGHT as i32 + SMALL_FONT.baseline as i32) / 2);
+            if value(metric::CPU_THROTTLED) >= 0.5 {
+                let crit = theme.crit.unwrap_or(Threshold::DEFAULT_CRIT).color;
+                Rectangle::new(text_offset, text_area)
+                    .into_styled(PrimitiveStyleBuilder::new().fill_color(rgb(crit)).build())
+                    .draw(display)?;
+                let badge_style = MonoTextStyleBuilder::new()
+                    .font(&SMALL_FONT)
+                    .text_color(rgb(colors.background))
+                    .build();
+                Text::new("THROTTLED", text_offset + baseline, badge_style).draw(display)?;
+            } else {
+                Rectangle::new(text_offset, text_area)
+                    .into_styled(background)
+                    .draw(display)?;
+                if let Some(mhz) = page.metrics.get(metric::CPU_MHZ_MAX) {
+                    let text = number_string(mhz, Unit::Megahertz);
+                    Text::new(&text, text_offset + baseline, small_text_style).draw(display)?;
+                }
+            }
+        }
+        Widget::Battery { row } => {
+            let Some(level) = page.metrics.get(metric::BATTERY_LEVEL) else {
+                return Ok(());
+            };
+            let top_left = Point::new(
+                (DISP_WIDTH - BATTERY_WIDTH) / 2,
+                line_y_offset(*row as i32) + 4,
+            );
+
+            // Outline with a nub on the right, filled to the charge level.
+            let outline = PrimitiveStyleBuilder::new()
+                .stroke_color(rgb(colors.text))
+                .stroke_width(1)
+                .fill_color(rgb(colors.background))
+                .build();
+            Rectangle::new(top_left, BATTERY_SIZE)
+                .into_styled(outline)
+                .draw(display)?;
+            Rectangle::new(
+                top_left
+                    + Point::new(
+                        BATTERY_SIZE.width as i32,
+                        (BATTERY_SIZE.height - BATTERY_NUB.height) as i32 / 2,
+                    ),
+                BATTERY_NUB,
+            )
+            .into_styled(
+                PrimitiveStyleBuilder::new()
+                    .fill_color(rgb(colors.text))
+                    .build(),
+            )
+            .draw(display)?;
+            let fill_color = if level < BATTERY_LOW {
+                theme.crit.unwrap_or(Threshold::DEFAULT_CRIT).color
+            } else {
+                colors.bar
+            };
+            let fill_width = ((BATTERY_SIZE.width - 4) as f32 * level.clamp(0.0, 1.0)) as u32;
+            Rectangle::new(
+                top_left + Point::new(2, 2),
+                Size::new(fill_width, BATTERY_SIZE.height - 4),
+            )
+            .into_styled(
+                PrimitiveStyleBuilder::new()
+                    .fill_color(rgb(fill_color))
+                    .build(),
+            )
+            .draw(display)?;
+
+            // Charge above the power, signed by direction.
+            let text_x = top_left.x + (BATTERY_SIZE.width + BATTERY_NUB.width) as i32 + 4;
+            let mut text: String<16> = String::new();
+            let _ = write!(text, "{}%", percent_string(level, false).trim_start());
+            Text::new(
+                &text,
+                Point::new(text_x, top_left.y - 4 + SMALL_FONT.baseline as i32),
+                small_text_style,
+            )
+            .draw(display)?;
+            if let Some(watts) = page.metrics.get(metric::BATTERY_POWER) {
+                let charging = page
+                    .metrics
+                    .get(metric::BATTERY_CHARGING)
+                    .unwrap_or_default();
+                let mut text: String<16> = String::new();
+                let sign = if charging >= 0.5 { '+' } else { '-' };
+                let _ = write!(text, "{}{}", sign, number_string(watts, Unit::Watts));
+                Text::new(
+                    &text,
+                    Point::new(
+                        text_x,
+                        top_left.y - 4
+                            + SMALL_FONT.character_size.height as i32
+                            + SMALL_FONT.baseline as i32,
+                    ),
+                    small_text_style,
+                )
+                .draw(display)?;
+            }
+        }
+        Widget::Sparkline { row, metric } => {
+            // Newest sample is drawn at the right edge.
+            let height = FONT.character_size.height;
+            let bottom = line_y_offset(*row as i32) + height as i32;
+            let Some(samples) = page.history.samples(*metric) else {
+                return Ok(());
+            };
+            let start_x = DISP_X_PAD + (HISTORY_LEN - samples.len()) as i32 * SPARK_WIDTH as i32;
+            for (i, value) in samples.enumerate() {
+                let bar_height = ((height as f32 * value) as u32).clamp(1, height);
+                Rectangle::new(
+                    Point::new(
+                        start_x + i as i32 * SPARK_WIDTH as i32,
+                        bottom - bar_height as i32,
+                    ),
+                    Size::new(SPARK_WIDTH, bar_height),
+                )
+                .into_styled(bar_style(colors.bar, value))
+                .draw(display)?;
+            }
+        }
+    }
 
     Ok(())
 }
 
+// Converts a theme color for the display.
+fn rgb(color: Color) -> Rgb565 {
+    RawU16::new(color.0).into()
+}
+
 // Returns the screen Y pixel offset for the top of the specified text line number.
 fn line_y_offset(line: i32) -> i32 {
     DISP_Y_PAD + (line * (LINE_Y_PAD + FONT.character_size.height as i32))
@@ -189,12 +930,27 @@ fn text_point_right(line: i32, text: &str) -> Point {
     text_point(DISP_WIDTH - DISP_X_PAD - text_width, line)
 }
 
+// Returns the point to render aligned text for the specified row.
+fn aligned_point(align: Align, row: u8, text: &str) -> Point {
+    match align {
+        Align::Left => text_point(DISP_X_PAD, row as i32),
+        Align::Right => text_point_right(row as i32, text),
+    }
+}
+
 struct Bar {
     value: f32,
     style: PrimitiveStyle<Rgb565>,
 }
 
-fn bar_graph<T>(display: &mut T, offset: Point, size: Size, bar: Bar) -> Result<(), T::Error>
+// Draws a bar graph, clearing the remainder of its area.
+fn bar_graph<T>(
+    display: &mut T,
+    background: PrimitiveStyle<Rgb565>,
+    offset: Point,
+    size: Size,
+    bar: Bar,
+) -> Result<(), T::Error>
 where
     T: DrawTarget<Color = Rgb565>,
 {
@@ -206,10 +962,19 @@ where
     };
 
     // Wide, high value bar.
-    Rectangle::new(offset, Size::new(scale_x(bar.value), size.height))
+    let scaled = scale_x(bar.value);
+    Rectangle::new(offset, Size::new(scaled, size.height))
         .into_styled(bar.style)
         .draw(display)?;
 
+    // Clear remaining space.
+    Rectangle::new(
+        Point::new(scaled as i32, 0) + offset,
+        Size::new(size.width - scaled, size.height),
+    )
+    .into_styled(background)
+    .draw(display)?;
+
     Ok(())
 }
 
@@ -263,6 +1028,83 @@ where
     Ok(())
 }
 
+// Formats load averages, process counts and uptime, omitting those not reported.
+fn status_string(metrics: &Metrics) -> String<48> {
+    let mut result = String::new();
+
+    // Results are truncated rather than failing, if somehow too long.
+    let loads = [metric::LOAD_1, metric::LOAD_5, metric::LOAD_15].map(|id| metrics.get(id));
+    if let [Some(l1), Some(l5), Some(l15)] = loads {
+        let _ = write!(
+            result,
+            "load {:.0}/{:.0}/{:.0}%  ",
+            l1 * 100.0,
+            l5 * 100.0,
+            l15 * 100.0
+        );
+    }
+    if let (Some(running), Some(total)) = (
+        metrics.get(metric::PROCS_RUNNING),
+        metrics.get(metric::PROCS_TOTAL),
+    ) {
+        let _ = write!(result, "proc {}/{}  ", running as u32, total as u32);
+    }
+    if let Some(uptime) = metrics.get(metric::UPTIME) {
+        let _ = write!(result, "up {}", number_string(uptime, Unit::Seconds));
+    }
+
+    result
+}
+
+fn format_value(value: f32, format: Format, unit: Unit) -> String<24> {
+    let text = match format {
+        Format::Percent => percent_string(value, false),
+        Format::PercentTenths => percent_string(value, true),
+        Format::RemainingPercent => percent_string(1.0 - value, false),
+        Format::Number => return number_string(value, unit),
+    };
+
+    // Cannot fail, percent strings are shorter than the destination.
+    String::from(text.as_str())
+}
+
+// Formats a value in its unit, scaling large values to keep the text short.
+fn number_string(value: f32, unit: Unit) -> String<24> {
+    let mut result = String::new();
+
+    // Results are truncated rather than failing, if somehow too long.
+    let _ = match unit {
+        Unit::None if value.abs() < 100.0 => write!(result, "{:.1}", value),
+        Unit::None => write!(result, "{:.0}", value),
+        Unit::Ratio => write!(result, "{:.0}%", value * 100.0),
+        Unit::Bytes => {
+            const SUFFIXES: [&str; 5] = ["B", "K", "M", "G", "T"];
+            let mut scaled = value;
+            let mut suffix = 0;
+            while scaled >= 1024.0 && suffix < SUFFIXES.len() - 1 {
+                scaled /= 1024.0;
+                suffix += 1;
+            }
+            write!(result, "{:.1}{}", scaled, SUFFIXES[suffix])
+        }
+        Unit::Seconds => {
+            let secs = value as u32;
+            match secs {
+                0..=59 => write!(result, "{}s", secs),
+                60..=3599 => write!(result, "{}m", secs / 60),
+                3600..=86399 => write!(result, "{}h{}m", secs / 3600, secs % 3600 / 60),
+                _ => write!(result, "{}d{}h", secs / 86400, secs % 86400 / 3600),
+            }
+        }
+        Unit::Celsius => write!(result, "{:.0}C", value),
+        Unit::Megahertz if value >= 1000.0 => write!(result, "{:.1}GHz", value / 1000.0),
+        Unit::Megahertz => write!(result, "{:.0}MHz", value),
+        Unit::Watts => write!(result, "{:.1}W", value),
+    };
+
+    result
+}
+
 fn percent_string(ratio: f32, fractional: bool) -> String<16> {
     fn digit(d: i32) -> char {
         (b'0' + d as u8) as char
@@ -1,9 +1,25 @@
+use core::future::poll_fn;
+use core::task::Poll;
+use heapless::spsc;
 use rp2040_hal::usb;
+use rtic_common::waker_registration::CriticalSectionWakerRegistration;
+use shared::message::{FRAME_QUEUE_LEN, MAX_FRAME_BYTES};
 use usb_device::prelude::*;
 
-pub const BUF_BYTES: usize = 64;
+pub const BUF_BYTES: usize = MAX_FRAME_BYTES;
 const TERMINATOR: u8 = 0;
 
+/// A complete frame, including its terminator, followed by zeros.
+pub type Packet = [u8; BUF_BYTES];
+
+/// Packets read from USB, waiting to be handled.  Holds one less than its length.
+pub type PacketQueue = spsc::Queue<Packet, { FRAME_QUEUE_LEN + 1 }>;
+pub type PacketProducer = spsc::Producer<'static, Packet, { FRAME_QUEUE_LEN + 1 }>;
+pub type PacketConsumer = spsc::Consumer<'static, Packet, { FRAME_QUEUE_LEN + 1 }>;
+
+// Wakes the task waiting on the packet queue.
+static PACKET_READY: CriticalSectionWakerRegistration = CriticalSectionWakerRegistration::new();
+
 type StmUsbDevice = UsbDevice<'static, usb::UsbBus>;
 type StmSerialPort = usbd_serial::SerialPort<'static, usb::UsbBus>;
 
@@ -24,39 +40,46 @@ impl Serial {
         }
     }
 
-    /// Attempts to read a packet from the USB serial port, buffering incomplete packets
-    /// for a future attempt.  Returned packets include the terminating byte.
-    pub fn read_packet(&mut self, packet_buf: &mut [u8]) -> Result<usize, UsbError> {
-        if self.poll()? == 0 {
-            // No new serial data to process.
-            return Ok(0);
-        }
+    /// Reads from the USB serial port, queueing every complete packet buffered, and keeping
+    /// any incomplete packet for a future attempt.  Returns the number of packets dropped
+    /// because the queue was full.
+    pub fn read_packets(&mut self, queue: &mut PacketProducer) -> Result<usize, UsbError> {
+        self.poll()?;
 
-        for i in 0..self.buf_next {
-            if self.buf[i] == TERMINATOR {
-                if i > packet_buf.len() {
-                    return Err(UsbError::BufferOverflow);
-                }
-
-                // Copy a complete packet to provided buffer.
-                let _ = &packet_buf[..i + 1].copy_from_slice(&self.buf[..i + 1]);
-
-                if i + 1 == self.buf_next {
-                    // Buffer is now empty, reset index.
-                    self.buf_next = 0;
-                } else {
-                    // Move trailing data to start of buffer, skipping terminator.
-                    let start = i + 1;
-                    self.buf.copy_within(start..self.buf_next, 0);
-                    self.buf_next -= start;
-                }
-
-                return Ok(i + 1);
+        let mut dropped = 0;
+        let mut start = 0;
+        while let Some(len) = self.buf[start..self.buf_next]
+            .iter()
+            .position(|&b| b == TERMINATOR)
+            .map(|i| i + 1)
+        {
+            let mut packet = [0u8; BUF_BYTES];
+            packet[..len].copy_from_slice(&self.buf[start..start + len]);
+            if queue.enqueue(packet).is_err() {
+                dropped += 1;
             }
+            start += len;
+        }
+
+        if start > 0 {
+            // Move trailing data to start of buffer.
+            self.buf.copy_within(start..self.buf_next, 0);
+            self.buf_next -= start;
+            PACKET_READY.wake();
         }
 
-        // No terminator found; packet is not yet complete.
-        Ok(0)
+        Ok(dropped)
+    }
+
+    /// Writes a complete packet to the USB serial port.  Packets are small enough to fit
+    /// into the port's transmit buffer, a partial write indicates the host is not reading.
+    pub fn write_packet(&mut self, packet: &[u8]) -> Result<(), UsbError> {
+        let count = self.port.write(packet)?;
+        if count < packet.len() {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        Ok(())
     }
 
     /// Polls the USB serial port, reading bytes into `Serial.buf`.
@@ -82,3 +105,16 @@ impl Serial {
         }
     }
 }
+
+/// Waits for the next packet queued by `Serial::read_packets`.
+pub async fn next_packet(queue: &mut PacketConsumer) -> Packet {
+    poll_fn(|cx| {
+        // Registered before checking, so a packet queued in between still wakes this task.
+        PACKET_READY.register(cx.waker());
+        match queue.dequeue() {
+            Some(packet) => Poll::Ready(packet),
+            None => Poll::Pending,
+        }
+    })
+    .await
+}
@@ -8,7 +8,9 @@ use rtic_monotonics::rp2040::prelude::*;
 
 mod gfx;
 mod io;
+mod notify;
 mod perf;
+mod settings;
 
 rp2040_timer_monotonic!(Mono);
 
@@ -29,28 +31,46 @@ mod app {
 
     use crate::{
         gfx, io,
-        perf::{self, FramesDeque, PerfFrame},
+        notify::NotificationQueue,
+        perf::{self, FramesDeque, History, Metrics, MetricsMeta, PerfFrame},
+        settings,
     };
     use core::mem::MaybeUninit;
     use cortex_m::asm;
     use defmt::{debug, error, expect, info, unwrap, warn};
     use embedded_graphics::{pixelcolor::Rgb565, prelude::*};
     use embedded_graphics_framebuf::FrameBuf;
-    use embedded_hal::{digital::OutputPin, spi};
+    use embedded_hal::{digital::OutputPin, pwm::SetDutyCycle, spi};
     use fugit::{ExtU64, RateExtU32};
     use postcard;
-    use rp2040_hal::{self as hal, clocks::Clock, gpio, usb, watchdog::Watchdog};
-    use shared::{message, message::PerfData};
+    use rp2040_hal::{self as hal, clocks::Clock, gpio, pwm, usb, watchdog::Watchdog};
+    use shared::{
+        layout::Layout,
+        message::{self, Alerts, Hosts, SleepReason},
+        metric::Metric,
+        settings::{Page, Settings},
+        time::LocalTime,
+    };
     use usb_device::{bus::UsbBusAllocator, prelude::*};
 
     // Frequency of the board crystal.
     const XOSC_CRYSTAL_FREQ: u32 = 12_000_000;
 
+    // Backlight brightness percent while the host is asleep, unless set dimmer.
+    const SLEEP_BRIGHTNESS: u8 = 10;
+
     // Duration to illuminate status LED upon data RX.
     const STATUS_LED_MS: u64 = 50;
 
-    // Delay from no data received to blanking the screen.
-    const BLANK_SCREEN_MS: u64 = 30000;
+    // Status LED while any metric is alerting, one step per STATUS_LED_MS: a double blink
+    // each second.
+    const ALERT_LED_PATTERN: [bool; 20] = [
+        true, true, false, false, true, true, false, false, false, false, false, false, false,
+        false, false, false, false, false, false, false,
+    ];
+
+    // Duration of each phase of alert flashing.
+    const ALERT_FLASH_MS: u64 = 500;
 
     // Periods are measured in system clock cycles; smaller is more frequent.
     const USB_VENDOR_ID: u16 = 0x1209; // pid.codes VID.
@@ -60,6 +80,9 @@ mod app {
     type ActivityLED =
         gpio::Pin<gpio::bank0::Gpio25, gpio::FunctionSio<gpio::SioOutput>, gpio::PullDown>;
 
+    // PWM slice driving the display backlight on GP4.
+    type Backlight = pwm::Slice<pwm::Pwm2, pwm::FreeRunning>;
+
     type DisplayBuf = FrameBuf<Rgb565, &'static mut [Rgb565; 240 * 135]>;
 
     // ST7789V IPS screen, aka T-Display.
@@ -81,6 +104,17 @@ mod app {
         gpio::Pin<gpio::bank0::Gpio0, gpio::FunctionSio<gpio::SioOutput>, gpio::PullDown>,
     >;
 
+    /// What the host last reported about itself, besides metrics.
+    #[derive(Clone, Copy, PartialEq)]
+    pub enum HostState {
+        // Sending metrics.
+        Active,
+        // Stopped, having cleared the screen.
+        Stopped,
+        // Asleep or locked, metrics resume once it is in use.
+        Asleep(SleepReason),
+    }
+
     #[shared]
     struct Shared {
         // Queue of perf data frames to display.
@@ -88,12 +122,43 @@ mod app {
 
         serial: io::Serial,
         display: Display,
+        backlight: Backlight,
+
+        // Persistent device settings, loaded from flash.
+        settings: Settings,
+
+        // Page currently being displayed.
+        page: Page,
+
+        // Widgets displayed on the perf page.
+        layout: Layout,
+
+        // Recent perf data for sparklines.
+        history: History,
 
         // Blinks ActivityLED briefly when set true.
         pulse_led: bool,
 
-        // Previously received perf data message.
-        prev_perf: Option<PerfData>,
+        // Previously committed metrics, as last displayed.
+        prev_perf: Option<Metrics>,
+
+        // Units and labels of received metrics.
+        meta: MetricsMeta,
+
+        // Notifications to show over the current page.
+        notifications: NotificationQueue,
+
+        // Metrics in an alert state on the host.
+        alerts: Alerts,
+
+        // Hosts reported by the daemon, for the hosts page.
+        hosts: Hosts,
+
+        // Reported by the host as it stops or sleeps, until metrics are next received.
+        host_state: HostState,
+
+        // Time set by the host, and when it was received, to keep time between updates.
+        clock: Option<(LocalTime, <Mono as rtic_monotonics::Monotonic>::Instant)>,
 
         // Last time we received a valid message.
         msg_time: <Mono as rtic_monotonics::Monotonic>::Instant,
@@ -103,11 +168,14 @@ mod app {
     struct Local {
         led: crate::app::ActivityLED,
         frame_buf: crate::app::DisplayBuf,
+        packets_tx: io::PacketProducer,
+        packets_rx: io::PacketConsumer,
     }
 
     #[init(local = [
            usb_bus: Option<UsbBusAllocator<usb::UsbBus>> = None,
            frame_buf_store: MaybeUninit<[Rgb565; 240 * 135]> = MaybeUninit::uninit(),
+           packets: io::PacketQueue = io::PacketQueue::new(),
     ])]
     fn init(ctx: init::Context) -> (Shared, Local) {
         // Soft-reset does not release the hardware spinlocks.
@@ -148,6 +216,9 @@ mod app {
         let mut led = pins.gpio25.into_push_pull_output();
         unwrap!(led.set_low());
 
+        let settings = settings::load();
+        info!("Loaded settings: {:?}", settings);
+
         // Init frame buffer.
         let frame_buf_store: &'static mut _ =
             ctx.local.frame_buf_store.write([Rgb565::BLACK; 240 * 135]);
@@ -164,10 +235,13 @@ mod app {
         );
 
         // Setup T-Display.
-        // TODO: Investigate PWM for night time.
         unwrap!(pins.gpio22.into_push_pull_output().set_high()); // Power on display.
-        let mut bl_pin = pins.gpio4.into_push_pull_output();
-        unwrap!(bl_pin.set_low()); // Backlight off until we've cleared the display.
+        let pwm_slices = pwm::Slices::new(ctx.device.PWM, &mut resets);
+        let mut backlight = pwm_slices.pwm2;
+        backlight.set_ph_correct();
+        backlight.enable();
+        backlight.channel_a.output_to(pins.gpio4);
+        unwrap!(backlight.channel_a.set_duty_cycle_fully_off()); // Off until display cleared.
 
         let cs_pin = pins.gpio5.into_push_pull_output();
         let dc_pin = pins.gpio1.into_push_pull_output();
@@ -181,7 +255,7 @@ mod app {
         );
 
         expect!(display.clear(Rgb565::BLACK), "display clears");
-        unwrap!(bl_pin.set_high());
+        set_brightness(&mut backlight, &settings);
 
         // Setup USB bus and serial port device.
         *ctx.local.usb_bus = Some(UsbBusAllocator::new(usb::UsbBus::new(
@@ -204,9 +278,11 @@ mod app {
             "Failed to set usb_device strings"
         );
         let usb_dev = usb_dev.device_class(usbd_serial::USB_CLASS_CDC).build();
+        let (packets_tx, packets_rx) = ctx.local.packets.split();
 
         // Start tasks.
         unwrap!(pulse_led::spawn());
+        unwrap!(handle_packet::spawn());
         unwrap!(show_perf::spawn());
         unwrap!(no_data_timeout::spawn());
 
@@ -217,92 +293,324 @@ mod app {
                 frames: FramesDeque::new(),
                 serial: io::Serial::new(usb_dev, port),
                 display,
+                backlight,
+                settings,
+                page: settings.default_page,
+                layout: Layout::default(),
+                history: History::default(),
                 pulse_led: false,
                 prev_perf: None,
+                meta: MetricsMeta::default(),
+                notifications: NotificationQueue::new(),
+                alerts: Alerts::default(),
+                hosts: Hosts::default(),
+                host_state: HostState::Active,
+                clock: None,
                 msg_time: Mono::now(),
             },
-            Local { led, frame_buf },
+            Local {
+                led,
+                frame_buf,
+                packets_tx,
+                packets_rx,
+            },
         )
     }
 
-    #[task(shared = [pulse_led], local = [led])]
+    #[task(shared = [pulse_led, alerts], local = [led])]
     async fn pulse_led(ctx: pulse_led::Context) {
-        let mut pulse_led = ctx.shared.pulse_led;
+        let pulse_led::SharedResources {
+            mut pulse_led,
+            mut alerts,
+            ..
+        } = ctx.shared;
         let led = ctx.local.led;
+        let mut step = 0;
 
         loop {
-            pulse_led.lock(|pulse_led| {
-                if *pulse_led {
+            // Alerts take over the LED from activity pulses.
+            if alerts.lock(|alerts| alerts.is_active()) {
+                step = (step + 1) % ALERT_LED_PATTERN.len();
+                if ALERT_LED_PATTERN[step] {
                     led.set_high().unwrap();
-                    *pulse_led = false;
                 } else {
                     led.set_low().unwrap();
                 }
-            });
+                pulse_led.lock(|pulse_led| *pulse_led = false);
+            } else {
+                pulse_led.lock(|pulse_led| {
+                    if *pulse_led {
+                        led.set_high().unwrap();
+                        *pulse_led = false;
+                    } else {
+                        led.set_low().unwrap();
+                    }
+                });
+            }
 
             // Clear LED after a delay.
             Mono::delay(STATUS_LED_MS.millis()).await;
         }
     }
 
-    #[task(priority = 4, binds = USBCTRL_IRQ, shared = [serial, pulse_led])]
+    #[task(priority = 4, binds = USBCTRL_IRQ, shared = [serial, pulse_led], local = [packets_tx])]
     fn usb_event(ctx: usb_event::Context) {
         // TODO: schedule 10ms poll to be compliant.
         let usb_event::SharedResources {
             serial, pulse_led, ..
         } = ctx.shared;
+        let packets_tx = ctx.local.packets_tx;
         (serial, pulse_led).lock(|serial, pulse_led| {
-            crate::handle_usb_event(serial);
+            crate::handle_usb_event(serial, packets_tx);
             *pulse_led = true;
         });
     }
 
-    #[task(priority = 3, shared = [msg_time])]
-    async fn handle_packet(mut ctx: handle_packet::Context, mut buf: [u8; io::BUF_BYTES]) {
-        let msg: Result<message::FromHost, _> = postcard::from_bytes_cobs(&mut buf);
-        match msg {
-            Ok(msg) => {
-                debug!("Rx message: {:?}", msg);
-                if let message::FromHost::ShowPerf(perf_data) = msg {
-                    ctx.shared.msg_time.lock(|msg_time| {
-                        *msg_time = Mono::now();
-                    });
-
-                    // TODO: should use a queue here.
-                    handle_perf::spawn(perf_data).ok();
+    #[task(
+        priority = 3,
+        shared = [
+            msg_time,
+            host_state,
+            clock,
+            serial,
+            settings,
+            backlight,
+            page,
+            layout,
+            meta,
+            notifications,
+            alerts,
+            hosts,
+        ],
+        local = [packets_rx, pending: Metrics = Metrics::new()],
+    )]
+    async fn handle_packet(mut ctx: handle_packet::Context) -> ! {
+        loop {
+            let mut buf = io::next_packet(ctx.local.packets_rx).await;
+            let msg: Result<message::FromHost, _> = postcard::from_bytes_cobs(&mut buf);
+            match msg {
+                Ok(msg) => {
+                    debug!("Rx message: {:?}", msg);
+                    match msg {
+                        message::FromHost::ShowPerf(perf_data) => {
+                            for metric in perf_data.metrics() {
+                                accept_metric(ctx.local.pending, &mut ctx.shared.meta, &metric);
+                            }
+                            commit_metrics(
+                                &mut ctx.shared.msg_time,
+                                &mut ctx.shared.host_state,
+                                ctx.local.pending,
+                            );
+                        }
+                        message::FromHost::UpdateMetrics(batch) => {
+                            for metric in &batch.metrics {
+                                accept_metric(ctx.local.pending, &mut ctx.shared.meta, metric);
+                            }
+                            if batch.commit {
+                                commit_metrics(
+                                    &mut ctx.shared.msg_time,
+                                    &mut ctx.shared.host_state,
+                                    ctx.local.pending,
+                                );
+                            }
+                        }
+                        message::FromHost::GetSettings => {
+                            let current = ctx.shared.settings.lock(|settings| *settings);
+                            reply(
+                                &mut ctx.shared.serial,
+                                &message::FromDevice::Settings(current),
+                            );
+                        }
+                        message::FromHost::SetSettings(new_settings) => {
+                            update_settings(&mut ctx.shared.settings, |settings| {
+                                *settings = new_settings
+                            });
+                            ctx.shared
+                                .backlight
+                                .lock(|backlight| set_brightness(backlight, &new_settings));
+                            reply(
+                                &mut ctx.shared.serial,
+                                &message::FromDevice::Settings(new_settings),
+                            );
+                        }
+                        message::FromHost::SetTheme(theme) => {
+                            update_settings(&mut ctx.shared.settings, |settings| {
+                                settings.theme = theme
+                            });
+                        }
+                        message::FromHost::SetLayout(new_layout) => {
+                            ctx.shared.layout.lock(|layout| *layout = new_layout);
+                        }
+                        message::FromHost::ShowPage(new_page) => {
+                            ctx.shared.page.lock(|page| *page = new_page);
+                        }
+                        message::FromHost::Notify(notification) => {
+                            ctx.shared
+                                .notifications
+                                .lock(|notifications| notifications.push(notification));
+                        }
+                        message::FromHost::SetAlerts(new_alerts) => {
+                            ctx.shared.alerts.lock(|alerts| *alerts = new_alerts);
+                        }
+                        message::FromHost::SetHosts(new_hosts) => {
+                            ctx.shared.hosts.lock(|hosts| *hosts = new_hosts);
+                        }
+                        message::FromHost::ClearScreen => {
+                            ctx.shared
+                                .host_state
+                                .lock(|host_state| *host_state = HostState::Stopped);
+                            // Alerts no longer apply, so stop signalling them on the LED.
+                            ctx.shared.alerts.lock(|alerts| *alerts = Alerts::default());
+                        }
+                        message::FromHost::SetTime(time) => {
+                            ctx.shared
+                                .clock
+                                .lock(|clock| *clock = Some((time, Mono::now())));
+                        }
+                        message::FromHost::Sleep(reason) => {
+                            ctx.shared
+                                .host_state
+                                .lock(|host_state| *host_state = HostState::Asleep(reason));
+                            ctx.shared.alerts.lock(|alerts| *alerts = Alerts::default());
+                        }
+                    }
+                }
+                Err(_) => {
+                    error!("Failed to deserialize message");
+                    asm::bkpt();
                 }
             }
-            Err(_) => {
-                error!("Failed to deserialize message");
-                asm::bkpt();
+        }
+    }
+
+    /// Adds a metric to the values pending commit, recording how it is displayed.
+    fn accept_metric(
+        pending: &mut Metrics,
+        meta: &mut impl rtic::Mutex<T = MetricsMeta>,
+        metric: &Metric,
+    ) {
+        pending.set(metric.id, metric.value);
+        meta.lock(|meta| meta.update(metric));
+    }
+
+    /// Hands the accumulated metrics off for display.  Values are retained, so metrics
+    /// the host updates less often than others continue to be displayed.
+    fn commit_metrics(
+        msg_time: &mut impl rtic::Mutex<T = <Mono as rtic_monotonics::Monotonic>::Instant>,
+        host_state: &mut impl rtic::Mutex<T = HostState>,
+        pending: &Metrics,
+    ) {
+        msg_time.lock(|msg_time| {
+            *msg_time = Mono::now();
+        });
+        host_state.lock(|host_state| *host_state = HostState::Active);
+
+        // TODO: should use a queue here.
+        handle_perf::spawn(pending.clone()).ok();
+    }
+
+    /// Writes the current settings to flash at low priority, as interrupts are disabled
+    /// while it runs.
+    #[task(priority = 1, shared = [settings])]
+    async fn save_settings(mut ctx: save_settings::Context) {
+        let current = ctx.shared.settings.lock(|settings| *settings);
+        settings::save(&current);
+    }
+
+    /// Modifies settings, scheduling them to be saved if they changed.  Unchanged settings
+    /// are not saved, to avoid wearing flash when the host resends them.
+    fn update_settings(
+        settings: &mut impl rtic::Mutex<T = Settings>,
+        f: impl FnOnce(&mut Settings),
+    ) {
+        let changed = settings.lock(|settings| {
+            let prev = *settings;
+            f(settings);
+            *settings != prev
+        });
+
+        // A save that is already pending will pick up these changes.
+        if changed && save_settings::spawn().is_err() {
+            debug!("Settings save already pending");
+        }
+    }
+
+    /// Sends a message to the host, logging failures.
+    fn reply(serial: &mut impl rtic::Mutex<T = io::Serial>, msg: &message::FromDevice) {
+        let mut buf = [0u8; io::BUF_BYTES];
+        match postcard::to_slice_cobs(msg, &mut buf) {
+            Ok(packet) => {
+                if serial.lock(|serial| serial.write_packet(packet)).is_err() {
+                    warn!("Failed to write reply to host");
+                }
             }
+            Err(_) => error!("Failed to serialize reply"),
         }
     }
 
-    /// Displays PerfData smoothly, by averaging new_perf with prev_perf.  It then updates
+    /// Sets backlight PWM duty cycle from settings.
+    fn set_brightness(backlight: &mut Backlight, settings: &Settings) {
+        let percent = settings.brightness.min(100);
+        unwrap!(backlight.channel_a.set_duty_cycle_percent(percent));
+    }
+
+    /// Displays metrics smoothly, by averaging new_perf with prev_perf.  It then updates
     /// prev_perf, and schedules itself to display that value directly.
-    #[task(priority = 2, shared = [prev_perf, frames])]
-    async fn handle_perf(ctx: handle_perf::Context, new_perf: PerfData) {
+    #[task(priority = 2, shared = [prev_perf, frames, layout, history, meta])]
+    async fn handle_perf(ctx: handle_perf::Context, new_perf: Metrics) {
         let handle_perf::SharedResources {
-            prev_perf, frames, ..
+            prev_perf,
+            frames,
+            layout,
+            history,
+            meta,
+            ..
         } = ctx.shared;
 
-        (prev_perf, frames).lock(
-            |prev_perf: &mut Option<PerfData>, frames: &mut FramesDeque| {
+        (layout, history).lock(|layout: &mut Layout, history: &mut History| {
+            history.record(layout, &new_perf);
+        });
+
+        (prev_perf, frames, meta).lock(
+            |prev_perf: &mut Option<Metrics>, frames: &mut FramesDeque, meta: &mut MetricsMeta| {
                 let prev_value = prev_perf.take();
 
-                // Calculate perf data to display, and previous data to keep.
-                *prev_perf = perf::update_state(prev_value, new_perf, frames);
+                // Calculate metrics to display, and previous metrics to keep.
+                *prev_perf = perf::update_state(prev_value, new_perf, meta, frames);
             },
         );
     }
 
     /// Loop which displays available perf frames.
-    #[task(shared = [display, frames], local = [frame_buf])]
+    #[task(
+        shared = [
+            display,
+            frames,
+            settings,
+            page,
+            layout,
+            history,
+            meta,
+            notifications,
+            alerts,
+            hosts,
+            host_state,
+        ],
+        local = [frame_buf],
+    )]
     async fn show_perf(ctx: show_perf::Context) -> ! {
         let show_perf::SharedResources {
             mut display,
             mut frames,
+            mut settings,
+            mut page,
+            mut layout,
+            mut history,
+            mut meta,
+            mut notifications,
+            mut alerts,
+            mut hosts,
+            mut host_state,
             ..
         } = ctx.shared;
         let frame_buf = ctx.local.frame_buf;
@@ -313,27 +621,91 @@ mod app {
             instant += perf::FRAME_MS.millis();
             Mono::delay_until(instant).await;
 
+            // Frames queued before the host stopped or slept would draw over its screen.
+            if host_state.lock(|host_state| *host_state != HostState::Active) {
+                frames.lock(|frames| frames.clear());
+                continue;
+            }
+
+            let (color_mode, theme) =
+                settings.lock(|settings| (settings.color_mode, settings.theme));
+            let page_shown = page.lock(|page| *page);
+            let notice =
+                notifications.lock(|notifications| notifications.current(instant).cloned());
+            let alerting = alerts.lock(|alerts| alerts.clone());
+            let host_list = hosts.lock(|hosts| hosts.clone());
+            // Copied, as handle_packet updates them and must not wait for the redraw.
+            let layout_shown = layout.lock(|layout| layout.clone());
+            let meta_shown = meta.lock(|meta| meta.clone());
+            let flash =
+                (instant.duration_since_epoch().to_millis() / ALERT_FLASH_MS).is_multiple_of(2);
+
             // Pop a frame off the front of the frame queue and display it.
-            (&mut display, &mut frames).lock(|display: &mut Display, frames: &mut FramesDeque| {
-                match frames.pop_front() {
-                    Some(PerfFrame::Complete(frame)) => {
-                        gfx::draw_perf(frame_buf, &frame).unwrap();
-                        display.draw_iter(frame_buf.into_iter()).unwrap();
-                    }
-                    Some(PerfFrame::Partial(frame)) => {
-                        gfx::draw_cpu_bar_graph(display, &frame).unwrap();
+            (&mut display, &mut frames, &mut history).lock(
+                |display: &mut Display, frames: &mut FramesDeque, history: &mut History| {
+                    let perf_page = |metrics| gfx::PerfPage {
+                        layout: &layout_shown,
+                        metrics,
+                        meta: &meta_shown,
+                        history,
+                        theme: &theme,
+                        daytime: color_mode.daytime(metrics.daytime()),
+                        alerts: &alerting,
+                        flash,
+                        hosts: &host_list,
+                    };
+
+                    // The top and hosts pages are static between updates, so ignore partial
+                    // frames.  Partial frames are also skipped while a notification is
+                    // shown, as animated widgets could draw over it.
+                    match (page_shown, frames.pop_front()) {
+                        (Page::Perf, Some(PerfFrame::Complete(frame))) => {
+                            gfx::draw_perf(frame_buf, &perf_page(&frame)).unwrap();
+                            if let Some(notification) = &notice {
+                                gfx::draw_notice(frame_buf, notification, &perf_page(&frame))
+                                    .unwrap();
+                            }
+                            display.draw_iter(frame_buf.into_iter()).unwrap();
+                        }
+                        (Page::Perf, Some(PerfFrame::Partial(frame))) if notice.is_none() => {
+                            gfx::draw_animated(display, &perf_page(&frame)).unwrap();
+                        }
+                        (Page::Top, Some(PerfFrame::Complete(frame))) => {
+                            gfx::draw_top(frame_buf, &perf_page(&frame)).unwrap();
+                            if let Some(notification) = &notice {
+                                gfx::draw_notice(frame_buf, notification, &perf_page(&frame))
+                                    .unwrap();
+                            }
+                            display.draw_iter(frame_buf.into_iter()).unwrap();
+                        }
+                        (Page::Hosts, Some(PerfFrame::Complete(frame))) => {
+                            let now_secs = instant.duration_since_epoch().to_secs();
+                            gfx::draw_hosts(frame_buf, &perf_page(&frame), now_secs).unwrap();
+                            if let Some(notification) = &notice {
+                                gfx::draw_notice(frame_buf, notification, &perf_page(&frame))
+                                    .unwrap();
+                            }
+                            display.draw_iter(frame_buf.into_iter()).unwrap();
+                        }
+                        _ => {}
                     }
-                    None => {}
-                }
-            });
+                },
+            );
         }
     }
 
-    #[task(priority = 2, shared = [display, msg_time])]
+    #[task(
+        priority = 2,
+        shared = [display, backlight, msg_time, host_state, clock, settings],
+    )]
     async fn no_data_timeout(ctx: no_data_timeout::Context) -> ! {
         let no_data_timeout::SharedResources {
             mut display,
+            mut backlight,
             mut msg_time,
+            mut host_state,
+            mut clock,
+            mut settings,
             ..
         } = ctx.shared;
 
@@ -341,7 +713,10 @@ mod app {
         enum TimeoutState {
             None,
             NoData,
+            // Showing the clock, at the given minute since the epoch.
+            Clock(i64),
             ClearScreen,
+            Asleep(SleepReason),
         }
         let mut state = TimeoutState::None;
 
@@ -349,6 +724,54 @@ mod app {
             Mono::delay(250.millis()).await;
             let instant = Mono::now();
 
+            let host = host_state.lock(|host_state| *host_state);
+            if matches!(state, TimeoutState::Asleep(_)) && !matches!(host, HostState::Asleep(_)) {
+                let current = settings.lock(|settings| *settings);
+                backlight.lock(|backlight| set_brightness(backlight, &current));
+            }
+
+            // The host reported why metrics stopped, so there is no need to report it.
+            match host {
+                HostState::Stopped => {
+                    if state != TimeoutState::ClearScreen {
+                        state = TimeoutState::ClearScreen;
+                        info!("Host stopped, clearing screen");
+                        display.lock(|display| display.clear(Rgb565::BLACK).ok());
+                    }
+                    continue;
+                }
+                HostState::Asleep(reason) => {
+                    if state != TimeoutState::Asleep(reason) {
+                        state = TimeoutState::Asleep(reason);
+                        info!("Host asleep, dimming screen");
+                        display.lock(|display| gfx::draw_sleep(display, reason).ok());
+                        let percent =
+                            settings.lock(|settings| settings.brightness.min(SLEEP_BRIGHTNESS));
+                        backlight.lock(|backlight| {
+                            unwrap!(backlight.channel_a.set_duty_cycle_percent(percent))
+                        });
+                    }
+                    continue;
+                }
+                HostState::Active => {}
+            }
+
+            // Zero disables blanking.
+            let (blank_ms, idle_clock) = settings.lock(|settings| {
+                let blank_ms = match settings.blank_timeout_secs {
+                    0 => u64::MAX,
+                    secs => secs as u64 * 1000,
+                };
+                (blank_ms, settings.idle_clock)
+            });
+            // Time advances from the last update by the host.
+            let local_time = clock.lock(|clock| {
+                clock.and_then(|(time, received)| {
+                    let elapsed = instant.checked_duration_since(received)?;
+                    Some(time.add_secs(elapsed.to_secs()))
+                })
+            });
+
             msg_time.lock(|msg_time| {
                 let elapsed = match instant.checked_duration_since(*msg_time) {
                     Some(elapsed) => elapsed,
@@ -361,8 +784,17 @@ mod app {
                 }
 
                 display.lock(|display| {
-                    if elapsed.to_millis() < BLANK_SCREEN_MS {
-                        if state != TimeoutState::NoData {
+                    if elapsed.to_millis() < blank_ms {
+                        if let Some(time) = local_time.filter(|_| idle_clock) {
+                            let minute = time.0.div_euclid(60);
+                            if state != TimeoutState::Clock(minute) {
+                                if !matches!(state, TimeoutState::Clock(_)) {
+                                    info!("No perf data received recently, showing clock");
+                                }
+                                state = TimeoutState::Clock(minute);
+                                gfx::draw_clock(display, &time.date_time()).ok();
+                            }
+                        } else if state != TimeoutState::NoData {
                             state = TimeoutState::NoData;
                             info!("No perf data received recently");
                             gfx::draw_message(display, "No data received").ok();
@@ -370,7 +802,7 @@ mod app {
                     } else if state != TimeoutState::ClearScreen {
                         state = TimeoutState::ClearScreen;
                         // TODO disable backlight
-                        warn!("No perf data received in {} ms", BLANK_SCREEN_MS);
+                        warn!("No perf data received in {} ms", blank_ms);
                         display.clear(Rgb565::BLACK).ok();
                     }
                 });
@@ -379,11 +811,11 @@ mod app {
     }
 }
 
-/// Handles high and low priority USB interrupts.
-fn handle_usb_event(serial: &mut io::Serial) {
-    let mut result = [0u8; io::BUF_BYTES];
-    let len = serial.read_packet(&mut result[..]).unwrap();
-    if len > 0 && app::handle_packet::spawn(result).is_err() {
-        error!("Failed to spawn handle_packet, likely still handling last packet")
+/// Handles high and low priority USB interrupts, queueing received packets for
+/// `handle_packet`.
+fn handle_usb_event(serial: &mut io::Serial, packets: &mut io::PacketProducer) {
+    let dropped = serial.read_packets(packets).unwrap();
+    if dropped > 0 {
+        error!("Packet queue full, dropped {} packets", dropped);
     }
 }
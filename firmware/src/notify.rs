@@ -0,0 +1,56 @@
+use crate::Mono;
+use defmt::warn;
+use fugit::ExtU64;
+use heapless::Deque;
+use rtic_monotonics::Monotonic;
+use shared::message::Notification;
+
+type Instant = <Mono as Monotonic>::Instant;
+
+// Maximum number of notifications waiting to be shown.
+const QUEUE_LEN: usize = 4;
+
+/// Notifications waiting to be shown, the first is shown until it expires.
+#[derive(Default)]
+pub struct NotificationQueue {
+    queue: Deque<Notification, QUEUE_LEN>,
+    // Expiry of the first notification, once it has been shown.
+    expires: Option<Instant>,
+}
+
+impl NotificationQueue {
+    pub const fn new() -> Self {
+        NotificationQueue {
+            queue: Deque::new(),
+            expires: None,
+        }
+    }
+
+    /// Queues a notification, dropping the oldest if the queue is full.
+    pub fn push(&mut self, notification: Notification) {
+        if self.queue.is_full() {
+            warn!("Notification queue full, dropping oldest");
+            self.next();
+        }
+        self.queue.push_back(notification).ok();
+    }
+
+    /// Returns the notification to show at `now`, moving on to the next as each expires.
+    pub fn current(&mut self, now: Instant) -> Option<&Notification> {
+        if matches!(self.expires, Some(expires) if expires <= now) {
+            self.next();
+        }
+
+        let front = self.queue.front()?;
+        if self.expires.is_none() {
+            self.expires = Some(now + (front.duration_secs.max(1) as u64).secs());
+        }
+
+        Some(front)
+    }
+
+    fn next(&mut self) {
+        self.queue.pop_front();
+        self.expires = None;
+    }
+}
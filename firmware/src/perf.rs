@@ -1,11 +1,14 @@
-use defmt::{error, info};
-use heapless::Deque;
-use shared::message::PerfData;
+use defmt::{error, info, warn};
+use heapless::{Deque, Vec};
+use shared::{
+    layout::{Layout, Widget},
+    metric::{self, Metric, MetricId, MetricLabel, Unit},
+};
 
 // Frames per second for interpolated display updates.
 const FRAMES_PER_SECOND: u32 = 18;
 
-// CPU bar fall-off rate in percentage points per second.
+// Smoothed metric fall-off rate in percentage points per second.
 const FALL_PCT_PER_SECOND: f32 = 70.0;
 
 /// Delay between animation frames in millseconds.
@@ -13,30 +16,166 @@ pub const FRAME_MS: u64 = 1000 / FRAMES_PER_SECOND as u64;
 
 const FALL_FRAC_PER_FRAME: f32 = FALL_PCT_PER_SECOND / 100.0 / FRAMES_PER_SECOND as f32;
 
+// Maximum number of distinct metrics tracked.
+const MAX_METRICS: usize = 48;
+
+/// Metric values to be displayed.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    values: Vec<(MetricId, f32), MAX_METRICS>,
+}
+
+impl Metrics {
+    pub const fn new() -> Self {
+        Metrics { values: Vec::new() }
+    }
+
+    pub fn get(&self, id: MetricId) -> Option<f32> {
+        self.values.iter().find(|(m, _)| *m == id).map(|(_, v)| *v)
+    }
+
+    /// Sets the value of a metric, ignoring new metrics once the table is full.
+    pub fn set(&mut self, id: MetricId, value: f32) {
+        match self.values.iter_mut().find(|(m, _)| *m == id) {
+            Some((_, v)) => *v = value,
+            None => {
+                if self.values.push((id, value)).is_err() {
+                    warn!("Metric table full, ignoring {}", id);
+                }
+            }
+        }
+    }
+
+    /// Returns the daytime flag sent by the host, defaulting to night.
+    pub fn daytime(&self) -> bool {
+        self.get(metric::DAYTIME).unwrap_or_default() >= 0.5
+    }
+}
+
+/// Describes how metrics are displayed, as sent alongside their values.
+#[derive(Clone, Default)]
+pub struct MetricsMeta {
+    entries: Vec<MetaEntry, MAX_METRICS>,
+}
+
+#[derive(Clone)]
+pub struct MetaEntry {
+    pub id: MetricId,
+    pub unit: Unit,
+    pub label: Option<MetricLabel>,
+    pub smoothed: bool,
+}
+
+impl MetricsMeta {
+    pub fn get(&self, id: MetricId) -> Option<&MetaEntry> {
+        self.entries.iter().find(|e| e.id == id)
+    }
+
+    /// Records how a metric is to be displayed, ignoring new metrics once the table is full.
+    pub fn update(&mut self, metric: &Metric) {
+        let entry = MetaEntry {
+            id: metric.id,
+            unit: metric.unit,
+            label: metric.label.clone(),
+            smoothed: metric.smoothed,
+        };
+
+        match self.entries.iter_mut().find(|e| e.id == metric.id) {
+            Some(existing) => *existing = entry,
+            None => {
+                self.entries.push(entry).ok();
+            }
+        }
+    }
+
+    fn smoothed(&self, id: MetricId) -> bool {
+        self.get(id).map(|e| e.smoothed).unwrap_or_default()
+    }
+}
+
 pub enum PerfFrame {
     // Complete frame should redraw the entire screen.
-    Complete(PerfData),
-    // Partial frame only updates CPU graphs.
-    Partial(PerfData),
+    Complete(Metrics),
+    // Partial frame only updates animated widgets.
+    Partial(Metrics),
 }
 
 // Frames of perf data queued for display.
 pub type FramesDeque = Deque<PerfFrame, 64>;
 
+/// Number of samples retained for sparklines, one per perf data update.
+pub const HISTORY_LEN: usize = 78;
+
+// Maximum number of metrics with history retained.
+const MAX_HISTORIES: usize = 4;
+
+/// Recent values of the metrics displayed as sparklines.
+#[derive(Default)]
+pub struct History {
+    metrics: Vec<(MetricId, Deque<u8, HISTORY_LEN>), MAX_HISTORIES>,
+}
+
+impl History {
+    /// Records the values of metrics shown as sparklines in the layout, and forgets
+    /// metrics that are no longer shown.
+    pub fn record(&mut self, layout: &Layout, metrics: &Metrics) {
+        let shown = |id: MetricId| {
+            layout
+                .widgets
+                .iter()
+                .any(|w| matches!(w, Widget::Sparkline { metric, .. } if *metric == id))
+        };
+        self.metrics.retain(|(id, _)| shown(*id));
+
+        for widget in &layout.widgets {
+            let Widget::Sparkline { metric, .. } = widget else {
+                continue;
+            };
+            let Some(value) = metrics.get(*metric) else {
+                continue;
+            };
+
+            if !self.metrics.iter().any(|(id, _)| id == metric)
+                && self.metrics.push((*metric, Deque::new())).is_err()
+            {
+                error!("Too many sparkline metrics");
+                continue;
+            }
+            if let Some((_, samples)) = self.metrics.iter_mut().find(|(id, _)| id == metric) {
+                if samples.is_full() {
+                    samples.pop_front();
+                }
+                samples
+                    .push_back((value.clamp(0.0, 1.0) * 255.0) as u8)
+                    .ok();
+            }
+        }
+    }
+
+    /// Returns recorded samples of a metric, oldest first, as 0-1.0 values.
+    pub fn samples(&self, id: MetricId) -> Option<impl ExactSizeIterator<Item = f32> + '_> {
+        self.metrics
+            .iter()
+            .find(|(metric, _)| *metric == id)
+            .map(|(_, samples)| samples.iter().map(|sample| *sample as f32 / 255.0))
+    }
+}
+
 /// Calculates what to display based on the previously stored state and new target state,
 /// if present.
 ///
-/// The returned PerfData should be stored as a basis for rendering future frames,
+/// The returned Metrics should be stored as a basis for rendering future frames,
 /// as it represents the final frame expected to be displayed on screen.
 pub fn update_state(
-    previous: Option<PerfData>,
-    target: PerfData,
+    previous: Option<Metrics>,
+    target: Metrics,
+    meta: &MetricsMeta,
     frames: &mut FramesDeque,
-) -> Option<PerfData> {
+) -> Option<Metrics> {
     match previous {
         // Displays new perf packet unaltered, as there is no history.
         None => {
-            frames.push_back(PerfFrame::Complete(target)).ok();
+            frames.push_back(PerfFrame::Complete(target.clone())).ok();
             Some(target)
         }
 
@@ -53,23 +192,25 @@ pub fn update_state(
             }
 
             // Generate upcoming frames. Does not schedule frame at 1s, as that
-            // is when the next PerfData packet should arrive from the host.
+            // is when the next metrics commit should arrive from the host.
             let mut prev = prev;
             for i in 0..FRAMES_PER_SECOND {
-                // Calculate perf data for this frame, store in prev for basis of next frame.
-                prev = PerfData {
-                    all_cores_load: update_cpu_load(prev.all_cores_load, target.all_cores_load),
-                    all_cores_avg: target.all_cores_avg,
-                    peak_core_load: update_cpu_load(prev.peak_core_load, target.peak_core_load),
-                    memory_load: target.memory_load,
-                    daytime: target.daytime,
-                };
+                // Calculate metrics for this frame, store in prev for basis of next frame.
+                let mut next = target.clone();
+                for (id, value) in next.values.iter_mut() {
+                    if meta.smoothed(*id) {
+                        if let Some(prev_value) = prev.get(*id) {
+                            *value = update_smoothed(prev_value, *value);
+                        }
+                    }
+                }
+                prev = next;
 
                 let frame = if i == 0 {
-                    PerfFrame::Complete(prev)
+                    PerfFrame::Complete(prev.clone())
                 } else {
-                    // Only paint CPU bar graphs.
-                    PerfFrame::Partial(prev)
+                    // Only paint animated widgets.
+                    PerfFrame::Partial(prev.clone())
                 };
 
                 if frames.push_back(frame).is_err() {
@@ -85,14 +226,15 @@ pub fn update_state(
     }
 }
 
-// Approximates a VU-meter, jumps up quickly, falls slowly.
-fn update_cpu_load(prev_load: f32, target_load: f32) -> f32 {
-    if target_load > prev_load {
-        // Jump to higher loads immediately.
-        target_load
+// Approximates a VU-meter, jumps up quickly, falls slowly.  Values larger than 1.0 fall
+// proportionally faster, so that any unit falls at a similar visual rate.
+fn update_smoothed(prev_value: f32, target_value: f32) -> f32 {
+    if target_value > prev_value {
+        // Jump to higher values immediately.
+        target_value
     } else {
-        // Ease in to lower loads.
-        // debug!("target: {}, prev: {}, fallcfg: {}", target_load, prev_load, FALL_FRAC_PER_FRAME);
-        f32::max(target_load, prev_load - FALL_FRAC_PER_FRAME)
+        // Ease in to lower values.
+        let fall = FALL_FRAC_PER_FRAME * f32::max(prev_value.abs(), 1.0);
+        f32::max(target_value, prev_value - fall)
     }
 }
@@ -0,0 +1,120 @@
+use cortex_m::interrupt;
+use defmt::{info, warn};
+use rp2040_hal::rom_data;
+use shared::settings::{self, Settings, SLOT_BYTES};
+
+// XIP address flash is mapped to.
+const XIP_BASE: u32 = 0x1000_0000;
+
+// Offset of the settings sector from the start of flash, must match memory.x.
+const SECTOR_OFFSET: u32 = 2048 * 1024 - SECTOR_BYTES as u32;
+const SECTOR_BYTES: usize = 4096;
+
+// Flash may only be programmed in whole pages.
+const PAGE_BYTES: usize = 256;
+
+// 4K sector erase command.
+const SECTOR_ERASE_CMD: u8 = 0x20;
+
+// Size of the second stage bootloader at the start of flash.
+const BOOT2_WORDS: usize = 64;
+
+/// Loads the most recently saved settings, or defaults if none are present.
+pub fn load() -> Settings {
+    match settings::scan(sector()).latest {
+        Some(settings) => settings,
+        None => {
+            info!("No valid settings in flash, using defaults");
+            Settings::default()
+        }
+    }
+}
+
+/// Saves settings to the next free slot in the settings sector, erasing the sector first
+/// if every slot has been used.
+///
+/// Interrupts are disabled while flash is being modified, as XIP is unavailable.
+pub fn save(new_settings: &Settings) {
+    let mut slot = [0u8; SLOT_BYTES];
+    if settings::encode(new_settings, &mut slot).is_err() {
+        warn!("Settings too large to save");
+        return;
+    }
+
+    let (erase, index) = match settings::scan(sector()).next_free {
+        Some(index) => (false, index),
+        None => (true, 0),
+    };
+
+    // Unused bytes of the page are left erased, so other slots in it are not disturbed.
+    let slot_offset = index * SLOT_BYTES;
+    let page_start = slot_offset - slot_offset % PAGE_BYTES;
+    let mut page = [0xffu8; PAGE_BYTES];
+    page[slot_offset - page_start..][..SLOT_BYTES].copy_from_slice(&slot);
+
+    info!("Saving settings to slot {} (erase: {})", index, erase);
+    interrupt::free(|_| unsafe {
+        write_flash(SECTOR_OFFSET + page_start as u32, &page, erase);
+    });
+}
+
+// Returns the settings sector via its memory mapped XIP address.
+fn sector() -> &'static [u8] {
+    unsafe { core::slice::from_raw_parts((XIP_BASE + SECTOR_OFFSET) as *const u8, SECTOR_BYTES) }
+}
+
+/// Looks up the ROM flash routines, then hands off to a function in RAM to call them.
+///
+/// A copy of boot2 is made so that fast XIP can be restored afterwards, the ROM routines
+/// only know how to re-enter slow XIP mode.
+///
+/// # Safety
+///
+/// Must be called with interrupts disabled; no code may execute from flash until it returns.
+unsafe fn write_flash(offset: u32, page: &[u8; PAGE_BYTES], erase: bool) {
+    let mut boot2 = [0u32; BOOT2_WORDS];
+    core::ptr::copy_nonoverlapping(XIP_BASE as *const u32, boot2.as_mut_ptr(), BOOT2_WORDS);
+
+    let rom = RomFlash {
+        connect_internal_flash: rom_data::connect_internal_flash::ptr(),
+        flash_exit_xip: rom_data::flash_exit_xip::ptr(),
+        flash_range_erase: rom_data::flash_range_erase::ptr(),
+        flash_range_program: rom_data::flash_range_program::ptr(),
+        flash_flush_cache: rom_data::flash_flush_cache::ptr(),
+        flash_enter_cmd_xip: rom_data::flash_enter_cmd_xip::ptr(),
+        // Set the thumb bit to call boot2 as a function.
+        boot2: core::mem::transmute::<usize, unsafe extern "C" fn()>(boot2.as_ptr() as usize + 1),
+    };
+
+    write_flash_in_ram(&rom, offset, page.as_ptr(), erase);
+}
+
+struct RomFlash {
+    connect_internal_flash: unsafe extern "C" fn(),
+    flash_exit_xip: unsafe extern "C" fn(),
+    flash_range_erase: unsafe extern "C" fn(u32, usize, u32, u8),
+    flash_range_program: unsafe extern "C" fn(u32, *const u8, usize),
+    flash_flush_cache: unsafe extern "C" fn(),
+    flash_enter_cmd_xip: unsafe extern "C" fn(),
+    boot2: unsafe extern "C" fn(),
+}
+
+// Runs from RAM, as XIP is disabled while the flash is being erased or programmed.
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+unsafe fn write_flash_in_ram(rom: &RomFlash, offset: u32, data: *const u8, erase: bool) {
+    (rom.connect_internal_flash)();
+    (rom.flash_exit_xip)();
+    if erase {
+        (rom.flash_range_erase)(
+            SECTOR_OFFSET,
+            SECTOR_BYTES,
+            SECTOR_BYTES as u32,
+            SECTOR_ERASE_CMD,
+        );
+    }
+    (rom.flash_range_program)(offset, data, PAGE_BYTES);
+    (rom.flash_flush_cache)();
+    (rom.flash_enter_cmd_xip)();
+    (rom.boot2)();
+}
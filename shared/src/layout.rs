@@ -0,0 +1,203 @@
+use crate::metric::{self, MetricId};
+use heapless::{String, Vec};
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of widgets in a layout.
+pub const MAX_WIDGETS: usize = 10;
+
+/// Number of text rows that fit on the display.
+pub const ROWS: u8 = 5;
+
+pub type Label = String<12>;
+pub type Suffix = String<8>;
+
+/// Describes which widgets appear on the performance page, and where.
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Layout {
+    pub widgets: Vec<Widget, MAX_WIDGETS>,
+}
+
+/// A single element of a layout.  Widgets are positioned by row, bars span the width of the
+/// display.
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Widget {
+    // Fixed text.
+    Label {
+        row: u8,
+        align: Align,
+        text: Label,
+    },
+    // Formatted metric value followed by a suffix, the metric label is used if the suffix
+    // is empty.
+    Value {
+        row: u8,
+        align: Align,
+        metric: MetricId,
+        format: Format,
+        suffix: Suffix,
+    },
+    // Bar graph of a single metric.
+    Bar {
+        row: u8,
+        metric: MetricId,
+    },
+    // Overlaid bar graphs, the left metric is expected to be smaller than the right.
+    DoubleBar {
+        row: u8,
+        left: MetricId,
+        right: MetricId,
+    },
+    // Recent history of a metric.
+    Sparkline {
+        row: u8,
+        metric: MetricId,
+    },
+    // Used memory with cache stacked after it, above a thin swap bar.
+    Memory {
+        row: u8,
+        used: MetricId,
+        cache: MetricId,
+        swap: MetricId,
+    },
+    // Compact line of load averages, process counts and uptime.
+    Status {
+        row: u8,
+    },
+    // CPU, memory and IO pressure, colored by severity.
+    Pressure {
+        row: u8,
+    },
+    // Average and fastest core frequency, with a badge while thermally throttled.
+    Frequency {
+        row: u8,
+    },
+    // Battery charge and power, centered on the row.  Hidden unless a battery is present.
+    Battery {
+        row: u8,
+    },
+}
+
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Align {
+    Left,
+    Right,
+}
+
+/// How a metric value is rendered as text.
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    // Whole percent, e.g. " 5".
+    Percent,
+    // Percent with tenths, e.g. " 5.2".
+    PercentTenths,
+    // Whole percent remaining, for example free memory from memory load.
+    RemainingPercent,
+    // Value in its unit, scaled to fit, e.g. "1.5G" or "45C".
+    Number,
+}
+
+impl Widget {
+    pub fn row(&self) -> u8 {
+        match self {
+            Widget::Label { row, .. }
+            | Widget::Value { row, .. }
+            | Widget::Bar { row, .. }
+            | Widget::DoubleBar { row, .. }
+            | Widget::Sparkline { row, .. }
+            | Widget::Memory { row, .. }
+            | Widget::Status { row }
+            | Widget::Pressure { row }
+            | Widget::Frequency { row }
+            | Widget::Battery { row } => *row,
+        }
+    }
+
+    /// Returns true for widgets that are redrawn between complete frames, to animate
+    /// changing values.
+    pub fn is_animated(&self) -> bool {
+        matches!(
+            self,
+            Widget::Bar { .. }
+                | Widget::DoubleBar { .. }
+                | Widget::Memory { .. }
+                | Widget::Frequency { .. }
+        )
+    }
+}
+
+impl Default for Layout {
+    /// The original hw-gauge display: CPU average and bars, followed by free and used memory,
+    /// with cache and swap usage, battery and a system status line where the host reports
+    /// them.
+    fn default() -> Self {
+        let mut widgets = Vec::new();
+        let _ = widgets.push(label(0, "CPU"));
+        let _ = widgets.push(Widget::Value {
+            row: 0,
+            align: Align::Right,
+            metric: metric::CPU_AVG,
+            format: Format::PercentTenths,
+            suffix: String::from("% Avg"),
+        });
+        let _ = widgets.push(Widget::DoubleBar {
+            row: 1,
+            left: metric::CPU_LOAD,
+            right: metric::CPU_PEAK,
+        });
+        let _ = widgets.push(label(2, "RAM"));
+        let _ = widgets.push(Widget::Value {
+            row: 2,
+            align: Align::Right,
+            metric: metric::MEM_LOAD,
+            format: Format::RemainingPercent,
+            suffix: String::from("% Free"),
+        });
+        let _ = widgets.push(Widget::Battery { row: 2 });
+        let _ = widgets.push(Widget::Memory {
+            row: 3,
+            used: metric::MEM_LOAD,
+            cache: metric::MEM_CACHE,
+            swap: metric::SWAP_LOAD,
+        });
+        let _ = widgets.push(Widget::Status { row: 4 });
+
+        Layout { widgets }
+    }
+}
+
+fn label(row: u8, text: &str) -> Widget {
+    Widget::Label {
+        row,
+        align: Align::Left,
+        text: String::from(text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_layout_fits_display() {
+        let layout = Layout::default();
+
+        assert!(layout.widgets.iter().all(|w| w.row() < ROWS));
+    }
+
+    #[test]
+    fn postcard_roundtrip() {
+        let layout = Layout::default();
+        let mut buf = [0u8; 256];
+
+        let bytes = postcard::to_slice(&layout, &mut buf).unwrap();
+        let actual: Layout = postcard::from_bytes(bytes).unwrap();
+
+        assert_eq!(actual, layout);
+    }
+}
@@ -1,6 +1,11 @@
 #![no_std]
 
+pub mod layout;
 pub mod message;
+pub mod metric;
+pub mod settings;
+pub mod theme;
+pub mod time;
 
 #[cfg(test)]
 mod tests {
@@ -1,10 +1,69 @@
+use crate::layout::Layout;
+use crate::metric::{self, Metric, MetricBatch, MetricId};
+use crate::settings::{Page, Settings};
+use crate::theme::Theme;
+use crate::time::LocalTime;
+use heapless::{String, Vec};
 use serde::{Deserialize, Serialize};
 
+/// Largest COBS encoded frame, including terminator, either side will send.
+pub const MAX_FRAME_BYTES: usize = 256;
+
+/// Frames the device queues while handling earlier ones.  The host sends fewer than this each
+/// period, so a slow redraw does not lose frames.
+pub const FRAME_QUEUE_LEN: usize = 16;
+
+/// Longest notification text, in bytes.
+pub const NOTIFY_TEXT_LEN: usize = 64;
+
+/// Most metrics that may be alerting at once.
+pub const MAX_ALERTS: usize = 8;
+
+/// Most hosts shown on the hosts page.
+pub const MAX_HOSTS: usize = 4;
+
+/// Longest host name, in bytes.
+pub const HOST_NAME_LEN: usize = 12;
+
+// Messages are deserialized in place on the device, where boxing is not an option.
+#[allow(clippy::large_enum_variant)]
 #[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
 #[derive(Debug, Serialize, Deserialize)]
 pub enum FromHost {
+    // Blanks the screen as the host stops, until metrics are next received.
     ClearScreen,
+    // Fixed set of metrics, retained for compatibility with older hosts.  Equivalent to
+    // committing the metrics returned by `PerfData::metrics`.
     ShowPerf(PerfData),
+    // Updates any number of metrics by ID.
+    UpdateMetrics(MetricBatch),
+    // Requests the device reply with its current settings.
+    GetSettings,
+    // Applies and persists settings, the device replies with the stored settings.
+    SetSettings(Settings),
+    // Applies and persists a color theme.
+    SetTheme(Theme),
+    // Replaces the widgets shown on the performance page.
+    SetLayout(Layout),
+    // Switches the page displayed, until the next switch or power cycle.
+    ShowPage(Page),
+    // Briefly shows a text notification over the current page.
+    Notify(Notification),
+    // Replaces the set of metrics in an alert state, empty once all have recovered.
+    SetAlerts(Alerts),
+    // Replaces the hosts shown on the hosts page.
+    SetHosts(Hosts),
+    // Shows the host is asleep or locked, dimmed, until metrics are next received.
+    Sleep(SleepReason),
+    // Sets the device clock, shown while idle.  Sent periodically, as the device has no
+    // battery backed clock and follows the host's time zone changes.
+    SetTime(LocalTime),
+}
+
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Debug, Serialize, Deserialize)]
+pub enum FromDevice {
+    Settings(Settings),
 }
 
 #[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
@@ -21,3 +80,257 @@ pub struct PerfData {
     // Daytime or nightime display mode.
     pub daytime: bool,
 }
+
+/// One-shot text notification, e.g. a finished build.
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Notification {
+    // Wrapped to fit the screen by the device.
+    pub text: String<NOTIFY_TEXT_LEN>,
+    pub level: NotifyLevel,
+    // Seconds to show the notification, once it reaches the front of the queue.
+    pub duration_secs: u16,
+}
+
+/// Metrics whose alert rules have triggered on the host.
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Alerts {
+    pub metrics: Vec<MetricId, MAX_ALERTS>,
+}
+
+impl Alerts {
+    /// Returns true if any metric is alerting.
+    pub fn is_active(&self) -> bool {
+        !self.metrics.is_empty()
+    }
+
+    /// Returns true if the metric is alerting.
+    pub fn contains(&self, id: MetricId) -> bool {
+        self.metrics.contains(&id)
+    }
+}
+
+/// Hosts aggregated by the daemon, and how the hosts page shows them.
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Hosts {
+    pub hosts: Vec<HostSummary, MAX_HOSTS>,
+    pub view: HostView,
+}
+
+/// Latest CPU and memory load of a single host.
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HostSummary {
+    pub name: String<HOST_NAME_LEN>,
+    // Aggregate load of all CPU cores, 0-1.0.
+    pub cpu_load: f32,
+    // Memory load, 0-1.0.
+    pub memory_load: f32,
+    // Set once the host has stopped reporting, loads are the last received.
+    pub stale: bool,
+}
+
+/// Selects between showing all hosts at once, or cycling through them.
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HostView {
+    // One row per host.
+    #[default]
+    Overview,
+    // One host at a time, each for the given number of seconds.
+    Rotate(u16),
+}
+
+/// Why the host stopped sending metrics for a while.
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SleepReason {
+    // The host is about to suspend or hibernate.
+    Suspend,
+    // The user's session is locked.
+    Lock,
+}
+
+/// Severity of a notification, selecting its color.
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifyLevel {
+    #[default]
+    Info,
+    Warning,
+    Error,
+}
+
+impl core::str::FromStr for NotifyLevel {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "info" => Ok(NotifyLevel::Info),
+            "warning" => Ok(NotifyLevel::Warning),
+            "error" => Ok(NotifyLevel::Error),
+            _ => Err(()),
+        }
+    }
+}
+
+impl PerfData {
+    /// Returns the value of a well-known metric carried by PerfData.
+    pub fn metric(&self, id: MetricId) -> Option<f32> {
+        match id {
+            metric::CPU_LOAD => Some(self.all_cores_load),
+            metric::CPU_AVG => Some(self.all_cores_avg),
+            metric::CPU_PEAK => Some(self.peak_core_load),
+            metric::MEM_LOAD => Some(self.memory_load),
+            metric::DAYTIME => Some(if self.daytime { 1.0 } else { 0.0 }),
+            _ => None,
+        }
+    }
+
+    /// Converts PerfData into the equivalent well-known metrics.
+    pub fn metrics(&self) -> impl Iterator<Item = Metric> + '_ {
+        const IDS: [MetricId; 5] = [
+            metric::CPU_LOAD,
+            metric::CPU_AVG,
+            metric::CPU_PEAK,
+            metric::MEM_LOAD,
+            metric::DAYTIME,
+        ];
+
+        IDS.iter()
+            .filter_map(move |id| Some(Metric::new(*id, self.metric(*id)?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{Align, Format, Widget, MAX_WIDGETS};
+    use crate::metric::MAX_BATCH;
+
+    fn frame_len<T: Serialize>(msg: &T) -> usize {
+        let mut buf = [0u8; 256];
+        postcard::to_slice_cobs(msg, &mut buf).unwrap().len()
+    }
+
+    #[test]
+    fn largest_messages_fit_frame() {
+        let settings = Settings {
+            theme: Theme::SOLARIZED,
+            ..Settings::default()
+        };
+
+        assert!(frame_len(&FromHost::SetSettings(settings)) <= MAX_FRAME_BYTES);
+        assert!(frame_len(&FromHost::SetTheme(Theme::SOLARIZED)) <= MAX_FRAME_BYTES);
+        assert!(frame_len(&FromDevice::Settings(settings)) <= MAX_FRAME_BYTES);
+    }
+
+    #[test]
+    fn longest_notification_fits_frame() {
+        let mut text = String::new();
+        for _ in 0..NOTIFY_TEXT_LEN {
+            text.push('x').unwrap();
+        }
+
+        let notification = Notification {
+            text,
+            level: NotifyLevel::Error,
+            duration_secs: u16::MAX,
+        };
+
+        assert!(frame_len(&FromHost::Notify(notification)) <= MAX_FRAME_BYTES);
+    }
+
+    #[test]
+    fn largest_alerts_fit_frame() {
+        let mut alerts = Alerts::default();
+        for _ in 0..MAX_ALERTS {
+            alerts.metrics.push(MetricId(u16::MAX)).unwrap();
+        }
+
+        assert!(frame_len(&FromHost::SetAlerts(alerts)) <= MAX_FRAME_BYTES);
+    }
+
+    #[test]
+    fn largest_hosts_fit_frame() {
+        let mut hosts = Hosts {
+            view: HostView::Rotate(u16::MAX),
+            ..Default::default()
+        };
+        for _ in 0..MAX_HOSTS {
+            let mut name = String::new();
+            for _ in 0..HOST_NAME_LEN {
+                name.push('x').unwrap();
+            }
+            let host = HostSummary {
+                name,
+                cpu_load: f32::MAX,
+                memory_load: f32::MAX,
+                stale: true,
+            };
+            hosts.hosts.push(host).unwrap();
+        }
+
+        assert!(frame_len(&FromHost::SetHosts(hosts)) <= MAX_FRAME_BYTES);
+    }
+
+    #[test]
+    fn parse_notify_level() {
+        assert_eq!("warning".parse(), Ok(NotifyLevel::Warning));
+        assert_eq!("fatal".parse::<NotifyLevel>(), Err(()));
+    }
+
+    #[test]
+    fn largest_batch_fits_frame() {
+        let mut batch = MetricBatch::default();
+        for _ in 0..MAX_BATCH {
+            let mut metric = Metric::new(MetricId(u16::MAX), f32::MAX);
+            metric.label = Some("12345678".into());
+            batch.metrics.push(metric).unwrap();
+        }
+
+        assert!(frame_len(&FromHost::UpdateMetrics(batch)) <= MAX_FRAME_BYTES);
+    }
+
+    #[test]
+    fn perf_data_metrics() {
+        let perf = PerfData {
+            all_cores_load: 0.25,
+            all_cores_avg: 0.5,
+            peak_core_load: 0.75,
+            memory_load: 1.0,
+            daytime: true,
+        };
+
+        let mut metrics = perf.metrics();
+
+        assert_eq!(metrics.next(), Some(Metric::new(metric::CPU_LOAD, 0.25)));
+        assert_eq!(metrics.next(), Some(Metric::new(metric::CPU_AVG, 0.5)));
+        assert_eq!(metrics.next(), Some(Metric::new(metric::CPU_PEAK, 0.75)));
+        assert_eq!(metrics.next(), Some(Metric::new(metric::MEM_LOAD, 1.0)));
+        assert_eq!(metrics.next(), Some(Metric::new(metric::DAYTIME, 1.0)));
+        assert_eq!(metrics.next(), None);
+    }
+
+    #[test]
+    fn largest_layout_fits_frame() {
+        let mut layout = Layout {
+            widgets: Default::default(),
+        };
+        for _ in 0..MAX_WIDGETS {
+            let widget = Widget::Value {
+                row: 0,
+                align: Align::Right,
+                metric: MetricId(u16::MAX),
+                format: Format::PercentTenths,
+                suffix: "12345678".into(),
+            };
+            layout.widgets.push(widget).unwrap();
+        }
+
+        assert!(frame_len(&FromHost::SetLayout(layout)) <= MAX_FRAME_BYTES);
+    }
+}
@@ -0,0 +1,452 @@
+use heapless::{String, Vec};
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of metrics in a single update message.
+pub const MAX_BATCH: usize = 8;
+
+pub type MetricLabel = String<8>;
+
+/// Identifies a value the host reports, such as CPU load.
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MetricId(pub u16);
+
+// Aggregate load of all CPU cores, 0-1.0.
+pub const CPU_LOAD: MetricId = MetricId(1);
+// All cores load averaged over time, 0-1.0.
+pub const CPU_AVG: MetricId = MetricId(2);
+// Load on peak core, 0-1.0.
+pub const CPU_PEAK: MetricId = MetricId(3);
+// Memory load, 0-1.0.
+pub const MEM_LOAD: MetricId = MetricId(4);
+// Daytime display mode, 1.0 for day and 0.0 for night.
+pub const DAYTIME: MetricId = MetricId(5);
+// Memory used by caches and not counted in MEM_LOAD, 0-1.0.
+pub const MEM_CACHE: MetricId = MetricId(6);
+// Swap load, 0-1.0.
+pub const SWAP_LOAD: MetricId = MetricId(7);
+// Memory used, in bytes.
+pub const MEM_USED: MetricId = MetricId(8);
+// Memory available for new allocations, in bytes.
+pub const MEM_AVAILABLE: MetricId = MetricId(9);
+// Memory used by caches, in bytes.
+pub const MEM_CACHED: MetricId = MetricId(10);
+// Swap used, in bytes.
+pub const SWAP_USED: MetricId = MetricId(11);
+// Load averages over 1, 5 and 15 minutes, divided by the number of cores.
+pub const LOAD_1: MetricId = MetricId(12);
+pub const LOAD_5: MetricId = MetricId(13);
+pub const LOAD_15: MetricId = MetricId(14);
+// Number of runnable processes.
+pub const PROCS_RUNNING: MetricId = MetricId(15);
+// Number of processes.
+pub const PROCS_TOTAL: MetricId = MetricId(16);
+// Time since boot, in seconds.
+pub const UPTIME: MetricId = MetricId(17);
+// Share of time some or all runnable tasks stalled on a resource over the last 10 seconds,
+// 0-1.0, from Linux pressure stall information.
+pub const PSI_CPU_SOME: MetricId = MetricId(18);
+pub const PSI_CPU_FULL: MetricId = MetricId(19);
+pub const PSI_MEM_SOME: MetricId = MetricId(20);
+pub const PSI_MEM_FULL: MetricId = MetricId(21);
+pub const PSI_IO_SOME: MetricId = MetricId(22);
+pub const PSI_IO_FULL: MetricId = MetricId(23);
+// Average and fastest core frequency, relative to the maximum, 0-1.0.
+pub const CPU_FREQ_AVG: MetricId = MetricId(24);
+pub const CPU_FREQ_MAX: MetricId = MetricId(25);
+// Average and fastest core frequency, in MHz.
+pub const CPU_MHZ_AVG: MetricId = MetricId(26);
+pub const CPU_MHZ_MAX: MetricId = MetricId(27);
+// 1.0 if the CPU was thermally throttled since the previous update, otherwise 0.0.
+pub const CPU_THROTTLED: MetricId = MetricId(28);
+// Battery charge, 0-1.0.  Only reported when a battery is present.
+pub const BATTERY_LEVEL: MetricId = MetricId(29);
+// 1.0 while the battery is charging, otherwise 0.0.
+pub const BATTERY_CHARGING: MetricId = MetricId(30);
+// Battery charge or discharge rate, in watts.
+pub const BATTERY_POWER: MetricId = MetricId(31);
+
+/// Number of processes reported in each top consumer list.
+pub const TOP_COUNT: usize = 5;
+
+// Ranked processes by CPU load, 0-1.0 of all cores, labelled with the process name.
+const TOP_CPU: u16 = 0x100;
+// Ranked processes by resident memory, 0-1.0 of total memory, labelled with the process name.
+const TOP_MEM: u16 = 0x110;
+
+/// Returns the ID of the top CPU consumer at `rank`, starting from 0.
+pub const fn top_cpu(rank: usize) -> MetricId {
+    MetricId(TOP_CPU + rank as u16)
+}
+
+/// Returns the ID of the top memory consumer at `rank`, starting from 0.
+pub const fn top_mem(rank: usize) -> MetricId {
+    MetricId(TOP_MEM + rank as u16)
+}
+
+/// IDs at or above this value are free for site specific metrics.
+pub const FIRST_CUSTOM: MetricId = MetricId(0x8000);
+
+/// Returns the ID of a site specific metric slot, named `custom_<slot>` in configuration.
+pub const fn custom(slot: u16) -> MetricId {
+    MetricId(FIRST_CUSTOM.0 + (slot & 0x7fff))
+}
+
+/// Unit of a metric value, used when rendering it as text.
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Unit {
+    None,
+    // Fraction of a whole, 0-1.0.
+    Ratio,
+    Bytes,
+    Seconds,
+    Celsius,
+    Megahertz,
+    Watts,
+}
+
+/// A single metric value sent by the host.  A NaN value indicates its source failed.
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Metric {
+    pub id: MetricId,
+    pub value: f32,
+    pub unit: Unit,
+    // Short description, for metrics without a well-known ID.
+    pub label: Option<MetricLabel>,
+    // Animate towards new values, rather than jumping to them.
+    pub smoothed: bool,
+}
+
+impl Metric {
+    /// Creates a metric, taking the unit and smoothing from the registry of well-known
+    /// metrics.  Unknown metrics are unitless and unsmoothed.
+    pub fn new(id: MetricId, value: f32) -> Metric {
+        let (unit, smoothed) = match info(id) {
+            Some(info) => (info.unit, info.smoothed),
+            None => (Unit::None, false),
+        };
+
+        Metric {
+            id,
+            value,
+            unit,
+            label: None,
+            smoothed,
+        }
+    }
+}
+
+/// A group of metric updates.  The device begins displaying the accumulated values once it
+/// receives a batch with `commit` set.
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MetricBatch {
+    pub metrics: Vec<Metric, MAX_BATCH>,
+    pub commit: bool,
+}
+
+impl MetricBatch {
+    /// Splits metrics into batches small enough to send, committing with the last.
+    pub fn split(metrics: &[Metric]) -> impl Iterator<Item = MetricBatch> + '_ {
+        let count = metrics.len().div_ceil(MAX_BATCH).max(1);
+        (0..count).map(move |i| {
+            let chunk = metrics.chunks(MAX_BATCH).nth(i).unwrap_or_default();
+            MetricBatch {
+                metrics: chunk.iter().cloned().collect(),
+                commit: i + 1 == count,
+            }
+        })
+    }
+}
+
+/// Describes a well-known metric.
+#[derive(Debug)]
+pub struct MetricInfo {
+    pub id: MetricId,
+    // Name used to refer to the metric in configuration.
+    pub name: &'static str,
+    pub unit: Unit,
+    // Values are animated on the device.
+    pub smoothed: bool,
+}
+
+/// Registry of metrics with well-known IDs.
+pub const WELL_KNOWN: &[MetricInfo] = &[
+    MetricInfo {
+        id: CPU_LOAD,
+        name: "cpu_load",
+        unit: Unit::Ratio,
+        smoothed: true,
+    },
+    MetricInfo {
+        id: CPU_AVG,
+        name: "cpu_avg",
+        unit: Unit::Ratio,
+        smoothed: false,
+    },
+    MetricInfo {
+        id: CPU_PEAK,
+        name: "cpu_peak",
+        unit: Unit::Ratio,
+        smoothed: true,
+    },
+    MetricInfo {
+        id: MEM_LOAD,
+        name: "mem_load",
+        unit: Unit::Ratio,
+        smoothed: false,
+    },
+    MetricInfo {
+        id: DAYTIME,
+        name: "daytime",
+        unit: Unit::None,
+        smoothed: false,
+    },
+    MetricInfo {
+        id: MEM_CACHE,
+        name: "mem_cache",
+        unit: Unit::Ratio,
+        smoothed: false,
+    },
+    MetricInfo {
+        id: SWAP_LOAD,
+        name: "swap_load",
+        unit: Unit::Ratio,
+        smoothed: false,
+    },
+    MetricInfo {
+        id: MEM_USED,
+        name: "mem_used",
+        unit: Unit::Bytes,
+        smoothed: false,
+    },
+    MetricInfo {
+        id: MEM_AVAILABLE,
+        name: "mem_available",
+        unit: Unit::Bytes,
+        smoothed: false,
+    },
+    MetricInfo {
+        id: MEM_CACHED,
+        name: "mem_cached",
+        unit: Unit::Bytes,
+        smoothed: false,
+    },
+    MetricInfo {
+        id: SWAP_USED,
+        name: "swap_used",
+        unit: Unit::Bytes,
+        smoothed: false,
+    },
+    MetricInfo {
+        id: LOAD_1,
+        name: "load_1",
+        unit: Unit::Ratio,
+        smoothed: false,
+    },
+    MetricInfo {
+        id: LOAD_5,
+        name: "load_5",
+        unit: Unit::Ratio,
+        smoothed: false,
+    },
+    MetricInfo {
+        id: LOAD_15,
+        name: "load_15",
+        unit: Unit::Ratio,
+        smoothed: false,
+    },
+    MetricInfo {
+        id: PROCS_RUNNING,
+        name: "procs_running",
+        unit: Unit::None,
+        smoothed: false,
+    },
+    MetricInfo {
+        id: PROCS_TOTAL,
+        name: "procs_total",
+        unit: Unit::None,
+        smoothed: false,
+    },
+    MetricInfo {
+        id: UPTIME,
+        name: "uptime",
+        unit: Unit::Seconds,
+        smoothed: false,
+    },
+    MetricInfo {
+        id: PSI_CPU_SOME,
+        name: "psi_cpu_some",
+        unit: Unit::Ratio,
+        smoothed: false,
+    },
+    MetricInfo {
+        id: PSI_CPU_FULL,
+        name: "psi_cpu_full",
+        unit: Unit::Ratio,
+        smoothed: false,
+    },
+    MetricInfo {
+        id: PSI_MEM_SOME,
+        name: "psi_mem_some",
+        unit: Unit::Ratio,
+        smoothed: false,
+    },
+    MetricInfo {
+        id: PSI_MEM_FULL,
+        name: "psi_mem_full",
+        unit: Unit::Ratio,
+        smoothed: false,
+    },
+    MetricInfo {
+        id: PSI_IO_SOME,
+        name: "psi_io_some",
+        unit: Unit::Ratio,
+        smoothed: false,
+    },
+    MetricInfo {
+        id: PSI_IO_FULL,
+        name: "psi_io_full",
+        unit: Unit::Ratio,
+        smoothed: false,
+    },
+    MetricInfo {
+        id: CPU_FREQ_AVG,
+        name: "cpu_freq_avg",
+        unit: Unit::Ratio,
+        smoothed: false,
+    },
+    MetricInfo {
+        id: CPU_FREQ_MAX,
+        name: "cpu_freq_max",
+        unit: Unit::Ratio,
+        smoothed: true,
+    },
+    MetricInfo {
+        id: CPU_MHZ_AVG,
+        name: "cpu_mhz_avg",
+        unit: Unit::Megahertz,
+        smoothed: false,
+    },
+    MetricInfo {
+        id: CPU_MHZ_MAX,
+        name: "cpu_mhz_max",
+        unit: Unit::Megahertz,
+        smoothed: false,
+    },
+    MetricInfo {
+        id: CPU_THROTTLED,
+        name: "cpu_throttled",
+        unit: Unit::None,
+        smoothed: false,
+    },
+    MetricInfo {
+        id: BATTERY_LEVEL,
+        name: "battery_level",
+        unit: Unit::Ratio,
+        smoothed: false,
+    },
+    MetricInfo {
+        id: BATTERY_CHARGING,
+        name: "battery_charging",
+        unit: Unit::None,
+        smoothed: false,
+    },
+    MetricInfo {
+        id: BATTERY_POWER,
+        name: "battery_power",
+        unit: Unit::Watts,
+        smoothed: false,
+    },
+];
+
+/// Looks up a well-known metric by name.
+pub fn by_name(name: &str) -> Option<MetricId> {
+    if let Some(slot) = name.strip_prefix("custom_") {
+        return slot
+            .parse::<u16>()
+            .ok()
+            .filter(|slot| *slot <= 0x7fff)
+            .map(custom);
+    }
+
+    WELL_KNOWN.iter().find(|m| m.name == name).map(|m| m.id)
+}
+
+/// Looks up the description of a well-known metric.
+pub fn info(id: MetricId) -> Option<&'static MetricInfo> {
+    WELL_KNOWN.iter().find(|m| m.id == id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn by_name_finds_metric() {
+        assert_eq!(by_name("cpu_peak"), Some(CPU_PEAK));
+        assert_eq!(by_name("bogus"), None);
+    }
+
+    #[test]
+    fn by_name_finds_custom_slot() {
+        assert_eq!(by_name("custom_0"), Some(FIRST_CUSTOM));
+        assert_eq!(by_name("custom_3"), Some(MetricId(0x8003)));
+        assert_eq!(by_name("custom_40000"), None);
+        assert_eq!(by_name("custom_x"), None);
+    }
+
+    #[test]
+    fn new_uses_registry() {
+        let metric = Metric::new(CPU_LOAD, 0.5);
+
+        assert_eq!(metric.unit, Unit::Ratio);
+        assert!(metric.smoothed);
+    }
+
+    #[test]
+    fn new_unknown_metric() {
+        let metric = Metric::new(FIRST_CUSTOM, 42.0);
+
+        assert_eq!(metric.unit, Unit::None);
+        assert!(!metric.smoothed);
+    }
+
+    #[test]
+    fn split_commits_last_batch() {
+        let metrics: Vec<_, 16> = (0..MAX_BATCH as u16 + 1)
+            .map(|i| Metric::new(MetricId(i), 0.0))
+            .collect();
+
+        let batches: Vec<_, 4> = MetricBatch::split(&metrics).collect();
+
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].metrics.len(), MAX_BATCH);
+        assert!(!batches[0].commit);
+        assert_eq!(batches[1].metrics.len(), 1);
+        assert!(batches[1].commit);
+    }
+
+    #[test]
+    fn split_empty_still_commits() {
+        let batches: Vec<_, 4> = MetricBatch::split(&[]).collect();
+
+        assert_eq!(batches.len(), 1);
+        assert!(batches[0].metrics.is_empty());
+        assert!(batches[0].commit);
+    }
+
+    #[test]
+    fn well_known_ids_are_unique() {
+        for (i, a) in WELL_KNOWN.iter().enumerate() {
+            for b in &WELL_KNOWN[i + 1..] {
+                assert_ne!(a.id, b.id, "{} and {} share an ID", a.name, b.name);
+                assert_ne!(a.name, b.name);
+            }
+            assert!(a.id.0 < FIRST_CUSTOM.0);
+        }
+    }
+}
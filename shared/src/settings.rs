@@ -0,0 +1,276 @@
+use crate::theme::Theme;
+use serde::{Deserialize, Serialize};
+
+/// Size of a single settings record slot in flash.  Slots are written sequentially through
+/// a flash sector, so the sector only needs erasing once every slot has been used.
+pub const SLOT_BYTES: usize = 128;
+
+/// Version of the record payload.  Records with any other version are ignored when loading,
+/// causing the device to fall back to defaults.
+pub const RECORD_VERSION: u8 = 3;
+
+const RECORD_MAGIC: [u8; 2] = *b"GS";
+const HEADER_BYTES: usize = 8;
+const ERASED: u8 = 0xff;
+
+/// Device settings that persist across power cycles.
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    // Backlight brightness in percent, 0-100.
+    pub brightness: u8,
+    // Seconds without data before the screen is blanked, 0 to never blank.
+    pub blank_timeout_secs: u16,
+    // Shows a clock, once the host has set the time, until the screen is blanked.
+    pub idle_clock: bool,
+    // Page displayed at power on.
+    pub default_page: Page,
+    // Day/night color selection.
+    pub color_mode: ColorMode,
+    // Colors used to render pages.
+    pub theme: Theme,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            brightness: 100,
+            // Long enough for the idle clock to be useful.
+            blank_timeout_secs: 600,
+            idle_clock: true,
+            default_page: Page::Perf,
+            color_mode: ColorMode::Auto,
+            theme: Theme::DEFAULT,
+        }
+    }
+}
+
+/// Pages the device is capable of displaying.
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Page {
+    Perf,
+    // Processes using the most CPU and memory.
+    Top,
+    // CPU and memory of each host reported by the daemon.
+    Hosts,
+}
+
+/// Selects between the day and night color schemes.
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorMode {
+    // Follow the daytime flag sent by the host.
+    Auto,
+    Day,
+    Night,
+}
+
+impl ColorMode {
+    /// Resolves the display mode, given the daytime flag sent by the host.
+    pub fn daytime(self, host_daytime: bool) -> bool {
+        match self {
+            ColorMode::Auto => host_daytime,
+            ColorMode::Day => true,
+            ColorMode::Night => false,
+        }
+    }
+}
+
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Debug, PartialEq, Eq)]
+pub enum RecordError {
+    // Settings did not fit into a slot.
+    TooLarge,
+}
+
+/// Encodes settings into a flash slot.  Unused bytes are left in the erased state.
+///
+/// Slot layout: magic (2), version (1), payload length (1), CRC-32 of payload (4), payload.
+pub fn encode(settings: &Settings, slot: &mut [u8; SLOT_BYTES]) -> Result<(), RecordError> {
+    slot.fill(ERASED);
+
+    let (header, body) = slot.split_at_mut(HEADER_BYTES);
+    let len = postcard::to_slice(settings, body)
+        .map_err(|_| RecordError::TooLarge)?
+        .len();
+    let crc = crc32(&body[..len]);
+
+    header[0..2].copy_from_slice(&RECORD_MAGIC);
+    header[2] = RECORD_VERSION;
+    header[3] = len as u8;
+    header[4..8].copy_from_slice(&crc.to_le_bytes());
+
+    Ok(())
+}
+
+/// Decodes settings from a flash slot, returning None if the slot is erased, corrupt or
+/// holds an unsupported version.
+pub fn decode(slot: &[u8]) -> Option<Settings> {
+    if slot.len() < HEADER_BYTES || slot[0..2] != RECORD_MAGIC || slot[2] != RECORD_VERSION {
+        return None;
+    }
+
+    let len = slot[3] as usize;
+    let body = slot[HEADER_BYTES..].get(..len)?;
+    let crc = u32::from_le_bytes([slot[4], slot[5], slot[6], slot[7]]);
+    if crc32(body) != crc {
+        return None;
+    }
+
+    postcard::from_bytes(body).ok()
+}
+
+/// Returns true if the slot has not been written since the sector was erased.
+pub fn is_erased(slot: &[u8]) -> bool {
+    slot.iter().all(|b| *b == ERASED)
+}
+
+/// Result of scanning a flash sector for settings records.
+#[derive(Debug, PartialEq)]
+pub struct Scan {
+    // Most recently written valid settings.
+    pub latest: Option<Settings>,
+    // Index of the first erased slot, None if the sector is full.
+    pub next_free: Option<usize>,
+}
+
+/// Scans a sector of slots, finding the latest valid record and the next slot to write.
+pub fn scan(sector: &[u8]) -> Scan {
+    let mut latest = None;
+
+    for (i, slot) in sector.chunks_exact(SLOT_BYTES).enumerate() {
+        if is_erased(slot) {
+            return Scan {
+                latest,
+                next_free: Some(i),
+            };
+        }
+        if let Some(settings) = decode(slot) {
+            latest = Some(settings);
+        }
+    }
+
+    Scan {
+        latest,
+        next_free: None,
+    }
+}
+
+// CRC-32 (IEEE), computed bitwise to avoid a lookup table.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn custom() -> Settings {
+        Settings {
+            brightness: 40,
+            blank_timeout_secs: 60,
+            idle_clock: false,
+            default_page: Page::Perf,
+            color_mode: ColorMode::Night,
+            theme: Theme::SOLARIZED,
+        }
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn encode_decode_roundtrip() {
+        let mut slot = [0u8; SLOT_BYTES];
+        encode(&custom(), &mut slot).unwrap();
+
+        assert_eq!(decode(&slot), Some(custom()));
+    }
+
+    #[test]
+    fn decode_rejects_corruption() {
+        let mut slot = [0u8; SLOT_BYTES];
+        encode(&custom(), &mut slot).unwrap();
+        slot[HEADER_BYTES] ^= 0x01;
+
+        assert_eq!(decode(&slot), None);
+    }
+
+    #[test]
+    fn decode_rejects_other_version() {
+        let mut slot = [0u8; SLOT_BYTES];
+        encode(&custom(), &mut slot).unwrap();
+        slot[2] = RECORD_VERSION + 1;
+
+        assert_eq!(decode(&slot), None);
+    }
+
+    #[test]
+    fn scan_erased_sector() {
+        let sector = [ERASED; SLOT_BYTES * 4];
+
+        let actual = scan(&sector);
+
+        assert_eq!(
+            actual,
+            Scan {
+                latest: None,
+                next_free: Some(0)
+            }
+        );
+    }
+
+    #[test]
+    fn scan_finds_latest_record() {
+        let mut sector = [ERASED; SLOT_BYTES * 4];
+        let mut slot = [0u8; SLOT_BYTES];
+        encode(&Settings::default(), &mut slot).unwrap();
+        sector[..SLOT_BYTES].copy_from_slice(&slot);
+        encode(&custom(), &mut slot).unwrap();
+        sector[SLOT_BYTES..SLOT_BYTES * 2].copy_from_slice(&slot);
+
+        let actual = scan(&sector);
+
+        assert_eq!(
+            actual,
+            Scan {
+                latest: Some(custom()),
+                next_free: Some(2)
+            }
+        );
+    }
+
+    #[test]
+    fn scan_skips_corrupt_record() {
+        let mut sector = [ERASED; SLOT_BYTES * 2];
+        let mut slot = [0u8; SLOT_BYTES];
+        encode(&custom(), &mut slot).unwrap();
+        sector[..SLOT_BYTES].copy_from_slice(&slot);
+        slot[HEADER_BYTES] ^= 0x01;
+        sector[SLOT_BYTES..].copy_from_slice(&slot);
+
+        let actual = scan(&sector);
+
+        assert_eq!(
+            actual,
+            Scan {
+                latest: Some(custom()),
+                next_free: None
+            }
+        );
+    }
+}
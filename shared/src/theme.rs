@@ -0,0 +1,248 @@
+use serde::{Deserialize, Serialize};
+
+/// RGB565 color, as used by the display.
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Color(pub u16);
+
+impl Color {
+    pub const BLACK: Color = Color::rgb565(0, 0, 0);
+    pub const WHITE: Color = Color::rgb565(31, 63, 31);
+
+    /// Creates a color from 5-bit red, 6-bit green and 5-bit blue components.
+    pub const fn rgb565(r: u8, g: u8, b: u8) -> Color {
+        Color(((r as u16 & 0x1f) << 11) | ((g as u16 & 0x3f) << 5) | (b as u16 & 0x1f))
+    }
+
+    /// Creates a color from 8-bit components, discarding the low bits.
+    pub const fn rgb888(r: u8, g: u8, b: u8) -> Color {
+        Color::rgb565(r >> 3, g >> 2, b >> 3)
+    }
+}
+
+/// Colors used to render a single display mode.
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Palette {
+    pub background: Color,
+    pub text: Color,
+    // Single bars, such as memory.
+    pub bar: Color,
+    // Left (lower) portion of double bars, such as all cores CPU load.
+    pub bar_avg: Color,
+    // Right (upper) portion of double bars, such as peak core CPU load.
+    pub bar_peak: Color,
+}
+
+/// Bar color override once a value reaches a level.
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Threshold {
+    // Level in percent, 0-100.
+    pub level: u8,
+    pub color: Color,
+}
+
+impl Threshold {
+    pub const DEFAULT_WARN: Threshold = Threshold {
+        level: 75,
+        color: Color::rgb888(0xe6, 0x9f, 0x00),
+    };
+    pub const DEFAULT_CRIT: Threshold = Threshold {
+        level: 90,
+        color: Color::rgb888(0xd5, 0x1e, 0x00),
+    };
+}
+
+/// Day and night palettes, plus the thresholds where bars change color.
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Theme {
+    pub day: Palette,
+    pub night: Palette,
+    pub warn: Option<Threshold>,
+    pub crit: Option<Threshold>,
+}
+
+impl Theme {
+    /// Original hw-gauge colors, without thresholds.
+    pub const DEFAULT: Theme = Theme {
+        day: Palette {
+            background: Color::rgb565(24, 48, 24),
+            text: Color::BLACK,
+            bar: Color::rgb565(7, 43, 11),
+            bar_avg: Color::rgb565(10, 10, 22),
+            bar_peak: Color::rgb565(15, 30, 28),
+        },
+        night: Palette {
+            background: Color::BLACK,
+            text: Color::rgb565(24, 48, 24),
+            bar: Color::rgb565(0, 30, 3),
+            bar_avg: Color::rgb565(10, 10, 22),
+            bar_peak: Color::rgb565(3, 3, 8),
+        },
+        warn: None,
+        crit: None,
+    };
+
+    /// Black and white, with grey peak bars.
+    pub const HIGH_CONTRAST: Theme = Theme {
+        day: Palette {
+            background: Color::WHITE,
+            text: Color::BLACK,
+            bar: Color::BLACK,
+            bar_avg: Color::BLACK,
+            bar_peak: Color::rgb565(14, 28, 14),
+        },
+        night: Palette {
+            background: Color::BLACK,
+            text: Color::WHITE,
+            bar: Color::WHITE,
+            bar_avg: Color::WHITE,
+            bar_peak: Color::rgb565(14, 28, 14),
+        },
+        warn: Some(Threshold {
+            level: 75,
+            color: Color::rgb565(31, 48, 0),
+        }),
+        crit: Some(Threshold {
+            level: 90,
+            color: Color::rgb565(31, 0, 0),
+        }),
+    };
+
+    /// Okabe-Ito palette, distinguishable with common forms of color blindness.
+    pub const COLORBLIND: Theme = Theme {
+        day: Palette {
+            background: Color::rgb888(0xe0, 0xe0, 0xe0),
+            text: Color::BLACK,
+            bar: Color::rgb888(0x00, 0x9e, 0x73),
+            bar_avg: Color::rgb888(0x00, 0x72, 0xb2),
+            bar_peak: Color::rgb888(0x56, 0xb4, 0xe9),
+        },
+        night: Palette {
+            background: Color::BLACK,
+            text: Color::rgb888(0xe0, 0xe0, 0xe0),
+            bar: Color::rgb888(0x00, 0x9e, 0x73),
+            bar_avg: Color::rgb888(0x00, 0x72, 0xb2),
+            bar_peak: Color::rgb888(0x00, 0x39, 0x59),
+        },
+        warn: Some(Threshold::DEFAULT_WARN),
+        crit: Some(Threshold::DEFAULT_CRIT),
+    };
+
+    /// Ethan Schoonover's Solarized, light by day and dark by night.
+    pub const SOLARIZED: Theme = Theme {
+        day: Palette {
+            background: Color::rgb888(0xfd, 0xf6, 0xe3),
+            text: Color::rgb888(0x65, 0x7b, 0x83),
+            bar: Color::rgb888(0x85, 0x99, 0x00),
+            bar_avg: Color::rgb888(0x26, 0x8b, 0xd2),
+            bar_peak: Color::rgb888(0x2a, 0xa1, 0x98),
+        },
+        night: Palette {
+            background: Color::rgb888(0x00, 0x2b, 0x36),
+            text: Color::rgb888(0x83, 0x94, 0x96),
+            bar: Color::rgb888(0x85, 0x99, 0x00),
+            bar_avg: Color::rgb888(0x26, 0x8b, 0xd2),
+            bar_peak: Color::rgb888(0x2a, 0xa1, 0x98),
+        },
+        warn: Some(Threshold {
+            level: 75,
+            color: Color::rgb888(0xb5, 0x89, 0x00),
+        }),
+        crit: Some(Threshold {
+            level: 90,
+            color: Color::rgb888(0xdc, 0x32, 0x2f),
+        }),
+    };
+
+    /// Returns the palette for the display mode.
+    pub fn palette(&self, daytime: bool) -> &Palette {
+        if daytime {
+            &self.day
+        } else {
+            &self.night
+        }
+    }
+
+    /// Returns the color of a bar displaying `value` (0-1.0), replacing the `base` color
+    /// once the value reaches a threshold.
+    pub fn bar_color(&self, base: Color, value: f32) -> Color {
+        let percent = value * 100.0;
+        let reached = |t: &Option<Threshold>| match t {
+            Some(t) if percent >= t.level as f32 => Some(t.color),
+            _ => None,
+        };
+
+        reached(&self.crit).or(reached(&self.warn)).unwrap_or(base)
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::DEFAULT
+    }
+}
+
+/// Names of the built-in themes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThemeName {
+    Default,
+    HighContrast,
+    Colorblind,
+    Solarized,
+}
+
+impl ThemeName {
+    pub fn theme(self) -> Theme {
+        match self {
+            ThemeName::Default => Theme::DEFAULT,
+            ThemeName::HighContrast => Theme::HIGH_CONTRAST,
+            ThemeName::Colorblind => Theme::COLORBLIND,
+            ThemeName::Solarized => Theme::SOLARIZED,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgb888_discards_low_bits() {
+        assert_eq!(Color::rgb888(0xff, 0xff, 0xff), Color::WHITE);
+        assert_eq!(Color::rgb888(0x08, 0x04, 0x08), Color::rgb565(1, 1, 1));
+    }
+
+    #[test]
+    fn bar_color_below_thresholds() {
+        let base = Color::rgb565(1, 2, 3);
+
+        let actual = Theme::COLORBLIND.bar_color(base, 0.5);
+
+        assert_eq!(actual, base);
+    }
+
+    #[test]
+    fn bar_color_at_warn() {
+        let actual = Theme::COLORBLIND.bar_color(Color::BLACK, 0.75);
+
+        assert_eq!(actual, Threshold::DEFAULT_WARN.color);
+    }
+
+    #[test]
+    fn bar_color_above_crit() {
+        let actual = Theme::COLORBLIND.bar_color(Color::BLACK, 0.95);
+
+        assert_eq!(actual, Threshold::DEFAULT_CRIT.color);
+    }
+
+    #[test]
+    fn bar_color_without_thresholds() {
+        let actual = Theme::DEFAULT.bar_color(Color::BLACK, 1.0);
+
+        assert_eq!(actual, Color::BLACK);
+    }
+}
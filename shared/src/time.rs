@@ -0,0 +1,151 @@
+use serde::{Deserialize, Serialize};
+
+const SECS_PER_DAY: i64 = 86_400;
+const DAYS_PER_ERA: i64 = 146_097;
+
+/// Wall clock time on the host, as seconds since 1970-01-01 00:00:00 with the host's UTC
+/// offset already applied, so the device needs no knowledge of time zones.
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct LocalTime(pub i64);
+
+/// Calendar date and time of day.
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: i32,
+    // 1-12.
+    pub month: u8,
+    // 1-31.
+    pub day: u8,
+    pub weekday: Weekday,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+#[cfg_attr(feature = "defmt-log", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Weekday {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+impl LocalTime {
+    /// Returns the time `secs` later.
+    pub fn add_secs(self, secs: u64) -> Self {
+        LocalTime(self.0.saturating_add(secs as i64))
+    }
+
+    /// Splits the time into a calendar date and time of day.
+    pub fn date_time(self) -> DateTime {
+        let days = self.0.div_euclid(SECS_PER_DAY);
+        let secs = self.0.rem_euclid(SECS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+
+        DateTime {
+            year,
+            month,
+            day,
+            // 1970-01-01 was a Thursday.
+            weekday: Weekday::from_monday_index((days + 3).rem_euclid(7) as u8),
+            hour: (secs / 3600) as u8,
+            minute: (secs / 60 % 60) as u8,
+            second: (secs % 60) as u8,
+        }
+    }
+}
+
+impl Weekday {
+    // Converts days since Monday, 0-6.
+    fn from_monday_index(index: u8) -> Self {
+        match index {
+            0 => Weekday::Mon,
+            1 => Weekday::Tue,
+            2 => Weekday::Wed,
+            3 => Weekday::Thu,
+            4 => Weekday::Fri,
+            5 => Weekday::Sat,
+            _ => Weekday::Sun,
+        }
+    }
+
+    /// Returns the three letter English abbreviation.
+    pub fn abbrev(self) -> &'static str {
+        match self {
+            Weekday::Mon => "Mon",
+            Weekday::Tue => "Tue",
+            Weekday::Wed => "Wed",
+            Weekday::Thu => "Thu",
+            Weekday::Fri => "Fri",
+            Weekday::Sat => "Sat",
+            Weekday::Sun => "Sun",
+        }
+    }
+}
+
+/// Converts days since 1970-01-01 into a (year, month, day) of the proleptic Gregorian
+/// calendar, using Howard Hinnant's algorithm of 400 year eras starting in March.
+fn civil_from_days(days: i64) -> (i32, u8, u8) {
+    let days = days + 719_468;
+    let era = days.div_euclid(DAYS_PER_ERA);
+    let day_of_era = days.rem_euclid(DAYS_PER_ERA);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u8;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    } as u8;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year as i32, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date_time(secs: i64) -> (i32, u8, u8, Weekday, u8, u8, u8) {
+        let dt = LocalTime(secs).date_time();
+        (
+            dt.year, dt.month, dt.day, dt.weekday, dt.hour, dt.minute, dt.second,
+        )
+    }
+
+    #[test]
+    fn epoch() {
+        assert_eq!(date_time(0), (1970, 1, 1, Weekday::Thu, 0, 0, 0));
+        assert_eq!(date_time(-1), (1969, 12, 31, Weekday::Wed, 23, 59, 59));
+    }
+
+    #[test]
+    fn leap_days() {
+        // 2024-02-29 12:34:56.
+        assert_eq!(
+            date_time(1_709_210_096),
+            (2024, 2, 29, Weekday::Thu, 12, 34, 56)
+        );
+        // 2000-03-01, after the leap day of a 400 year century.
+        assert_eq!(date_time(951_868_800), (2000, 3, 1, Weekday::Wed, 0, 0, 0));
+        // 2100-03-01, as 2100 is not a leap year.
+        assert_eq!(
+            date_time(4_107_542_400),
+            (2100, 3, 1, Weekday::Mon, 0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn add_secs() {
+        assert_eq!(LocalTime(59).add_secs(1).date_time().minute, 1);
+        assert_eq!(LocalTime(i64::MAX).add_secs(1), LocalTime(i64::MAX));
+    }
+}